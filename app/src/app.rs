@@ -108,6 +108,18 @@ impl App {
         debug!("{:?} at {:?} on page {}", tag, p, n);
         Some((n as usize, p))
     }
+    // direction of the text at the cursor
+    fn direction(&self, tag: Tag, pos: ItemPos) -> Direction {
+        if let (ItemPos::Within(_), Some(Item::Word(key))) = (pos, self.document.get_item(tag)) {
+            if let Some(direction) = self.document.get_word(key).direction() {
+                return direction;
+            }
+        }
+        let weave = self.document.get_weave(tag.seq());
+        self.design.get_type_or_default(weave.typ()).direction
+            .or(self.design.default().direction)
+            .unwrap_or_default()
+    }
     fn set_cursor_to(&mut self, ctx: &mut Context, tag: Tag, pos: ItemPos) {
         debug!("set_cursor_to({:?}, {:?}", tag, pos);
         let weave = self.document.get_weave(tag.seq());
@@ -129,11 +141,16 @@ impl App {
                         if let Some((page, rect)) = self.get_position(tag) {
                             let type_key = weave.typ();
                             let typ = self.design.get_type_or_default(type_key);
+                            let space = Vector2F::new(0.5 * typ.word_space.length.value, 0.0);
+                            let page_pos = match self.direction(tag, pos) {
+                                Direction::LeftToRight => rect.lower_right() + space,
+                                Direction::RightToLeft => rect.lower_left() - space,
+                            };
                             self.cursor = Some(Cursor {
                                 tag,
                                 page,
                                 pos,
-                                page_pos: rect.lower_right() + Vector2F::new(0.5 * typ.word_space.length.value, 0.0),
+                                page_pos,
                             });
                         } else {
                             self.cursor = None;
@@ -205,6 +222,18 @@ impl App {
                                 Some((tag, ItemPos::Within(n + c.len_utf8())))
                             }
 
+                            // soft hyphen, only within a word and not next to another one
+                            TextOp::InsertHyphenMark if n > 0 && n < text.len() => {
+                                if text[.. n].ends_with(HYPHEN_MARK) || text[n ..].starts_with(HYPHEN_MARK) {
+                                    return None;
+                                }
+                                let new_text = format!("{}{}{}", &text[.. n], HYPHEN_MARK, &text[n ..]);
+                                let new_item = Item::Word(self.document.create_word(&new_text));
+                                let tag = self.document.replace(cursor.tag, new_item);
+
+                                Some((tag, ItemPos::Within(n + HYPHEN_MARK.len_utf8())))
+                            }
+
                             // split, but only when within a word
                             TextOp::Split if n > 0 && n < text.len() => {
                                 let left_text = text[.. n].to_owned();
@@ -297,18 +326,22 @@ impl App {
     fn cursor_op(&mut self, op: CursorOp) -> Option<(Tag, ItemPos)> {
         let cursor = self.cursor?;
         debug!("cursor: {:?}", cursor);
+
+        // the keys move visually. in right-to-left text that is backwards.
+        let op = match self.direction(cursor.tag, cursor.pos) {
+            Direction::LeftToRight => op,
+            Direction::RightToLeft => op.mirror()
+        };
         match (cursor.pos, self.document.get_item(cursor.tag)) {
             (ItemPos::Within(n), Some(Item::Word(word_key))) => {
                 let text = &self.document.get_word(word_key).text;
                 match op {
                     CursorOp::GraphemeRight if n < text.len() => {
-                        // BIDI
                         let pos = text[n ..].grapheme_indices(true).nth(1)
                             .map(|(m, _)| n + m).unwrap_or(text.len());
                         Some((cursor.tag, ItemPos::Within(pos)))
                     }
                     CursorOp::GraphemeLeft if n > 0 => {
-                        // BIDI
                         let pos = text[.. n].grapheme_indices(true).rev().next()
                             .map(|(n, _)| n).unwrap_or(0);
                         Some((cursor.tag, ItemPos::Within(pos)))
@@ -372,6 +405,7 @@ impl App {
 
 enum TextOp {
     Insert(char),
+    InsertHyphenMark,
     Split,
     DeletePrevGrapheme,
     DeleteNextGrapheme,
@@ -385,6 +419,16 @@ enum CursorOp {
     ItemRight,
     ItemLeft,
 }
+impl CursorOp {
+    fn mirror(self) -> CursorOp {
+        match self {
+            CursorOp::GraphemeLeft => CursorOp::GraphemeRight,
+            CursorOp::GraphemeRight => CursorOp::GraphemeLeft,
+            CursorOp::ItemRight => CursorOp::ItemLeft,
+            CursorOp::ItemLeft => CursorOp::ItemRight,
+        }
+    }
+}
 
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -505,14 +549,12 @@ impl Interactive for App {
     fn mouse_input(&mut self, ctx: &mut Context, page: usize, pos: Vector2F, state: ElementState) {
         let old_cursor = self.cursor.take();
 
-        if let Some((tag, word_pos)) = self.pages[page].as_ref().unwrap().find(pos) {
-            let offset = pos.x() - word_pos.x();
-
-            if let Some((word_offset, n)) = self.cache.find(self.document.storage(), &self.design, offset, tag) {
+        if let Some((tag, _)) = self.pages[page].as_ref().unwrap().find(pos) {
+            if let Some((page_pos, n)) = self.cache.find(self.document.storage(), &self.design, tag, page, pos) {
                 self.cursor = Some(Cursor {
                     tag,
                     page,
-                    page_pos: word_offset + word_pos,
+                    page_pos,
                     pos: ItemPos::Within(n)
                 });
             } else {
@@ -557,6 +599,7 @@ impl Interactive for App {
         let s = match c {
             // backspace
             ' ' => self.text_op(TextOp::Split),
+            '-' | HYPHEN_MARK => self.text_op(TextOp::InsertHyphenMark),
             c if c.is_letter() => self.text_op(TextOp::Insert(c)),
             _ => None
         };
@@ -2,14 +2,13 @@ use grafeia_convert::build;
 use grafeia_core::*;
 use std::borrow::Cow;
 use std::fs::{self, File};
-use std::collections::HashMap;
 
-use pulldown_cmark::{Parser, Event, Tag, CodeBlockKind};
+use pulldown_cmark::{Parser, Options, Event, Tag, CodeBlockKind};
 use grafeia_core::*;
 use grafeia_core::object::tex::TeX;
-use grafeia_convert::build::DICT_EN_GB;
 use std::mem::replace;
 use std::io::BufWriter;
+use std::collections::HashMap;
 
 macro_rules! font {
     ($name:tt) => (
@@ -29,6 +28,52 @@ fn skip(events: &mut Parser) {
     }
 }
 
+// the anchor of a heading, like "#hyperlinks-and-anchors"
+fn slug(text: &str) -> String {
+    text.trim().chars()
+        .filter(|&c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+        .flat_map(char::to_lowercase)
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+// the (name, value) attributes of an opening tag like `<span lang="de" class=x>`
+fn tag_attributes<'a>(html: &'a str, tag: &str) -> Option<Vec<(&'a str, &'a str)>> {
+    let inner = html.trim().strip_prefix('<')?.strip_suffix('>')?;
+    let rest = inner.strip_prefix(tag)?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut attributes = vec![];
+    let mut rest = rest.trim_start();
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let (name, tail) = rest.split_at(end);
+        let (value, tail) = match tail.trim_start().strip_prefix('=') {
+            Some(tail) => {
+                let tail = tail.trim_start();
+                match tail.chars().next() {
+                    Some(quote) if quote == '"' || quote == '\'' => {
+                        let end = tail[1 ..].find(quote)? + 1;
+                        (&tail[1 .. end], &tail[end + 1 ..])
+                    }
+                    _ => tail.split_at(tail.find(char::is_whitespace).unwrap_or(tail.len()))
+                }
+            }
+            None => ("", tail)
+        };
+        attributes.push((name, value));
+        rest = tail.trim_start();
+    }
+    Some(attributes)
+}
+
+// Some for an opening span, with the language of `<span lang="de">`
+fn span_language(html: &str) -> Option<Option<&str>> {
+    let attributes = tag_attributes(html, "span")?;
+    Some(attributes.into_iter().find(|(name, _)| name.eq_ignore_ascii_case("lang")).map(|(_, value)| value))
+}
+
 fn main() {
     let mut args = std::env::args().skip(1);
     let output = File::create(args.next().expect("no output file given")).expect("can't create output file");
@@ -54,6 +99,14 @@ fn main() {
     let block_code = add_type("block-code", "Code in block form");
     let list = add_type("list", "Unnumbered list of items");
     let list_item = add_type("list-item", "A list items");
+    let ordered_list = add_type("ordered-list", "Numbered list of items");
+    let ordered_list_item = add_type("ordered-list-item", "A numbered list item");
+    let equation = add_type("equation", "Numbered equation");
+    let table = add_type("table", "Table");
+    let table_row = add_type("table-row", "Row of a table");
+    let table_cell = add_type("table-cell", "Cell of a table");
+    let link = add_type("link", "Link");
+    let span = add_type("span", "Text in another language");
 
     let coramont_regular = document.add_font(font!("Cormorant-Regular.ttf"));
     let coramont_bold = document.add_font(font!("Cormorant-Bold.ttf"));
@@ -79,7 +132,8 @@ fn main() {
         overflow_right: 0.0
     });
 
-    let dictionary = document.load_dict(DICT_EN_GB);
+    let languages = build::languages();
+    let dictionary = document.load_language(&languages, "en-gb").expect("no dictionary for en-gb");
 
     let default = TypeDesign {
         display:        Display::Paragraph(
@@ -91,19 +145,34 @@ fn main() {
         ),
         font:           Font {
             font_face: coramont_regular,
-            size: Length::mm(4.0)
+            size: Length::mm(4.0),
+            features: Features::new(),
+            variations: Variations::default(),
+            synthetic: Synthetic::default()
         },
         word_space: FlexMeasure {
             shrink:  Length::mm(1.0),
             length:  Length::mm(1.2),
             stretch: Length::mm(2.0)
         },
+        letter_spacing: FlexMeasure::zero(),
         line_height: Length::mm(5.0),
         indent: Length::zero(),
-        dictionary,
+        language: Some(dictionary),
+        direction: None,
         hyphen: Some(hyphen),
+        hyphenation: Hyphenation::default(),
+        initial: None,
+        color: Color::black(),
     };
     let mut design = Design::new("default design".into(), default.clone());
+
+    // greek and math symbols
+    design.set_fallback(vec![didot, latinmodern_math]);
+
+    // also the bookmarks of the pdf
+    design.set_contents_types(headings.clone());
+
     for (&typ, &size) in headings.iter().zip([10.0, 8.0, 6.0, 5.0, 5.0, 5.0f32].iter()) {
         design.set_type(typ,
             TypeDesign {
@@ -115,17 +184,25 @@ fn main() {
                 ),
                 font:           Font {
                     font_face: didot,
-                    size: Length::mm(size)
+                    size: Length::mm(size),
+                    features: Features::new(),
+                    variations: Variations::default(),
+                    synthetic: Synthetic::default()
                 },
                 word_space: FlexMeasure {
                     shrink:  Length::mm(0.2 * size),
                     length:  Length::mm(0.3 * size),
                     stretch: Length::mm(0.5 * size)
                 },
+                letter_spacing: FlexMeasure::zero(),
                 line_height: Length::mm(1.25 * size),
                 indent: Length::zero(),
-                dictionary,
+                language: Some(dictionary),
+                direction: None,
                 hyphen: None,
+                hyphenation: Hyphenation::default(),
+                initial: None,
+                color: Color::black(),
             }
         );
     }
@@ -156,39 +233,68 @@ fn main() {
             .. default
         }
     );
+    for &typ in [list, ordered_list].iter() {
+        design.set_type(
+            typ,
+            TypeDesign {
+                display:        Display::Block(
+                    VerticalPadding {
+                        above: Length::zero(),
+                        below: Length::mm(4.0)
+                    }
+                ),
+                indent:         Length::mm(5.),
+                .. default
+            }
+        );
+    }
+    for &typ in [list_item, ordered_list_item].iter() {
+        design.set_type(
+            typ,
+            TypeDesign {
+                display:        Display::Paragraph(
+                    Length::mm(0.0),
+                    VerticalPadding {
+                        above: Length::zero(),
+                        below: Length::zero()
+                    }
+                ),
+                .. default
+            }
+        );
+    }
     design.set_type(
-        list,
+        equation,
         TypeDesign {
             display:        Display::Block(
                 VerticalPadding {
-                    above: Length::zero(),
+                    above: Length::mm(2.0),
                     below: Length::mm(4.0)
                 }
             ),
-            indent:         Length::mm(5.),
-            .. default
-        }
-    );
-    design.set_type(
-        list_item,
-        TypeDesign {
-            display:        Display::Paragraph(
-                Length::mm(0.0),
-                VerticalPadding {
-                    above: Length::zero(),
-                    below: Length::zero()
-                }
-            ),
             .. default
         }
     );
+    design.set_counter("list-item", Counter {
+        reset: vec![ordered_list],
+        suffix: ".".into(),
+        .. Counter::new(vec![ordered_list_item], NumberStyle::Arabic)
+    });
+    design.set_counter("equation", Counter {
+        prefix: "(".into(),
+        suffix: ")".into(),
+        .. Counter::new(vec![equation], NumberStyle::Arabic)
+    });
     design.set_type(
         emphasis,
         TypeDesign {
             display:        Display::Inline,
             font:           Font {
                 font_face: coramont_italic,
-                size: Length::mm(4.0)
+                size: Length::mm(4.0),
+                features: Features::new(),
+                variations: Variations::default(),
+                synthetic: Synthetic::default()
             },
             .. default
         }
@@ -199,34 +305,87 @@ fn main() {
             display:        Display::Inline,
             font:           Font {
                 font_face: coramont_bold,
-                size: Length::mm(4.0)
+                size: Length::mm(4.0),
+                features: Features::new(),
+                variations: Variations::default(),
+                synthetic: Synthetic::default()
             },
             .. default
         }
     );
+    for &typ in [link, span].iter() {
+        design.set_type(
+            typ,
+            TypeDesign {
+                display:        Display::Inline,
+                .. default
+            }
+        );
+    }
     design.set_type(
         inline_code,
         TypeDesign {
             display:        Display::Inline,
             font:           Font {
                 font_face:  cutive_mono,
-                size: Length::mm(3.8)
+                size: Length::mm(3.8),
+                features: Features::new().disable("liga"),
+                variations: Variations::default(),
+                synthetic: Synthetic::default()
             },
             hyphen: None,
             .. default
         }
     );
 
+    design.set_type(
+        table,
+        TypeDesign {
+            display:        Display::Table(Table {
+                padding: VerticalPadding {
+                    above: Length::zero(),
+                    below: Length::mm(4.0)
+                },
+                column_gap: Length::mm(4.0),
+                row_gap: Length::mm(1.0),
+                header_rows: 1
+            }),
+            .. default
+        }
+    );
+    design.set_type(
+        table_cell,
+        TypeDesign {
+            display:        Display::Block(
+                VerticalPadding {
+                    above: Length::zero(),
+                    below: Length::zero()
+                }
+            ),
+            .. default
+        }
+    );
+
     let mut stack = vec![];
     let mut items = vec![];
     let mut current_key = document_typ;
 
-    let mut list_numbers = HashMap::new();
+    // destinations of the open links, and (sequence, destination) of the finished ones.
+    // links to headings are resolved once all of them are known.
+    let mut destinations = vec![];
+    let mut links = vec![];
+    let mut anchors = HashMap::new();
+    let mut heading_text = None;
+
+    // the language tags of the open spans, None for spans without one
+    let mut span_languages: Vec<Option<String>> = vec![];
+
+    // the first heading
+    let mut title = None;
 
     for path in args {
         let data = fs::read(path).unwrap();
-        let mut list_nr = None;
-        let mut events = Parser::new(std::str::from_utf8(&data).unwrap()).into_iter();
+        let mut events = Parser::new_ext(std::str::from_utf8(&data).unwrap(), Options::ENABLE_TABLES).into_iter();
         while let Some(event) = events.next() {
             println!("{:?}", event);
             match event {
@@ -234,30 +393,18 @@ fn main() {
                     let mut inner_items = vec![];
                     let key = match tag {
                         Tag::Paragraph => paragraph,
-                        Tag::Heading(level) => headings.get(level as usize).expect("invalid heading level").clone(),
+                        Tag::Heading(level) => {
+                            heading_text = Some(String::new());
+                            headings.get(level as usize).expect("invalid heading level").clone()
+                        }
                         Tag::BlockQuote => block_quote,
                         Tag::Emphasis => emphasis,
-                        Tag::List(first_nr) => {
-                            list_nr = first_nr;
-                            list
-                        }
+                        // numbered by the "list-item" counter
+                        Tag::List(Some(_)) => ordered_list,
+                        Tag::List(None) => list,
+                        Tag::Item if current_key == ordered_list => ordered_list_item,
                         Tag::Item => {
-                            let item = match list_nr {
-                                None => bullet,
-                                Some(n) => {
-                                    list_nr = Some(n + 1);
-                                    *list_numbers.entry(n).or_insert_with(|| {
-                                        document.add_symbol(Symbol {
-                                            text: format!("{}.", n),
-                                            leading: true,
-                                            trailing: false,
-                                            overflow_left: 1.0,
-                                            overflow_right: 0.0
-                                        })
-                                    })
-                                }
-                            };
-                            inner_items.push(Item::Symbol(item));
+                            inner_items.push(Item::Symbol(bullet));
                             list_item
                         }
                         Tag::CodeBlock(lang) => {
@@ -273,7 +420,8 @@ fn main() {
                                 CodeBlockKind::Fenced(s) => match s.as_ref() {
                                     "tex" | "TeX" | "latex" | "LaTeX" => {
                                         let key = document.create_object(Object::TeX(TeX::display(code, latinmodern_math)));
-                                        items.push(Item::Object(key));
+                                        let id = document.creat_seq_with_items(equation, vec![Item::Object(key)]);
+                                        items.push(Item::Sequence(id));
                                     },
                                     _ => {}
                                 }
@@ -282,6 +430,15 @@ fn main() {
                             continue;
                         }
                         Tag::Strong => strong,
+                        Tag::Link(_, destination, _) => {
+                            destinations.push(destination.to_string());
+                            link
+                        }
+                        Tag::Table(_) => table,
+
+                        // the head is the first row
+                        Tag::TableHead | Tag::TableRow => table_row,
+                        Tag::TableCell => table_cell,
                         _ => {
                             skip(&mut events);
                             continue;
@@ -295,9 +452,23 @@ fn main() {
                     let inner_items = replace(&mut items, parent_items);
                     let id = document.creat_seq_with_items(current_key, inner_items);
                     items.push(Item::Sequence(id));
+                    if current_key == link {
+                        links.push((id, destinations.pop().unwrap()));
+                    }
+                    if headings.contains(&current_key) {
+                        if let Some(text) = heading_text.take() {
+                            anchors.insert(slug(&text), id);
+                            if title.is_none() {
+                                title = Some(text);
+                            }
+                        }
+                    }
                     current_key = parent_key;
                 }
                 Event::Text(text) => {
+                    if let Some(ref mut heading) = heading_text {
+                        heading.push_str(&text);
+                    }
                     items.extend(document.create_text(text.as_ref()));
                 }
                 Event::Code(text) => {
@@ -306,12 +477,53 @@ fn main() {
                     let id = document.creat_seq_with_items(inline_code, words);
                     items.push(Item::Sequence(id));
                 }
+                // <span lang="de">…</span> sets the language of the text within
+                Event::Html(html) => {
+                    if let Some(tag) = span_language(&html) {
+                        // only spans with a language are sequences
+                        if tag.is_some() {
+                            stack.push((current_key, replace(&mut items, vec![])));
+                            current_key = span;
+                        }
+                        span_languages.push(tag.map(str::to_owned));
+                    } else if html.trim() == "</span>" {
+                        if let Some(Some(tag)) = span_languages.pop() {
+                            let (parent_key, parent_items) = stack.pop().unwrap();
+                            let inner_items = replace(&mut items, parent_items);
+                            let id = document.creat_seq_with_items(span, inner_items);
+                            items.push(Item::Sequence(id));
+
+                            match document.load_language(&languages, &tag) {
+                                Ok(dict) => document.set_language(id, dict),
+                                Err(e) => eprintln!("no dictionary for {}: {}", tag, e)
+                            }
+                            current_key = parent_key;
+                        }
+                    }
+                }
                 _ => {}
             }
         }
         assert_eq!(stack.len(), 0);
     }
 
+    for (id, destination) in links {
+        let target = if destination.starts_with('#') {
+            match anchors.get(&destination[1 ..]) {
+                Some(&heading) => Link::Internal(grafeia_core::Tag::Start(heading)),
+                None => {
+                    eprintln!("no heading for the link to {}", destination);
+                    continue;
+                }
+            }
+        } else {
+            Link::Uri(destination)
+        };
+        document.set_link(id, target);
+    }
+
+    document.set_metadata(Metadata { title, author: None });
+
     let root = document.creat_seq_with_items(document_typ, items);
     document.set_root(root);
 
@@ -326,3 +538,18 @@ fn main() {
     };
     state.store(BufWriter::new(output)).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_languages() {
+        assert_eq!(span_language(r#"<span lang="de">"#), Some(Some("de")));
+        assert_eq!(span_language("<span class=x LANG='el' >"), Some(Some("el")));
+        assert_eq!(span_language(r#"<span xml:lang="de" data-lang="fr">"#), Some(None));
+        assert_eq!(span_language("<span>"), Some(None));
+        assert_eq!(span_language(r#"<spank lang="de">"#), None);
+        assert_eq!(span_language("</span>"), None);
+    }
+}
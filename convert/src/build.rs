@@ -3,7 +3,10 @@ use grafeia_core::builder::*;
 use grafeia_core::object::{tex::*, svg::*};
 use std::borrow::Cow;
 
-pub static DICT_EN_GB: &'static [u8] = &*include_bytes!("../../data/dictionaries/en-gb.standard.bincode");
+/// the hyphenation dictionaries in data/dictionaries
+pub fn languages() -> LanguageRegistry {
+    LanguageRegistry::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/dictionaries"))
+}
 
 pub fn symbols(document: &mut Document) {
     let trailing = [",", ".", ":", "!", "?", ";", ];
@@ -55,14 +58,29 @@ pub fn build() -> State<'static> {
         &include_bytes!("../../data/latinmodern-math.otf")[..]
     );
 
+    document.load_bibtex(include_str!("../../data/references.bib")).unwrap();
+    document.set_metadata(Metadata {
+        title: Some("Test".into()),
+        author: Some("grafeia".into())
+    });
+
     let mut document = ContentBuilder::with_document(document)
+        .contents()
         .chapter().word("Test").finish()
         .paragraph()
-            .text("The distilled spirit of Garamond")
+            .text("The distilled spirit of")
+            .index_mark(Term::new("Garamond"))
+            .text("Garamond")
             .finish()
         .paragraph()
             .text("The ffine fish")
             .finish()
+        .paragraph()
+            .text("Lines are broken as described by")
+            .cite(Citation::new("knuth1981"))
+            .text("and in")
+            .cite(Citation { locator: Some("p. 94".into()), .. Citation::new("knuth1984") })
+            .finish()
         .paragraph()
             .text("Written in")
             .object(Object::Svg(SvgObject::new(Scale::FitTextHeight, include_bytes!("../../data/rust_logo.svg")[..].into())))
@@ -70,6 +88,7 @@ pub fn build() -> State<'static> {
             .object(Object::Svg(SvgObject::new(Scale::FitTextHeight, include_bytes!("../../data/pathfinder_logo.svg")[..].into())))
             .finish()
         .paragraph()
+            .index_mark(Term { sub: Some("ReX".into()), see_also: Some("TeX".into()), .. Term::new("typesetting") })
             .text("Using ReX to render")
             .object(Object::TeX(TeX::text(r#"T_e X"#, math_font)))
             .finish()
@@ -81,7 +100,9 @@ pub fn build() -> State<'static> {
             .text("And more text to collide with the previous equation.")
             .finish()
         .object(Object::TeX(TeX::display(r#"\frac{1}{\left(\sqrt{\phi\sqrt5} - \phi\right) e^{\frac{2}{5}\pi}} = 1 + \frac{e^{-2\pi}}{1 + \frac{e^{-4\pi}}{1 + \frac{e^{-6\pi}}{1 + \frac{e^{-8\pi}}{1 + \unicodecdots}}}}"#, math_font)))
-        .object(Object::Svg(SvgObject::new(Scale::FitWidth, include_bytes!("../../data/Ghostscript_Tiger.svg")[..].into())))
+        .figure(Object::Svg(SvgObject::new(Scale::FitWidth, include_bytes!("../../data/Ghostscript_Tiger.svg")[..].into())), "The Ghostscript tiger")
+        .index()
+        .bibliography()
         .finish();
 
 
@@ -95,27 +116,43 @@ pub fn build() -> State<'static> {
         overflow_right: 1.0
     });
 
-    let dictionary = document.load_dict(DICT_EN_GB);
+    let leader = document.add_symbol(Symbol {
+        text: ".".into(),
+        leading: false,
+        trailing: false,
+        overflow_left: 0.0,
+        overflow_right: 0.0
+    });
+
+    let dictionary = document.load_language(&languages(), "en-gb").expect("no dictionary for en-gb");
 
     let default = TypeDesign {
         display:   Display::Inline,
         font:           Font {
             font_face,
-            size:  Length::mm(4.0)
+            size:  Length::mm(4.0),
+            features: Features::new(),
+            variations: Variations::default(),
+            synthetic: Synthetic::default()
         },
         word_space: FlexMeasure {
             shrink:  Length::mm(1.0),
             length:  Length::mm(2.0),
             stretch: Length::mm(3.0)
         },
+        letter_spacing: FlexMeasure::zero(),
         line_height: Length::mm(5.0),
         indent:      Length::zero(),
         hyphen: Some(hyphen),
-        dictionary
+        hyphenation: Hyphenation::default(),
+        initial: None,
+        color: Color::black(),
+        language: Some(dictionary),
+        direction: None
     };
 
     
-    let mut design = Design::new("default design".into(), default);
+    let mut design = Design::new("default design".into(), default.clone());
     design.set_type(
         document.find_type("chapter").unwrap(),
         TypeDesign {
@@ -127,17 +164,25 @@ pub fn build() -> State<'static> {
             ),
             font:           Font {
                 font_face,
-                size:  Length::mm(8.0)
+                size:  Length::mm(8.0),
+                features: Features::new(),
+                variations: Variations::default(),
+                synthetic: Synthetic::default()
             },
             word_space: FlexMeasure {
                 shrink:  Length::mm(2.0),
                 length:   Length::mm(4.0),
                 stretch: Length::mm(6.0)
             },
+            letter_spacing: FlexMeasure::zero(),
             line_height: Length::mm(10.0),
             indent:      Length::zero(),
             hyphen: None,
-            dictionary
+            hyphenation: Hyphenation::default(),
+            initial: None,
+            color: Color::black(),
+            language: Some(dictionary),
+            direction: None
         }
     );
     design.set_type(
@@ -152,20 +197,116 @@ pub fn build() -> State<'static> {
             ),
             font:           Font {
                 font_face,
-                size:  Length::mm(4.0)
+                size:  Length::mm(4.0),
+                features: Features::new(),
+                variations: Variations::default(),
+                synthetic: Synthetic::default()
             },
             word_space: FlexMeasure {
                 shrink:  Length::mm(1.0),
                 length:  Length::mm(2.0),
                 stretch: Length::mm(3.0)
             },
+            letter_spacing: FlexMeasure::zero(),
             line_height: Length::mm(5.0),
             indent:      Length::zero(),
             hyphen: Some(hyphen),
-            dictionary
+            hyphenation: Hyphenation::default(),
+            initial: None,
+            color: Color::black(),
+            language: Some(dictionary),
+            direction: None
         }
     );
 
+    design.set_type(
+        document.find_type("figure").unwrap(),
+        TypeDesign {
+            display:        Display::Float(Float {
+                placement: Placement::Top,
+                padding: VerticalPadding {
+                    above: Length::zero(),
+                    below: Length::mm(6.0)
+                },
+                label: None,
+                gap: Length::mm(4.0),
+                contour: false
+            }),
+            .. default.clone()
+        }
+    );
+    design.set_type(
+        document.find_type("caption").unwrap(),
+        TypeDesign {
+            display:        Display::Block(
+                VerticalPadding {
+                    above: Length::mm(2.0),
+                    below: Length::zero()
+                }
+            ),
+            font:           Font {
+                font_face,
+                size:  Length::mm(3.5),
+                features: Features::new(),
+                variations: Variations::default(),
+                synthetic: Synthetic::default()
+            },
+            line_height: Length::mm(4.5),
+            .. default
+        }
+    );
+
+    let chapter = document.find_type("chapter").unwrap();
+    design.set_type(
+        document.find_type("contents").unwrap(),
+        TypeDesign {
+            display:        Display::Contents(Contents {
+                padding: VerticalPadding {
+                    above: Length::zero(),
+                    below: Length::mm(8.0)
+                },
+                indent: Length::mm(5.0),
+                leader
+            }),
+            .. default.clone()
+        }
+    );
+    design.set_contents_types(vec![chapter]);
+    design.set_type(
+        document.find_type("index").unwrap(),
+        TypeDesign {
+            display:        Display::Index(Index {
+                padding: VerticalPadding {
+                    above: Length::mm(8.0),
+                    below: Length::zero()
+                },
+                indent: Length::mm(5.0)
+            }),
+            .. default.clone()
+        }
+    );
+    design.set_type(
+        document.find_type("bibliography").unwrap(),
+        TypeDesign {
+            display:        Display::Bibliography(Bibliography {
+                padding: VerticalPadding {
+                    above: Length::mm(8.0),
+                    below: Length::zero()
+                },
+                gap: Length::mm(2.0)
+            }),
+            .. default.clone()
+        }
+    );
+    design.set_counter("chapter", Counter {
+        suffix: ".".into(),
+        .. Counter::new(vec![chapter], NumberStyle::UpperRoman)
+    });
+    design.set_counter("figure", Counter {
+        prefix: "Figure ".into(),
+        .. Counter::new(vec![document.find_type("figure").unwrap()], NumberStyle::Arabic)
+    });
+
     let target = default_target();
     State {
         root: document.root(),
@@ -196,6 +337,12 @@ pub fn default_target() -> Target {
             top: Length::mm(0.),
             height: Length::mm(240.)
         },
-        page_color: Color
+        bleed_box: Rect {
+            left: Length::mm(-3.),
+            width: Length::mm(176.),
+            top: Length::mm(-3.),
+            height: Length::mm(246.)
+        },
+        page_color: Color::white()
     }
 }
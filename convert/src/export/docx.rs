@@ -42,7 +42,7 @@ fn add_sequence<'a>(writer: &mut DocxWriter<'a>, storage: &'a Storage, key: Sequ
     let type_design = design.get_type_or_default(seq.typ());
     match type_design.display {
         Display::Inline => {},
-        Display::Paragraph(_, _) | Display::Block(_) => writer.flush_para(),
+        Display::Paragraph(_, _) | Display::Block(_) | Display::Float(_) | Display::Table(_) | Display::Contents(_) | Display::Index(_) | Display::Bibliography(_) => writer.flush_para(),
     }
 
     for item in seq.render() {
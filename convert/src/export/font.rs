@@ -0,0 +1,464 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use ttf_parser::{Face, GlyphId, name_id};
+
+/// The glyphs of a font used in an exported file, with the text each of them shows
+pub struct FontSubset {
+    data: Vec<u8>,
+    glyphs: BTreeMap<u16, String>,
+
+    // the glyph of U+0020
+    space: Option<u16>,
+
+    // of all glyphs in thousandths of an em
+    advances: Vec<f32>,
+}
+
+pub struct FontMetrics {
+    // in thousandths of an em
+    pub bbox: [f32; 4],
+    pub ascent: f32,
+    pub descent: f32,
+    pub cap_height: f32,
+
+    pub italic_angle: f32,
+
+    // as in a PDF font descriptor
+    pub flags: u32,
+}
+
+impl FontSubset {
+    pub fn new(data: &[u8]) -> Self {
+        let face = Face::from_slice(data, 0).ok();
+        let advances = face.as_ref().map(|face| {
+            let scale = 1000.0 / face.units_per_em().unwrap_or(1000) as f32;
+            (0 .. face.number_of_glyphs())
+                .map(|gid| face.glyph_hor_advance(GlyphId(gid)).unwrap_or(0) as f32 * scale)
+                .collect()
+        }).unwrap_or_default();
+        FontSubset {
+            data: data.into(),
+            glyphs: BTreeMap::new(),
+            space: face.and_then(|face| face.glyph_index(' ')).map(|gid| gid.0),
+            advances
+        }
+    }
+
+    /// the glyph drawn between words, so the text of a file has spaces
+    pub fn space(&self) -> Option<u16> {
+        self.space
+    }
+
+    /// the first text a glyph is added with is the one it shows
+    pub fn add(&mut self, gid: u16, text: &str) {
+        let entry = self.glyphs.entry(gid).or_default();
+        if entry.is_empty() {
+            entry.push_str(text);
+        }
+    }
+
+    /// only a cluster of one glyph gives it the text. the glyphs of longer clusters
+    /// are shared with other text, a base letter before a combining mark for example.
+    pub fn add_cluster(&mut self, glyphs: &[u16], text: &str) {
+        match *glyphs {
+            [gid] => self.add(gid, text),
+            _ => {
+                for &gid in glyphs {
+                    self.add(gid, "");
+                }
+            }
+        }
+    }
+
+    /// in thousandths of an em
+    pub fn advance(&self, gid: u16) -> f32 {
+        self.advances.get(gid as usize).cloned().unwrap_or(0.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    /// OpenType fonts with CFF outlines
+    pub fn is_cff(&self) -> bool {
+        self.data.starts_with(b"OTTO")
+    }
+
+    /// TrueType outlines are cut down to the used glyphs. glyph ids stay the same.
+    /// CFF fonts are kept whole.
+    pub fn font_file(&self) -> Option<Vec<u8>> {
+        if self.is_cff() {
+            return Some(self.data.clone());
+        }
+        subset_truetype(&self.data, &self.glyphs.keys().cloned().collect())
+    }
+
+    /// "ABCDEF+Cormorant-Regular". the tag depends on the glyphs in the subset.
+    /// CFF fonts are embedded whole and keep their name.
+    pub fn subset_name(&self) -> String {
+        let name = Face::from_slice(&self.data, 0).ok()
+            .and_then(|face| face.names()
+                .filter(|name| name.name_id() == name_id::POST_SCRIPT_NAME)
+                .find_map(|name| name.to_string()))
+            .unwrap_or_else(|| "Unnamed".into());
+        let name: String = name.chars().filter(|c| c.is_ascii_graphic() && !"[](){}<>/%#".contains(*c)).collect();
+        if self.is_cff() {
+            return name;
+        }
+
+        // FNV-1a
+        let mut hash: u32 = 0x811c9dc5;
+        for &gid in self.glyphs.keys() {
+            for &b in gid.to_be_bytes().iter() {
+                hash = (hash ^ b as u32).wrapping_mul(0x01000193);
+            }
+        }
+        let tag: String = (0 .. 6).map(|i| (b'A' + (hash >> (i * 5) & 31) as u8 % 26) as char).collect();
+        format!("{}+{}", tag, name)
+    }
+
+    /// (glyph id, advance) of the used glyphs in thousandths of an em
+    pub fn widths(&self) -> Vec<(u16, f32)> {
+        self.glyphs.keys().map(|&gid| (gid, self.advance(gid))).collect()
+    }
+
+    pub fn metrics(&self) -> Option<FontMetrics> {
+        let face = Face::from_slice(&self.data, 0).ok()?;
+        let scale = 1000.0 / face.units_per_em()? as f32;
+        let bbox = face.global_bounding_box();
+        let italic_angle = face.italic_angle().unwrap_or(0.0);
+
+        // symbolic, since the glyphs are not in the standard encoding
+        let mut flags = 4;
+        if face.is_monospaced() {
+            flags |= 1;
+        }
+        if italic_angle != 0.0 {
+            flags |= 64;
+        }
+        Some(FontMetrics {
+            bbox: [
+                bbox.x_min as f32 * scale,
+                bbox.y_min as f32 * scale,
+                bbox.x_max as f32 * scale,
+                bbox.y_max as f32 * scale
+            ],
+            ascent: face.ascender() as f32 * scale,
+            descent: face.descender() as f32 * scale,
+            cap_height: face.capital_height().unwrap_or(face.ascender()) as f32 * scale,
+            italic_angle,
+            flags
+        })
+    }
+
+    /// a CMap from the glyph ids to their text, for copying and searching
+    pub fn to_unicode(&self) -> String {
+        let entries: Vec<(u16, &str)> = self.glyphs.iter()
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(&gid, text)| (gid, text.as_str()))
+            .collect();
+
+        let mut cmap = String::new();
+        cmap.push_str("/CIDInit /ProcSet findresource begin\n");
+        cmap.push_str("12 dict begin\n");
+        cmap.push_str("begincmap\n");
+        cmap.push_str("/CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n");
+        cmap.push_str("/CMapName /Adobe-Identity-UCS def\n");
+        cmap.push_str("/CMapType 2 def\n");
+        cmap.push_str("1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n");
+
+        // at most 100 entries per block
+        for block in entries.chunks(100) {
+            writeln!(cmap, "{} beginbfchar", block.len()).unwrap();
+            for &(gid, text) in block {
+                write!(cmap, "<{:04X}> <", gid).unwrap();
+                for unit in text.encode_utf16() {
+                    write!(cmap, "{:04X}", unit).unwrap();
+                }
+                cmap.push_str(">\n");
+            }
+            cmap.push_str("endbfchar\n");
+        }
+        cmap.push_str("endcmap\n");
+        cmap.push_str("CMapName currentdict /CMap defineresource pop\n");
+        cmap.push_str("end\nend\n");
+        cmap
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let b = data.get(offset .. offset + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset .. offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[.. chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+// the tables of a font file by their tag
+fn table_directory(data: &[u8]) -> Option<BTreeMap<[u8; 4], &[u8]>> {
+    let mut tables = BTreeMap::new();
+    for i in 0 .. read_u16(data, 4)? as usize {
+        let record = 12 + 16 * i;
+        let mut tag = [0; 4];
+        tag.copy_from_slice(data.get(record .. record + 4)?);
+        let offset = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        tables.insert(tag, data.get(offset .. offset + length)?);
+    }
+    Some(tables)
+}
+
+// the data of a glyph in the glyf table
+fn glyph_data<'a>(loca: &[u8], glyf: &'a [u8], long_offsets: bool, gid: u16) -> Option<&'a [u8]> {
+    let gid = gid as usize;
+    let (start, end) = match long_offsets {
+        false => (read_u16(loca, 2 * gid)? as usize * 2, read_u16(loca, 2 * gid + 2)? as usize * 2),
+        true => (read_u32(loca, 4 * gid)? as usize, read_u32(loca, 4 * gid + 4)? as usize)
+    };
+    glyf.get(start .. end)
+}
+
+// the glyphs a composite glyph is made of. simple glyphs have none.
+fn components(glyph: &[u8]) -> Option<Vec<u16>> {
+    let mut components = vec![];
+    if glyph.len() < 10 || read_u16(glyph, 0)? as i16 >= 0 {
+        return Some(components);
+    }
+    let mut pos = 10;
+    loop {
+        let flags = read_u16(glyph, pos)?;
+        components.push(read_u16(glyph, pos + 2)?);
+        pos += if flags & 0x0001 != 0 { 8 } else { 6 };
+        pos += match flags {
+            f if f & 0x0008 != 0 => 2,
+            f if f & 0x0040 != 0 => 4,
+            f if f & 0x0080 != 0 => 8,
+            _ => 0
+        };
+        if flags & 0x0020 == 0 {
+            return Some(components);
+        }
+    }
+}
+
+// the glyphs that are not kept (or used by a kept composite glyph) are left empty.
+// only the tables needed to draw the glyphs are copied.
+fn subset_truetype(data: &[u8], keep: &BTreeSet<u16>) -> Option<Vec<u8>> {
+    let tables = table_directory(data)?;
+    let head = *tables.get(b"head")?;
+    let loca = *tables.get(b"loca")?;
+    let glyf = *tables.get(b"glyf")?;
+    let num_glyphs = read_u16(tables.get(b"maxp")?, 4)?;
+    let long_offsets = read_u16(head, 50)? != 0;
+    let glyph = |gid: u16| glyph_data(loca, glyf, long_offsets, gid);
+
+    // .notdef and the components of composite glyphs
+    let mut keep = keep.clone();
+    keep.insert(0);
+    let mut stack: Vec<u16> = keep.iter().cloned().collect();
+    while let Some(gid) = stack.pop() {
+        let data = match glyph(gid) {
+            Some(data) => data,
+            None => continue
+        };
+        for component in components(data)? {
+            if component < num_glyphs && keep.insert(component) {
+                stack.push(component);
+            }
+        }
+    }
+
+    let mut new_glyf = vec![];
+    let mut new_loca = vec![];
+    for gid in 0 .. num_glyphs {
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        if let Some(data) = glyph(gid).filter(|_| keep.contains(&gid)) {
+            new_glyf.extend_from_slice(data);
+            new_glyf.resize((new_glyf.len() + 3) & !3, 0);
+        }
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+
+    // long offsets in loca, and the checksum adjustment is set at the end
+    let mut new_head = head.to_vec();
+    new_head[8 .. 12].copy_from_slice(&[0; 4]);
+    new_head[50 .. 52].copy_from_slice(&1u16.to_be_bytes());
+
+    let mut out_tables: BTreeMap<[u8; 4], Vec<u8>> = BTreeMap::new();
+    for tag in [b"hhea", b"hmtx", b"maxp", b"cvt ", b"fpgm", b"prep"].iter() {
+        if let Some(&table) = tables.get(*tag) {
+            out_tables.insert(**tag, table.to_vec());
+        }
+    }
+    out_tables.insert(*b"head", new_head);
+    out_tables.insert(*b"loca", new_loca);
+    out_tables.insert(*b"glyf", new_glyf);
+
+    let n = out_tables.len() as u16;
+    let entry_selector = 15 - n.leading_zeros() as u16;
+    let search_range = 16 << entry_selector;
+    let mut out = vec![];
+    out.extend_from_slice(&0x00010000u32.to_be_bytes());
+    for &v in [n, search_range, entry_selector, n * 16 - search_range].iter() {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+
+    let mut offset = 12 + 16 * out_tables.len();
+    let mut head_offset = 0;
+    for (tag, table) in out_tables.iter() {
+        if tag == b"head" {
+            head_offset = offset;
+        }
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&checksum(table).to_be_bytes());
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) & !3;
+    }
+    for table in out_tables.values() {
+        out.extend_from_slice(table);
+        out.resize((out.len() + 3) & !3, 0);
+    }
+
+    let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&out));
+    out[head_offset + 8 .. head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    Some(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FONT: &[u8] = include_bytes!("../../../data/Cormorant-Regular.ttf");
+
+    // (loca, glyf, long offsets) of a font file
+    fn glyph_tables(data: &[u8]) -> (&[u8], &[u8], bool) {
+        let tables = table_directory(data).unwrap();
+        let long_offsets = read_u16(tables[b"head"], 50).unwrap() != 0;
+        (tables[b"loca"], tables[b"glyf"], long_offsets)
+    }
+
+    // a composite glyph with a component that is a composite glyph too, if there is one
+    fn composite() -> u16 {
+        let (loca, glyf, long_offsets) = glyph_tables(FONT);
+        let num_glyphs = Face::from_slice(FONT, 0).unwrap().number_of_glyphs();
+        let composites: Vec<u16> = (0 .. num_glyphs)
+            .filter(|&gid| !components(glyph_data(loca, glyf, long_offsets, gid).unwrap()).unwrap().is_empty())
+            .collect();
+        composites.iter().cloned()
+            .find(|&gid| components(glyph_data(loca, glyf, long_offsets, gid).unwrap()).unwrap().iter().any(|c| composites.contains(c)))
+            .unwrap_or(composites[0])
+    }
+
+    #[test]
+    fn subset_keeps_components() {
+        let gid = composite();
+        let subset = subset_truetype(FONT, &[gid].iter().cloned().collect()).unwrap();
+
+        // the closure of the components in the original font
+        let (loca, glyf, long_offsets) = glyph_tables(FONT);
+        let mut closure: BTreeSet<u16> = [0, gid].iter().cloned().collect();
+        let mut stack = vec![0, gid];
+        while let Some(gid) = stack.pop() {
+            for c in components(glyph_data(loca, glyf, long_offsets, gid).unwrap()).unwrap() {
+                if closure.insert(c) {
+                    stack.push(c);
+                }
+            }
+        }
+        assert!(closure.len() > 2);
+
+        let (new_loca, new_glyf, new_long_offsets) = glyph_tables(&subset);
+        assert!(new_long_offsets);
+        let num_glyphs = Face::from_slice(FONT, 0).unwrap().number_of_glyphs();
+        for gid in 0 .. num_glyphs {
+            let data = glyph_data(new_loca, new_glyf, true, gid).unwrap();
+            assert_eq!(!data.is_empty(), closure.contains(&gid) && !glyph_data(loca, glyf, long_offsets, gid).unwrap().is_empty(), "glyph {}", gid);
+        }
+    }
+
+    #[test]
+    fn subset_rebuilds_loca_and_glyf() {
+        let keep: BTreeSet<u16> = (1 .. 100).collect();
+        let subset = subset_truetype(FONT, &keep).unwrap();
+        let face = Face::from_slice(&subset, 0).unwrap();
+        assert_eq!(face.number_of_glyphs(), Face::from_slice(FONT, 0).unwrap().number_of_glyphs());
+
+        // one long offset per glyph and one for the end, and glyphs start at multiples of 4
+        let (loca, glyf, long_offsets) = glyph_tables(FONT);
+        let (new_loca, new_glyf, _) = glyph_tables(&subset);
+        assert_eq!(new_loca.len(), 4 * (face.number_of_glyphs() as usize + 1));
+        assert_eq!(read_u32(new_loca, new_loca.len() - 4).unwrap() as usize, new_glyf.len());
+        for gid in 0 .. 100 {
+            let old = glyph_data(loca, glyf, long_offsets, gid).unwrap();
+            let new = glyph_data(new_loca, new_glyf, true, gid).unwrap();
+            assert_eq!(read_u32(new_loca, 4 * gid as usize).unwrap() % 4, 0);
+            assert_eq!(&new[.. old.len()], old);
+            assert_eq!(new.len(), (old.len() + 3) & !3);
+        }
+    }
+
+    #[test]
+    fn subset_checksums() {
+        let subset = subset_truetype(FONT, &(1 .. 10).collect()).unwrap();
+        assert_eq!(checksum(&subset), 0xB1B0AFBA);
+
+        // the checksum of the head table is taken with the adjustment set to 0
+        for i in 0 .. read_u16(&subset, 4).unwrap() as usize {
+            let record = 12 + 16 * i;
+            let offset = read_u32(&subset, record + 8).unwrap() as usize;
+            let length = read_u32(&subset, record + 12).unwrap() as usize;
+            let mut table = subset[offset .. offset + length].to_vec();
+            if &subset[record .. record + 4] == b"head" {
+                table[8 .. 12].copy_from_slice(&[0; 4]);
+            }
+            assert_eq!(read_u32(&subset, record + 4).unwrap(), checksum(&table));
+        }
+    }
+
+    #[test]
+    fn to_unicode_ligature() {
+        let mut subset = FontSubset::new(FONT);
+        let face = Face::from_slice(FONT, 0).unwrap();
+        let f = face.glyph_index('f').unwrap().0;
+        subset.add_cluster(&[f], "f");
+        // a ligature shows the text of its cluster, the glyphs of longer clusters show none
+        subset.add_cluster(&[1000], "ffi");
+        subset.add_cluster(&[1001, 1002], "ffl");
+        subset.add_cluster(&[1000], "f");
+
+        let cmap = subset.to_unicode();
+        assert!(cmap.contains("2 beginbfchar\n"));
+        assert!(cmap.contains(&format!("<{:04X}> <0066>\n", f)));
+        assert!(cmap.contains("<03E8> <006600660069>\n"));
+        assert!(!cmap.contains("<03E9>"));
+        assert!(!cmap.contains("<03EA>"));
+    }
+
+    #[test]
+    fn to_unicode_combining_mark() {
+        let mut subset = FontSubset::new(FONT);
+        let face = Face::from_slice(FONT, 0).unwrap();
+        let e = face.glyph_index('e').unwrap().0;
+        let acute = face.glyph_index('\u{301}').map_or(1000, |gid| gid.0);
+        // the base letter is seen first in the cluster with the mark
+        subset.add_cluster(&[e, acute], "e\u{301}");
+        subset.add_cluster(&[e], "e");
+
+        let cmap = subset.to_unicode();
+        assert!(cmap.contains("1 beginbfchar\n"));
+        assert!(cmap.contains(&format!("<{:04X}> <0065>\n", e)));
+        assert!(!cmap.contains("0301"));
+        assert_eq!(subset.widths().len(), 2);
+    }
+}
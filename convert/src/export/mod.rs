@@ -1 +1,3 @@
 pub mod docx;
+pub mod font;
+pub mod pdf;
@@ -0,0 +1,1575 @@
+use grafeia_core::*;
+use grafeia_core::layout::Column;
+use grafeia_core::draw::{RenderItem, RenderedWord, Layout, Cache};
+use pathfinder_content::{outline::Outline, segment::SegmentKind, gradient::Gradient};
+use pathfinder_color::ColorU;
+use pathfinder_renderer::{scene::Scene, paint::Paint};
+use pathfinder_geometry::{rect::RectF, vector::Vector2F, transform2d::Transform2F};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::fmt::{self, Write as FmtWrite};
+use super::font::FontSubset;
+
+/// A string in a PDF file. ASCII text is written literally with the delimiters escaped,
+/// anything else as UTF-16 with a byte order mark.
+pub struct PdfString<'a>(pub &'a str);
+impl<'a> fmt::Display for PdfString<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_ascii() {
+            write!(f, "(")?;
+            for c in self.0.chars() {
+                match c {
+                    '(' | ')' | '\\' => write!(f, "\\{}", c)?,
+                    '\r' => write!(f, "\\r")?,
+                    '\n' => write!(f, "\\n")?,
+                    c => write!(f, "{}", c)?
+                }
+            }
+            write!(f, ")")
+        } else {
+            write!(f, "<FEFF")?;
+            for unit in self.0.encode_utf16() {
+                write!(f, "{:04X}", unit)?;
+            }
+            write!(f, ">")
+        }
+    }
+}
+
+/// URIs are 7-bit ASCII in a PDF file. other bytes and spaces are percent-encoded.
+pub fn ascii_uri(uri: &str) -> String {
+    let mut out = String::with_capacity(uri.len());
+    for &b in uri.as_bytes() {
+        match b {
+            b'!' ..= b'~' => out.push(b as char),
+            b => write!(out, "%{:02X}", b).unwrap()
+        }
+    }
+    out
+}
+
+struct Counter<T> {
+    inner: T,
+    count: u64
+}
+impl<T> Counter<T> {
+    fn new(inner: T) -> Counter<T> {
+        Counter { inner, count: 0 }
+    }
+    fn pos(&self) -> u64 {
+        self.count
+    }
+}
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The objects of a PDF file. Object numbers start at 1.
+/// Objects can be reserved first and set once the objects they refer to are known.
+pub struct PdfFile {
+    objects: Vec<Option<Vec<u8>>>,
+    compress: bool,
+}
+impl PdfFile {
+    pub fn new() -> Self {
+        PdfFile {
+            objects: vec![],
+            compress: true
+        }
+    }
+
+    /// streams are Flate compressed unless this is turned off
+    pub fn set_compress(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    pub fn reserve(&mut self) -> usize {
+        self.objects.push(None);
+        self.objects.len()
+    }
+
+    pub fn set(&mut self, nr: usize, data: impl Into<Vec<u8>>) {
+        self.objects[nr - 1] = Some(data.into());
+    }
+
+    pub fn add(&mut self, data: impl Into<Vec<u8>>) -> usize {
+        let nr = self.reserve();
+        self.set(nr, data);
+        nr
+    }
+
+    /// `dict` has the entries of the stream dictionary besides /Length and /Filter
+    pub fn set_stream(&mut self, nr: usize, dict: &str, data: &[u8]) {
+        let object = match self.compress {
+            true => stream(&format!("{} /Filter /FlateDecode", dict), &deflate::deflate_bytes_zlib(data)),
+            false => stream(dict, data)
+        };
+        self.set(nr, object);
+    }
+
+    pub fn add_stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        let nr = self.reserve();
+        self.set_stream(nr, dict, data);
+        nr
+    }
+
+    /// streams that have to stay readable without decoding, like the XMP metadata
+    pub fn add_raw_stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        self.add(stream(dict, data))
+    }
+
+    /// the catalog is `root`. objects that were reserved but never set are written as null.
+    pub fn write_to(&self, writer: impl Write, root: usize, info: Option<usize>) -> io::Result<()> {
+        let mut out = Counter::new(writer);
+        out.write_all(b"%PDF-1.7\n%\xB5\xED\xAE\xFB\n")?;
+
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (idx, object) in self.objects.iter().enumerate() {
+            offsets.push(out.pos());
+            write!(out, "{} 0 obj\n", idx + 1)?;
+            match *object {
+                Some(ref data) => out.write_all(data)?,
+                None => out.write_all(b"null\n")?
+            }
+            if !object.as_ref().map_or(true, |data| data.ends_with(b"\n")) {
+                out.write_all(b"\n")?;
+            }
+            out.write_all(b"endobj\n")?;
+        }
+
+        // every entry is exactly 20 bytes long
+        let startxref = out.pos();
+        write!(out, "xref\n0 {}\n", self.objects.len() + 1)?;
+        out.write_all(b"0000000000 65535 f\r\n")?;
+        for offset in offsets {
+            write!(out, "{:010} 00000 n\r\n", offset)?;
+        }
+
+        write!(out, "trailer\n<< /Size {}\n/Root {} 0 R\n", self.objects.len() + 1, root)?;
+        if let Some(info) = info {
+            write!(out, "/Info {} 0 R\n", info)?;
+        }
+        write!(out, ">>\nstartxref\n{}\n%%EOF\n", startxref)?;
+        out.flush()
+    }
+}
+
+fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+    let mut object = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+    object.extend_from_slice(data);
+    object.extend_from_slice(b"\nendstream\n");
+    object
+}
+
+const PRODUCER: &str = "grafeia";
+
+/// the document information dictionary
+pub fn add_info(file: &mut PdfFile, metadata: &Metadata) -> usize {
+    let mut info = String::from("<<");
+    if let Some(ref title) = metadata.title {
+        write!(info, " /Title {}", PdfString(title)).unwrap();
+    }
+    if let Some(ref author) = metadata.author {
+        write!(info, " /Author {}", PdfString(author)).unwrap();
+    }
+    write!(info, " /Producer {} >>\n", PdfString(PRODUCER)).unwrap();
+    file.add(info)
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c)
+        }
+    }
+    out
+}
+
+/// the XMP packet with the same title and author as the information dictionary
+pub fn xmp_metadata(metadata: &Metadata) -> String {
+    let mut xmp = String::new();
+    xmp.push_str("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    xmp.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    xmp.push_str("<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    xmp.push_str("<rdf:Description rdf:about=\"\"\n");
+    xmp.push_str("  xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n");
+    xmp.push_str("  xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\n");
+    if let Some(ref title) = metadata.title {
+        writeln!(xmp, "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>", xml_escape(title)).unwrap();
+    }
+    if let Some(ref author) = metadata.author {
+        writeln!(xmp, "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>", xml_escape(author)).unwrap();
+    }
+    writeln!(xmp, "<pdf:Producer>{}</pdf:Producer>", PRODUCER).unwrap();
+    xmp.push_str("</rdf:Description>\n");
+    xmp.push_str("</rdf:RDF>\n");
+    xmp.push_str("</x:xmpmeta>\n");
+    xmp.push_str("<?xpacket end=\"w\"?>");
+    xmp
+}
+
+/// for the /Metadata entry of the catalog
+pub fn add_xmp_metadata(file: &mut PdfFile, metadata: &Metadata) -> usize {
+    file.add_raw_stream("/Type /Metadata /Subtype /XML", xmp_metadata(metadata).as_bytes())
+}
+
+/// A bookmark. Entries of a higher level are nested in the previous entry of a lower level.
+pub struct OutlineEntry {
+    pub level: usize,
+    pub title: String,
+
+    // the object of the page and the position on it
+    pub page: usize,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// the outline dictionary for the /Outlines entry of the catalog, if there are any entries
+pub fn add_outlines(file: &mut PdfFile, entries: &[OutlineEntry]) -> Option<usize> {
+    if entries.is_empty() {
+        return None;
+    }
+    let root = file.reserve();
+    let ids: Vec<usize> = entries.iter().map(|_| file.reserve()).collect();
+
+    // the parent of each entry (None for the root) and the children of each entry
+    let mut parents = Vec::with_capacity(entries.len());
+    let mut children: Vec<Vec<usize>> = vec![vec![]; entries.len()];
+    let mut top = vec![];
+    let mut stack: Vec<usize> = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        while let Some(&last) = stack.last() {
+            if entries[last].level < entry.level {
+                break;
+            }
+            stack.pop();
+        }
+        match stack.last() {
+            Some(&parent) => children[parent].push(i),
+            None => top.push(i)
+        }
+        parents.push(stack.last().cloned());
+        stack.push(i);
+    }
+
+    let siblings = |i: usize| -> &[usize] {
+        match parents[i] {
+            Some(parent) => &children[parent],
+            None => &top
+        }
+    };
+    for (i, entry) in entries.iter().enumerate() {
+        let mut dict = String::new();
+        writeln!(dict, "<< /Title {}", PdfString(&entry.title)).unwrap();
+        writeln!(dict, "/Parent {} 0 R", parents[i].map_or(root, |p| ids[p])).unwrap();
+        let list = siblings(i);
+        let pos = list.iter().position(|&s| s == i).unwrap();
+        if pos > 0 {
+            writeln!(dict, "/Prev {} 0 R", ids[list[pos - 1]]).unwrap();
+        }
+        if let Some(&next) = list.get(pos + 1) {
+            writeln!(dict, "/Next {} 0 R", ids[next]).unwrap();
+        }
+        if let (Some(&first), Some(&last)) = (children[i].first(), children[i].last()) {
+            writeln!(dict, "/First {} 0 R", ids[first]).unwrap();
+            writeln!(dict, "/Last {} 0 R", ids[last]).unwrap();
+            // closed below the top level, so only the children would show
+            let n = children[i].len() as isize;
+            writeln!(dict, "/Count {}", if parents[i].is_none() { n } else { -n }).unwrap();
+        }
+        writeln!(dict, "/Dest [{} 0 R /XYZ {} {} null] >>", entry.page, entry.x, entry.y).unwrap();
+        file.set(ids[i], dict);
+    }
+
+    let visible = top.len() + top.iter().map(|&i| children[i].len()).sum::<usize>();
+    file.set(root, format!("<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>\n",
+        ids[top[0]], ids[*top.last().unwrap()], visible));
+    Some(root)
+}
+
+/// The colors a file is written in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorMode {
+    Rgb,
+    Cmyk,
+
+    // everything in shades of gray, spot colors included
+    Gray,
+}
+
+/// Writes colors in the color mode of the file and keeps the spot colors,
+/// which need a /Separation color space in the resources.
+pub struct Colors {
+    mode: ColorMode,
+
+    // the resource /S{n} is the separation spots[n]
+    spots: Vec<(String, [f32; 4])>,
+}
+impl Colors {
+    pub fn new(mode: ColorMode) -> Self {
+        Colors {
+            mode,
+            spots: vec![]
+        }
+    }
+
+    pub fn mode(&self) -> ColorMode {
+        self.mode
+    }
+
+    fn spot(&mut self, name: &str, alternate: [f32; 4]) -> usize {
+        match self.spots.iter().position(|(spot, _)| spot == name) {
+            Some(n) => n,
+            None => {
+                self.spots.push((name.into(), alternate));
+                self.spots.len() - 1
+            }
+        }
+    }
+
+    /// the operators that set the fill color, or the stroke color
+    pub fn set(&mut self, color: &Color, stroke: bool) -> String {
+        let op = |fill: &'static str| match stroke {
+            false => fill.to_owned(),
+            true => fill.to_uppercase()
+        };
+        match (self.mode, color) {
+            (ColorMode::Gray, color) => format!("{} {}", color.to_gray(), op("g")),
+            (_, &Color::Spot { ref name, tint, alternate }) => {
+                let n = self.spot(name, alternate);
+                format!("/S{} {} {} {}", n, op("cs"), tint, op("scn"))
+            }
+            (ColorMode::Rgb, color) => {
+                let (r, g, b) = color.to_rgb();
+                format!("{} {} {} {}", r, g, b, op("rg"))
+            }
+            (ColorMode::Cmyk, color) => {
+                let (c, m, y, k) = color.to_cmyk();
+                format!("{} {} {} {} {}", c, m, y, k, op("k"))
+            }
+        }
+    }
+
+    /// the device color space for shadings and the components of the color in it
+    pub fn components(&self, color: &Color) -> (&'static str, Vec<f32>) {
+        match self.mode {
+            ColorMode::Rgb => {
+                let (r, g, b) = color.to_rgb();
+                ("/DeviceRGB", vec![r, g, b])
+            }
+            ColorMode::Cmyk => {
+                let (c, m, y, k) = color.to_cmyk();
+                ("/DeviceCMYK", vec![c, m, y, k])
+            }
+            ColorMode::Gray => ("/DeviceGray", vec![color.to_gray()])
+        }
+    }
+
+    /// the entries of the /ColorSpace resource dictionary
+    pub fn resources(&self) -> String {
+        let mut out = String::new();
+        for (n, (name, [c, m, y, k])) in self.spots.iter().enumerate() {
+            let name: String = name.bytes().map(|b| match b {
+                b'a' ..= b'z' | b'A' ..= b'Z' | b'0' ..= b'9' | b'-' | b'_' | b'.' => (b as char).to_string(),
+                b => format!("#{:02X}", b)
+            }).collect();
+            writeln!(out, "/S{} [/Separation /{} /DeviceCMYK << /FunctionType 2 /Domain [0 1] /C0 [0 0 0 0] /C1 [{} {} {} {}] /N 1 >>]",
+                n, name, c, m, y, k).unwrap();
+        }
+        out
+    }
+}
+
+/// What a file for a printer needs besides the pages
+pub struct PrintOptions {
+    pub color_mode: ColorMode,
+
+    // crop and registration marks
+    pub marks: bool,
+
+    // the ICC profile of the printing condition, for CMYK or gray files
+    pub icc_profile: Option<Vec<u8>>,
+}
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            color_mode: ColorMode::Rgb,
+            marks: false,
+            icc_profile: None
+        }
+    }
+}
+
+// in millimeters, like the page
+const MARK_OFFSET: f32 = 3.0;
+const MARK_LENGTH: f32 = 5.0;
+const MARK_WIDTH: f32 = 0.1;
+const REGISTRATION_RADIUS: f32 = 2.0;
+
+/// the area the print marks of a page take
+pub fn marks_box(trim_box: RectF, bleed_box: RectF) -> RectF {
+    let distance = (trim_box.min_x() - bleed_box.min_x())
+        .max(trim_box.min_y() - bleed_box.min_y())
+        .max(bleed_box.max_x() - trim_box.max_x())
+        .max(bleed_box.max_y() - trim_box.max_y())
+        .max(0.0);
+    let d = Vector2F::splat(distance + MARK_OFFSET + MARK_LENGTH + REGISTRATION_RADIUS);
+    RectF::from_points(trim_box.origin() - d, trim_box.lower_right() + d)
+}
+
+/// crop marks at the corners of the trim box and registration marks at the middle of its sides,
+/// all outside of the bleed box, in the coordinates of the page.
+pub fn print_marks(colors: &mut Colors, trim_box: RectF, bleed_box: RectF) -> String {
+    let mut out = String::new();
+    writeln!(out, "q {} {} w", colors.set(&Color::registration(), true), MARK_WIDTH).unwrap();
+
+    fn line(out: &mut String, a: Vector2F, b: Vector2F) {
+        writeln!(out, "{} {} m {} {} l S", a.x(), a.y(), b.x(), b.y()).unwrap();
+    }
+    let (left, right) = (trim_box.min_x(), trim_box.max_x());
+    let (bottom, top) = (trim_box.min_y(), trim_box.max_y());
+
+    // horizontal marks start outside of the bleed on the left and right, vertical ones above and below
+    let start_left = bleed_box.min_x().min(left) - MARK_OFFSET;
+    let start_right = bleed_box.max_x().max(right) + MARK_OFFSET;
+    let start_bottom = bleed_box.min_y().min(bottom) - MARK_OFFSET;
+    let start_top = bleed_box.max_y().max(top) + MARK_OFFSET;
+    for &y in [bottom, top].iter() {
+        line(&mut out, Vector2F::new(start_left, y), Vector2F::new(start_left - MARK_LENGTH, y));
+        line(&mut out, Vector2F::new(start_right, y), Vector2F::new(start_right + MARK_LENGTH, y));
+    }
+    for &x in [left, right].iter() {
+        line(&mut out, Vector2F::new(x, start_bottom), Vector2F::new(x, start_bottom - MARK_LENGTH));
+        line(&mut out, Vector2F::new(x, start_top), Vector2F::new(x, start_top + MARK_LENGTH));
+    }
+
+    // a circle with a cross
+    let center = trim_box.origin() + Vector2F::splat(0.5) * trim_box.size();
+    let half = MARK_LENGTH / 2.;
+    let centers = [
+        Vector2F::new(center.x(), start_bottom - half),
+        Vector2F::new(center.x(), start_top + half),
+        Vector2F::new(start_left - half, center.y()),
+        Vector2F::new(start_right + half, center.y()),
+    ];
+    for &c in centers.iter() {
+        line(&mut out, c - Vector2F::new(half, 0.), c + Vector2F::new(half, 0.));
+        line(&mut out, c - Vector2F::new(0., half), c + Vector2F::new(0., half));
+        let r = REGISTRATION_RADIUS * 0.6;
+        // four bezier quarters
+        let k = 0.5523 * r;
+        writeln!(out, "{} {} m", c.x() + r, c.y()).unwrap();
+        writeln!(out, "{} {} {} {} {} {} c", c.x() + r, c.y() + k, c.x() + k, c.y() + r, c.x(), c.y() + r).unwrap();
+        writeln!(out, "{} {} {} {} {} {} c", c.x() - k, c.y() + r, c.x() - r, c.y() + k, c.x() - r, c.y()).unwrap();
+        writeln!(out, "{} {} {} {} {} {} c", c.x() - r, c.y() - k, c.x() - k, c.y() - r, c.x(), c.y() - r).unwrap();
+        writeln!(out, "{} {} {} {} {} {} c S", c.x() + k, c.y() - r, c.x() + r, c.y() - k, c.x() + r, c.y()).unwrap();
+    }
+    writeln!(out, "Q").unwrap();
+    out
+}
+
+/// the ICC profile as the default color space of the device colors, an entry of the /ColorSpace
+/// resources. profiles of printing conditions are for CMYK or gray files, so RGB files get none.
+pub fn add_icc_profile(file: &mut PdfFile, profile: &[u8], mode: ColorMode) -> Option<String> {
+    let (space, components) = match mode {
+        ColorMode::Rgb => return None,
+        ColorMode::Cmyk => ("DefaultCMYK", 4),
+        ColorMode::Gray => ("DefaultGray", 1)
+    };
+    let nr = file.add_stream(&format!("/N {}", components), profile);
+    Some(format!("/{} [/ICCBased {} 0 R]\n", space, nr))
+}
+
+struct PdfRect(RectF);
+impl fmt::Display for PdfRect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ll = self.0.lower_left();
+        let ur = self.0.upper_right();
+        write!(f, "[{} {} {} {}]", ll.x(), ll.y(), ur.x(), ur.y())
+    }
+}
+
+struct PdfTransform(Transform2F);
+impl fmt::Display for PdfTransform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = self.0.matrix;
+        let v = self.0.vector;
+        write!(f, "{} {} {} {} {} {}", m.m11(), m.m12(), m.m21(), m.m22(), v.x(), v.y())
+    }
+}
+
+struct Stream {
+    data: String,
+
+    // inside BT .. ET, with the font set in it
+    in_text: bool,
+    font: Option<usize>,
+    // the text matrix the current run of glyphs starts at, the pen position in ems
+    // from there, and the TJ array of the run
+    run_origin: Option<Transform2F>,
+    pen: f32,
+    run: Vec<String>,
+}
+impl Stream {
+    pub fn new() -> Stream {
+        Stream {
+            data: String::new(),
+            in_text: false,
+            font: None,
+            run_origin: None,
+            pen: 0.0,
+            run: vec![],
+        }
+    }
+    pub fn move_to(&mut self, p: Vector2F) {
+        writeln!(self.data, "{} {} m", p.x(), p.y()).unwrap();
+    }
+
+    pub fn line_to(&mut self, p: Vector2F) {
+        writeln!(self.data, "{} {} l", p.x(), p.y()).unwrap();
+    }
+
+    pub fn cubic_to(&mut self, c1: Vector2F, c2: Vector2F, p: Vector2F) {
+        writeln!(self.data, "{} {} {} {} {} {} c", c1.x(), c1.y(), c2.x(), c2.y(), p.x(), p.y()).unwrap();
+    }
+    pub fn fill(&mut self) {
+        writeln!(self.data, "f").unwrap();
+    }
+
+    pub fn close(&mut self) {
+        writeln!(self.data, "h").unwrap();
+    }
+
+    pub fn save(&mut self) {
+        self.end_text();
+        writeln!(self.data, "q").unwrap();
+    }
+    pub fn restore(&mut self) {
+        writeln!(self.data, "Q").unwrap();
+    }
+    pub fn set_fill_color(&mut self, colors: &mut Colors, color: &Color) {
+        writeln!(self.data, "{}", colors.set(color, false)).unwrap();
+    }
+    pub fn set_fill_pattern(&mut self, n: usize) {
+        writeln!(self.data, "/Pattern cs /P{} scn", n).unwrap();
+    }
+    pub fn set_alpha(&mut self, n: usize) {
+        writeln!(self.data, "/A{} gs", n).unwrap();
+    }
+    pub fn set_mask(&mut self, n: usize) {
+        writeln!(self.data, "/M{} gs", n).unwrap();
+    }
+
+    pub fn use_define(&mut self, n: usize, tr: Transform2F) {
+        self.end_text();
+        writeln!(self.data, "q {} cm /x{} Do Q", PdfTransform(tr), n).unwrap();
+    }
+
+    // a text object starts with the first glyph and lasts until the end of the line
+    // or other content
+    fn begin_text(&mut self) {
+        if !self.in_text {
+            writeln!(self.data, "BT").unwrap();
+            self.in_text = true;
+        }
+    }
+    pub fn end_text(&mut self) {
+        if self.in_text {
+            self.end_run();
+            writeln!(self.data, "ET").unwrap();
+            self.in_text = false;
+            self.font = None;
+            self.run_origin = None;
+        }
+    }
+    fn end_run(&mut self) {
+        if !self.run.is_empty() {
+            writeln!(self.data, "[{}] TJ", self.run.join(" ")).unwrap();
+            self.run.clear();
+        }
+    }
+    pub fn begin_actual_text(&mut self, text: &str) {
+        self.begin_text();
+        self.end_run();
+        writeln!(self.data, "/Span << /ActualText {} >> BDC", PdfString(text)).unwrap();
+    }
+    pub fn end_marked_content(&mut self) {
+        self.end_run();
+        writeln!(self.data, "EMC").unwrap();
+    }
+    // the transform maps the em square of the glyph, `advance` is in thousandths of an em.
+    // a glyph on the baseline of the run goes into its TJ array, after the distance
+    // from the advance of the glyph before.
+    pub fn show_glyph(&mut self, n: usize, gid: u16, advance: f32, tr: Transform2F) {
+        self.begin_text();
+        let offset = self.run_origin
+            .filter(|&origin| self.font == Some(n) && same_matrix(origin, tr))
+            .map(|origin| origin.inverse() * tr.vector)
+            .filter(|local| local.y().abs() < 1e-3);
+        let x = match offset {
+            Some(local) => {
+                // positive numbers move back
+                let adjust = (self.pen - local.x()) * 1000.0;
+                if adjust.abs() > 0.01 {
+                    self.run.push(format!("{}", adjust));
+                }
+                local.x()
+            }
+            None => {
+                self.end_run();
+                if self.font != Some(n) {
+                    writeln!(self.data, "/F{} 1 Tf", n).unwrap();
+                    self.font = Some(n);
+                }
+                writeln!(self.data, "{} Tm", PdfTransform(tr)).unwrap();
+                self.run_origin = Some(tr);
+                0.0
+            }
+        };
+        self.run.push(format!("<{:04X}>", gid));
+        self.pen = x + advance / 1000.0;
+    }
+    /// the font of the run of glyphs that is shown
+    pub fn text_font(&self) -> Option<usize> {
+        self.run_origin.and(self.font)
+    }
+    // a glyph right after the last one of the run
+    pub fn show_space(&mut self, gid: u16, advance: f32) {
+        self.run.push(format!("<{:04X}>", gid));
+        self.pen += advance / 1000.0;
+    }
+
+    pub fn draw_path(&mut self, outline: Outline) {
+        self.end_text();
+        for contour in outline.contours() {
+            for (segment_index, segment) in contour.iter().enumerate() {
+                if segment_index == 0 {
+                    self.move_to(segment.baseline.from());
+                }
+
+                match segment.kind {
+                    SegmentKind::None => {}
+                    SegmentKind::Line => self.line_to(segment.baseline.to()),
+                    SegmentKind::Quadratic => {
+                        let current = segment.baseline.from();
+                        let c = segment.ctrl.from();
+                        let p = segment.baseline.to();
+                        let c1 = Vector2F::splat(2./3.) * c + Vector2F::splat(1./3.) * current;
+                        let c2 = Vector2F::splat(2./3.) * c + Vector2F::splat(1./3.) * p;
+                        self.cubic_to(c1, c2, p);
+                    }
+                    SegmentKind::Cubic => self.cubic_to(
+                        segment.ctrl.from(),
+                        segment.ctrl.to(),
+                        segment.baseline.to()
+                    )
+                }
+            }
+
+            if contour.is_closed() {
+                self.close();
+            }
+        }
+    }
+}
+/// A page. Its object is written last, once the links on it are known.
+struct PdfPage {
+    id: usize,
+    contents: usize,
+    media_box: RectF,
+    trim_box: RectF,
+    bleed_box: RectF,
+    annotations: Vec<usize>,
+}
+
+enum LinkTarget {
+    Uri(String),
+
+    // page and position
+    Dest(usize, Vector2F),
+}
+
+/// The top-level struct that represents a (partially) in-memory PDF file
+pub struct Pdf {
+    file: PdfFile,
+
+    // the page tree, which the pages refer to
+    pages_nr: usize,
+
+    defines: HashMap<RenderItem, usize>,
+    pages: Vec<PdfPage>,
+
+    // the resource /F{n} is fonts[n]
+    fonts: Vec<(FontId, FontSubset)>,
+
+    // the resource /P{n} is the object patterns[n]
+    patterns: Vec<usize>,
+    pattern_keys: HashMap<Vec<u32>, usize>,
+
+    // the resource /M{n} sets the soft mask masks[n]
+    masks: Vec<usize>,
+    mask_keys: HashMap<Vec<u32>, usize>,
+
+    // the resource /A{n} sets the fill alpha alphas[n]
+    alphas: Vec<u8>,
+
+    colors: Colors,
+    marks: bool,
+    icc_profile: Option<Vec<u8>>,
+}
+impl Pdf {
+    /// Create a new blank PDF document
+    #[inline]
+    pub fn new(options: PrintOptions) -> Self {
+        let mut file = PdfFile::new();
+        let pages_nr = file.reserve();
+        Self {
+            file,
+            pages_nr,
+            defines: HashMap::new(),
+            pages: vec![],
+            fonts: vec![],
+            patterns: vec![],
+            pattern_keys: HashMap::new(),
+            masks: vec![],
+            mask_keys: HashMap::new(),
+            alphas: vec![],
+            colors: Colors::new(options.color_mode),
+            marks: options.marks,
+            icc_profile: options.icc_profile,
+        }
+    }
+    fn defined(&mut self, key: RenderItem, f: impl FnOnce() -> Outline) -> usize {
+        if let Some(&n) = self.defines.get(&key) {
+            return n;
+        }
+        let outline = f();
+        let bbox = outline.bounds();
+        let mut stream = Stream::new();
+        stream.draw_path(outline);
+        stream.fill();
+
+        let dict = format!("/Type /XObject /Subtype /Form /BBox {}", PdfRect(bbox));
+        let n = self.file.add_stream(&dict, stream.data.as_bytes());
+        self.defines.insert(key, n);
+        n
+    }
+    fn font(&mut self, storage: &Storage, id: FontId) -> usize {
+        match self.fonts.iter().position(|&(font_id, _)| font_id == id) {
+            Some(n) => n,
+            None => {
+                self.fonts.push((id, FontSubset::new(storage.get_font_face(id).data())));
+                self.fonts.len() - 1
+            }
+        }
+    }
+    // as text in the embedded fonts, unless the glyphs are not drawn as they are in the font file.
+    fn draw_layout(&mut self, stream: &mut Stream, storage: &Storage, item: RenderItem, layout: &Layout, p: Vector2F, spacing: f32) {
+        if layout.needs_outlines() {
+            // letter spaced words differ from line to line
+            if spacing.abs() < 1e-4 {
+                let n = self.defined(item, || layout.render(storage, Transform2F::default(), 0.0));
+                stream.use_define(n, Transform2F::from_translation(p));
+            } else {
+                stream.draw_path(layout.render(storage, Transform2F::from_translation(p), spacing));
+                stream.fill();
+            }
+            return;
+        }
+
+        // glyphs right to left show the text backwards
+        let actual_text = layout.actual_text();
+        if let Some(text) = actual_text {
+            stream.begin_actual_text(text);
+        }
+        let glyphs = layout.glyphs(storage, Transform2F::from_translation(p), spacing);
+        let mut rest = &glyphs[..];
+        while let Some((&(font_id, _, _, text), tail)) = rest.split_first() {
+            // the glyphs after the first one of a cluster have no text
+            let (cluster, tail) = rest.split_at(1 + tail.iter().take_while(|g| g.3.is_empty()).count());
+            rest = tail;
+
+            let n = self.font(storage, font_id);
+            let gids: Vec<u16> = cluster.iter().map(|&(_, gid, _, _)| gid.0 as u16).collect();
+            self.fonts[n].1.add_cluster(&gids, text);
+            // the font only maps one glyph to one char right
+            let span = actual_text.is_none() && (gids.len() > 1 || text.chars().count() > 1);
+            if span {
+                stream.begin_actual_text(text);
+            }
+            for (&(_, _, tr, _), &gid) in cluster.iter().zip(gids.iter()) {
+                stream.show_glyph(n, gid, self.fonts[n].1.advance(gid), tr);
+            }
+            if span {
+                stream.end_marked_content();
+            }
+        }
+        if actual_text.is_some() {
+            stream.end_marked_content();
+        }
+    }
+    // the space glyph after the last glyph of a word, for the words to be apart in the text of the file
+    fn draw_space(&mut self, stream: &mut Stream) {
+        if let Some(n) = stream.text_font() {
+            let subset = &mut self.fonts[n].1;
+            if let Some(gid) = subset.space() {
+                subset.add(gid, " ");
+                stream.show_space(gid, subset.advance(gid));
+            }
+        }
+    }
+    fn alpha(&mut self, alpha: u8) -> usize {
+        match self.alphas.iter().position(|&a| a == alpha) {
+            Some(n) => n,
+            None => {
+                self.alphas.push(alpha);
+                self.alphas.len() - 1
+            }
+        }
+    }
+
+    // an axial or radial shading pattern. the matrix maps the coordinates of the gradient to the page.
+    fn gradient(&mut self, gradient: &Gradient, matrix: Transform2F) -> usize {
+        let mut key = gradient_key(gradient);
+        key.extend(transform_key(matrix).iter());
+        if let Some(&n) = self.pattern_keys.get(&key) {
+            return n;
+        }
+
+        let colors = &self.colors;
+        let color = |c: ColorU| {
+            let (_, components) = colors.components(&Color::from_rgb8(c.r, c.g, c.b));
+            let components: Vec<String> = components.iter().map(|v| v.to_string()).collect();
+            format!("[{}]", components.join(" "))
+        };
+        let (space, _) = colors.components(&Color::black());
+        let shading = shading(gradient, &space, color);
+
+        let mut pattern = String::new();
+        writeln!(pattern, "<< /Type /Pattern").unwrap();
+        writeln!(pattern, "/PatternType 2").unwrap();
+        writeln!(pattern, "/Matrix [{}]", PdfTransform(matrix)).unwrap();
+        writeln!(pattern, "/Shading {}", shading).unwrap();
+        writeln!(pattern, ">>").unwrap();
+        let nr = self.file.add(pattern);
+        self.patterns.push(nr);
+        self.pattern_keys.insert(key, self.patterns.len() - 1);
+        self.patterns.len() - 1
+    }
+
+    // a luminosity soft mask with the alpha of the stops as gray, for gradients that are not opaque.
+    // the mask is drawn in the user space where it is set, which is the space of the gradient.
+    fn gradient_mask(&mut self, gradient: &Gradient, bbox: RectF) -> Option<usize> {
+        if gradient.stops().iter().all(|stop| stop.color.a == 255) {
+            return None;
+        }
+        let mut key = gradient_key(gradient);
+        key.extend([bbox.min_x(), bbox.min_y(), bbox.max_x(), bbox.max_y()].iter().map(|v| v.to_bits()));
+        if let Some(&n) = self.mask_keys.get(&key) {
+            return Some(n);
+        }
+
+        let shading = shading(gradient, "/DeviceGray", |c: ColorU| format!("[{}]", c.a as f32 / 255.));
+        let dict = format!("/Type /XObject /Subtype /Form /BBox {} /Group << /S /Transparency /CS /DeviceGray >> /Resources << /Shading << /Sh0 {} >> >>",
+            PdfRect(bbox), shading);
+        let nr = self.file.add_stream(&dict, b"/Sh0 sh\n");
+        self.masks.push(nr);
+        self.mask_keys.insert(key, self.masks.len() - 1);
+        Some(self.masks.len() - 1)
+    }
+
+    // the paths of a scene as fills. strokes are already outlined by pathfinder.
+    // page_tr is the transform of the page stream, which patterns do not see.
+    fn draw_scene(&mut self, stream: &mut Stream, scene: &Scene, page_tr: Transform2F) {
+        for (paint, outline, _) in scene.paths() {
+            stream.save();
+            match paint {
+                Paint::Color(color) => {
+                    if color.a == 0 {
+                        stream.restore();
+                        continue;
+                    }
+                    if color.a < 255 {
+                        let n = self.alpha(color.a);
+                        stream.set_alpha(n);
+                    }
+                    stream.set_fill_color(&mut self.colors, &Color::from_rgb8(color.r, color.g, color.b));
+                }
+                Paint::Gradient(gradient) => {
+                    if let Some(n) = self.gradient_mask(gradient, outline.bounds()) {
+                        stream.set_mask(n);
+                    }
+                    let n = self.gradient(gradient, page_tr);
+                    stream.set_fill_pattern(n);
+                }
+                Paint::Pattern(_) => {
+                    // images are not exported
+                    stream.restore();
+                    continue;
+                }
+            }
+            stream.draw_path(outline.clone());
+            stream.fill();
+            stream.restore();
+        }
+    }
+
+    pub fn render_page(&mut self, cache: &Cache, storage: &Storage, target: &Target, design: &Design, column: Column) {
+        let content_box: RectF = target.content_box.into();
+        let trim_box: RectF = target.trim_box.into();
+        let bleed_box: RectF = target.bleed_box.into();
+        let mut media_box: RectF = target.media_box.into();
+
+        let mut stream = Stream::new();
+        let page_tr = page_transform(target);
+        writeln!(stream.data, "{} cm", PdfTransform(page_tr)).unwrap();
+        if self.marks {
+            media_box = media_box.union_rect(marks_box(trim_box, bleed_box));
+            stream.data.push_str(&print_marks(&mut self.colors, trim_box, bleed_box));
+        }
+        // the color of the text, set where it changes
+        let mut fill: Option<&Color> = None;
+
+        for (y, line) in cache.column_lines(column) {
+            let items: Vec<_> = line.collect();
+            for (i, &(x, size, item, tag)) in items.iter().enumerate() {
+                let size: Vector2F = size.into();
+                // a space where glue follows on the line
+                let space = items.get(i + 1).map_or(false, |next| next.0.value as f32 > x.value as f32 + size.x() + 1e-3);
+                let p = content_box.origin() + Vector2F::new(x.value as f32, y.value as f32);
+                let rect = RectF::new(p - Vector2F::new(0.0, size.y()), size);
+                let color = &design.get_type_or_default(storage.get_weave(tag.seq()).typ()).color;
+                if fill != Some(color) {
+                    stream.set_fill_color(&mut self.colors, color);
+                    fill = Some(color);
+                }
+                // whether the item is text
+                let text = match item {
+                    RenderItem::Word(key, part, font) => {
+                        let layout = cache.word_layout_cache.get(&(font, key, part)).unwrap();
+                        let spacing = layout.spacing_for(size.x());
+                        self.draw_layout(&mut stream, storage, item, layout, p, spacing);
+                        true
+                    }
+                    RenderItem::Symbol(key, font) => {
+                        let layout = cache.symbol_layout_cache.get(&(font, key)).unwrap();
+                        self.draw_layout(&mut stream, storage, item, layout, p, 0.0);
+                        true
+                    }
+                    RenderItem::Generated(index) => {
+                        let layout = &cache.generated[index];
+                        self.draw_layout(&mut stream, storage, item, layout, p, 0.0);
+                        true
+                    }
+                    RenderItem::Leader(key, font) => {
+                        let layout = cache.symbol_layout_cache.get(&(font, key)).unwrap();
+                        for x in layout.repeat(p.x(), size.x()) {
+                            self.draw_layout(&mut stream, storage, RenderItem::Symbol(key, font), layout, Vector2F::new(x, p.y()), 0.0);
+                        }
+                        false
+                    }
+                    RenderItem::Object(key) => {
+                        let ctx = ObjectCtx {
+                            storage,
+                            target,
+                            design,
+                            typ: storage.get_weave(tag.seq()).typ()
+                        };
+                        let mut scene = Scene::new();
+                        storage.get_object(key).draw(ctx, p, size, &mut scene);
+                        self.draw_scene(&mut stream, &scene, page_tr);
+                        false
+                    }
+                    RenderItem::Row(_) | RenderItem::Empty => false
+                };
+                if text && space {
+                    self.draw_space(&mut stream);
+                }
+            }
+            stream.end_text();
+        }
+        
+        // Create the stream object for this page
+        let stream_object_id = self.file.add_stream("", stream.data.as_bytes());
+
+        // the bleed box may not reach past the media box
+        let bleed_box = bleed_box.intersection(media_box).unwrap_or(media_box);
+
+        // The page object, which describes settings for the whole page, is written at the end
+        let id = self.file.reserve();
+        self.pages.push(PdfPage {
+            id,
+            contents: stream_object_id,
+            media_box: page_tr * media_box,
+            trim_box: page_tr * trim_box,
+            bleed_box: page_tr * bleed_box,
+            annotations: vec![]
+        });
+    }
+
+    /// link annotations over the items of the sequences that are links.
+    /// the rects of the items of a link on the same line are joined.
+    pub fn add_links(&mut self, cache: &Cache, storage: &Storage, target: &Target, root: SequenceId) {
+        let mut links = HashMap::new();
+        collect_links(storage, root, None, &mut links);
+
+        let mut areas: Vec<(u32, RectF, SequenceId)> = vec![];
+        let mut add = |tag: Tag, (page_nr, rect): (u32, RectF)| {
+            if let Some(&link) = links.get(&tag.seq()) {
+                if rect.width() > 0.0 {
+                    areas.push((page_nr, rect, link));
+                }
+            }
+        };
+        for (&tag, &rect) in cache.positions.iter() {
+            add(tag, rect);
+        }
+        for (&tag, word) in cache.word_positions.iter() {
+            match *word {
+                RenderedWord::Full(rect) |
+                RenderedWord::Before(rect, _) |
+                RenderedWord::After(rect, _) => add(tag, rect),
+                RenderedWord::Both(before, after, _) => {
+                    add(tag, before);
+                    add(tag, after);
+                }
+            }
+        }
+        areas.sort_by(|a, b| (a.0, a.1.min_y(), a.1.min_x()).partial_cmp(&(b.0, b.1.min_y(), b.1.min_x())).unwrap());
+
+        let mut joined: Vec<(u32, RectF, SequenceId)> = vec![];
+        for (page_nr, rect, link) in areas {
+            match joined.last_mut() {
+                Some(last) if last.0 == page_nr && last.2 == link && last.1.max_y() > rect.min_y() && rect.max_y() > last.1.min_y() => {
+                    last.1 = last.1.union_rect(rect);
+                }
+                _ => joined.push((page_nr, rect, link))
+            }
+        }
+
+        let flip = page_transform(target);
+        for (page_nr, rect, link) in joined {
+            let link_target = match *storage.get_link(link).unwrap() {
+                Link::Uri(ref uri) => LinkTarget::Uri(uri.clone()),
+                Link::Internal(tag) => match cache.positions.get(&tag) {
+                    Some(&(page_nr, rect)) => LinkTarget::Dest(page_nr as usize, flip * rect.origin()),
+                    None => continue
+                }
+            };
+            let n = self.file.reserve();
+            let page = &mut self.pages[page_nr as usize];
+            page.annotations.push(n);
+
+            let mut annotation = String::new();
+            writeln!(annotation, "<< /Type /Annot").unwrap();
+            writeln!(annotation, "/Subtype /Link").unwrap();
+            writeln!(annotation, "/Rect {}", PdfRect(flip * rect)).unwrap();
+            writeln!(annotation, "/Border [0 0 0]").unwrap();
+            match link_target {
+                LinkTarget::Uri(uri) => writeln!(annotation, "/A << /S /URI /URI {} >>", PdfString(&ascii_uri(&uri))),
+                LinkTarget::Dest(page_nr, p) => writeln!(annotation, "/Dest [{} 0 R /XYZ {} {} null]", self.pages[page_nr].id, p.x(), p.y())
+            }.unwrap();
+            writeln!(annotation, ">>").unwrap();
+            self.file.set(n, annotation);
+        }
+    }
+
+    // the Type0 font with the used glyphs of the font, and the text they show
+    fn add_font(&mut self, n: usize) -> usize {
+        let subset = &self.fonts[n].1;
+        let cff = subset.is_cff();
+        let name = subset.subset_name();
+        let widths = subset.widths();
+        let metrics = subset.metrics();
+        let to_unicode = subset.to_unicode();
+        let file = subset.font_file();
+
+        let pdf_file = &mut self.file;
+        let file_nr = file.map(|data| {
+            let dict = match cff {
+                true => "/Subtype /OpenType".into(),
+                false => format!("/Length1 {}", data.len())
+            };
+            pdf_file.add_stream(&dict, &data)
+        });
+
+        let mut descriptor = String::new();
+        writeln!(descriptor, "<< /Type /FontDescriptor").unwrap();
+        writeln!(descriptor, "/FontName /{}", name).unwrap();
+        if let Some(m) = metrics {
+            writeln!(descriptor, "/Flags {}", m.flags).unwrap();
+            writeln!(descriptor, "/FontBBox [{} {} {} {}]", m.bbox[0], m.bbox[1], m.bbox[2], m.bbox[3]).unwrap();
+            writeln!(descriptor, "/ItalicAngle {}", m.italic_angle).unwrap();
+            writeln!(descriptor, "/Ascent {}", m.ascent).unwrap();
+            writeln!(descriptor, "/Descent {}", m.descent).unwrap();
+            writeln!(descriptor, "/CapHeight {}", m.cap_height).unwrap();
+        }
+        writeln!(descriptor, "/StemV 80").unwrap();
+        if let Some(file_nr) = file_nr {
+            writeln!(descriptor, "/{} {} 0 R", if cff { "FontFile3" } else { "FontFile2" }, file_nr).unwrap();
+        }
+        writeln!(descriptor, ">>").unwrap();
+        let descriptor_nr = self.file.add(descriptor);
+
+        let mut cid_font = String::new();
+        writeln!(cid_font, "<< /Type /Font").unwrap();
+        writeln!(cid_font, "/Subtype /{}", if cff { "CIDFontType0" } else { "CIDFontType2" }).unwrap();
+        writeln!(cid_font, "/BaseFont /{}", name).unwrap();
+        writeln!(cid_font, "/CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >>").unwrap();
+        writeln!(cid_font, "/FontDescriptor {} 0 R", descriptor_nr).unwrap();
+        write!(cid_font, "/W [").unwrap();
+        for (gid, width) in widths {
+            write!(cid_font, " {} [{}]", gid, width).unwrap();
+        }
+        writeln!(cid_font, " ]").unwrap();
+        if !cff {
+            writeln!(cid_font, "/CIDToGIDMap /Identity").unwrap();
+        }
+        writeln!(cid_font, ">>").unwrap();
+        let cid_font_nr = self.file.add(cid_font);
+
+        let to_unicode_nr = self.file.add_stream("", to_unicode.as_bytes());
+
+        let mut font = String::new();
+        writeln!(font, "<< /Type /Font").unwrap();
+        writeln!(font, "/Subtype /Type0").unwrap();
+        match cff {
+            true => writeln!(font, "/BaseFont /{}-Identity-H", name),
+            false => writeln!(font, "/BaseFont /{}", name)
+        }.unwrap();
+        writeln!(font, "/Encoding /Identity-H").unwrap();
+        writeln!(font, "/DescendantFonts [{} 0 R]", cid_font_nr).unwrap();
+        writeln!(font, "/ToUnicode {} 0 R", to_unicode_nr).unwrap();
+        writeln!(font, ">>").unwrap();
+        self.file.add(font)
+    }
+
+    /// bookmarks for the sequences of the types in Design::contents_types
+    pub fn add_outlines(&mut self, cache: &Cache, storage: &Storage, target: &Target) -> Option<usize> {
+        let flip = page_transform(target);
+        let entries: Vec<OutlineEntry> = cache.contents().iter().filter_map(|&(level, key)| {
+            let &(page_nr, rect) = cache.positions.get(&Tag::Start(key))?;
+            let p = flip * rect.origin();
+            Some(OutlineEntry {
+                level,
+                title: Cache::sequence_text(storage, key),
+                page: self.pages[page_nr as usize].id,
+                x: p.x(),
+                y: p.y()
+            })
+        }).collect();
+        add_outlines(&mut self.file, &entries)
+    }
+
+    /// Write the PDF file with the title and author of the document
+    pub fn write_to(&mut self, writer: impl io::Write, metadata: &Metadata, outlines: Option<usize>) -> io::Result<()> {
+        let mut resources = String::from("<< /XObject <<\n");
+        for n in self.defines.values() {
+            writeln!(resources, "/x{} {} 0 R", n, n).unwrap();
+        }
+        writeln!(resources, ">>\n/Font <<").unwrap();
+        for n in 0 .. self.fonts.len() {
+            let font_nr = self.add_font(n);
+            writeln!(resources, "/F{} {} 0 R", n, font_nr).unwrap();
+        }
+        writeln!(resources, ">>\n/Pattern <<").unwrap();
+        for (n, nr) in self.patterns.iter().enumerate() {
+            writeln!(resources, "/P{} {} 0 R", n, nr).unwrap();
+        }
+        writeln!(resources, ">>\n/ColorSpace <<").unwrap();
+        resources.push_str(&self.colors.resources());
+        let mode = self.colors.mode();
+        let file = &mut self.file;
+        if let Some(space) = self.icc_profile.as_ref().and_then(|profile| add_icc_profile(file, profile, mode)) {
+            resources.push_str(&space);
+        }
+        writeln!(resources, ">>\n/ExtGState <<").unwrap();
+        for (n, alpha) in self.alphas.iter().enumerate() {
+            writeln!(resources, "/A{} << /ca {} >>", n, *alpha as f32 / 255.).unwrap();
+        }
+        for (n, nr) in self.masks.iter().enumerate() {
+            writeln!(resources, "/M{} << /SMask << /S /Luminosity /G {} 0 R >> >>", n, nr).unwrap();
+        }
+        writeln!(resources, ">> >>").unwrap();
+        let resources_nr = self.file.add(resources);
+
+        for page in self.pages.iter() {
+            let mut page_object = String::new();
+            writeln!(page_object, "<< /Type /Page").unwrap();
+            writeln!(page_object, "/Parent {} 0 R", self.pages_nr).unwrap();
+            writeln!(page_object, "/MediaBox {}", PdfRect(page.media_box)).unwrap();
+            writeln!(page_object, "/BleedBox {}", PdfRect(page.bleed_box)).unwrap();
+            writeln!(page_object, "/TrimBox {}", PdfRect(page.trim_box)).unwrap();
+            writeln!(page_object, "/Contents {} 0 R", page.contents).unwrap();
+            if page.annotations.len() > 0 {
+                let refs: Vec<String> = page.annotations.iter().map(|n| format!("{} 0 R", n)).collect();
+                writeln!(page_object, "/Annots [{}]", refs.join(" ")).unwrap();
+            }
+            writeln!(page_object, ">>").unwrap();
+            self.file.set(page.id, page_object);
+        }
+
+        let kids: Vec<String> = self.pages.iter().map(|page| format!("{} 0 R", page.id)).collect();
+        let mut pages = String::new();
+        writeln!(pages, "<< /Type /Pages").unwrap();
+        writeln!(pages, "/Resources {} 0 R", resources_nr).unwrap();
+        writeln!(pages, "/Count {}", self.pages.len()).unwrap();
+        writeln!(pages, "/Kids [{}] >>", kids.join(" ")).unwrap();
+        self.file.set(self.pages_nr, pages);
+
+        let info_nr = add_info(&mut self.file, metadata);
+        let metadata_nr = add_xmp_metadata(&mut self.file, metadata);
+
+        let mut catalog = String::new();
+        writeln!(catalog, "<< /Type /Catalog").unwrap();
+        writeln!(catalog, "/Pages {} 0 R", self.pages_nr).unwrap();
+        writeln!(catalog, "/Metadata {} 0 R", metadata_nr).unwrap();
+        if let Some(outlines_nr) = outlines {
+            writeln!(catalog, "/Outlines {} 0 R", outlines_nr).unwrap();
+            writeln!(catalog, "/PageMode /UseOutlines").unwrap();
+        }
+        writeln!(catalog, ">>").unwrap();
+        let catalog_nr = self.file.add(catalog);
+
+        self.file.write_to(writer, catalog_nr, Some(info_nr))
+    }
+}
+
+// the stops of a gradient, covering 0 to 1
+fn gradient_stops(gradient: &Gradient) -> Vec<(f32, ColorU)> {
+    let mut stops: Vec<(f32, ColorU)> = gradient.stops().iter().map(|stop| (stop.offset, stop.color)).collect();
+    match (stops.first().cloned(), stops.last().cloned()) {
+        (Some(first), Some(last)) => {
+            if first.0 > 0.0 {
+                stops.insert(0, (0.0, first.1));
+            }
+            if last.0 < 1.0 {
+                stops.push((1.0, last.1));
+            }
+        }
+        _ => stops = vec![(0.0, ColorU::black()), (1.0, ColorU::black())]
+    }
+    if stops.len() == 1 {
+        stops.push((1.0, stops[0].1));
+    }
+    stops
+}
+
+// the shading dictionary of a gradient, with the stops as colors of the space
+fn shading(gradient: &Gradient, space: &str, color: impl Fn(ColorU) -> String) -> String {
+    let stops = gradient_stops(gradient);
+    let segment = |a: ColorU, b: ColorU| format!("<< /FunctionType 2 /Domain [0 1] /C0 {} /C1 {} /N 1 >>", color(a), color(b));
+    let function = match stops.len() {
+        2 => segment(stops[0].1, stops[1].1),
+        n => {
+            let functions: Vec<String> = stops.windows(2).map(|w| segment(w[0].1, w[1].1)).collect();
+            let bounds: Vec<String> = stops[1 .. n - 1].iter().map(|s| s.0.to_string()).collect();
+            let encode: Vec<&str> = (0 .. n - 1).map(|_| "0 1").collect();
+            format!("<< /FunctionType 3 /Domain [0 1] /Functions [{}] /Bounds [{}] /Encode [{}] >>",
+                functions.join(" "), bounds.join(" "), encode.join(" "))
+        }
+    };
+
+    let line = gradient.line();
+    let (from, to) = (line.from(), line.to());
+    match gradient.radii() {
+        None => format!("<< /ShadingType 2 /ColorSpace {} /Coords [{} {} {} {}] /Function {} /Extend [true true] >>",
+            space, from.x(), from.y(), to.x(), to.y(), function),
+        Some(radii) => format!("<< /ShadingType 3 /ColorSpace {} /Coords [{} {} {} {} {} {}] /Function {} /Extend [true true] >>",
+            space, from.x(), from.y(), radii.x(), to.x(), to.y(), radii.y(), function)
+    }
+}
+
+// the bits of a gradient, to share the objects of equal gradients
+fn gradient_key(gradient: &Gradient) -> Vec<u32> {
+    let line = gradient.line();
+    let mut key = vec![gradient.stops().len() as u32, line.from().x().to_bits(), line.from().y().to_bits(), line.to().x().to_bits(), line.to().y().to_bits()];
+    if let Some(radii) = gradient.radii() {
+        key.extend([radii.x().to_bits(), radii.y().to_bits()].iter());
+    }
+    for stop in gradient.stops() {
+        let c = stop.color;
+        key.push(stop.offset.to_bits());
+        key.push(u32::from_be_bytes([c.r, c.g, c.b, c.a]));
+    }
+    key
+}
+
+// pages are laid out with y down. PDF has y up, flipped around the middle of the media box,
+// so the media box stays where it is.
+fn page_transform(target: &Target) -> Transform2F {
+    let media_box: RectF = target.media_box.into();
+    Transform2F::row_major(1.0, 0.0, 0.0, -1.0, 0.0, media_box.min_y() + media_box.max_y())
+}
+
+// up to rounding
+fn same_matrix(a: Transform2F, b: Transform2F) -> bool {
+    let (a, b) = (a.matrix, b.matrix);
+    [a.m11() - b.m11(), a.m12() - b.m12(), a.m21() - b.m21(), a.m22() - b.m22()].iter().all(|d| d.abs() < 1e-5)
+}
+
+fn transform_key(tr: Transform2F) -> [u32; 6] {
+    let m = tr.matrix;
+    let v = tr.vector;
+    [m.m11().to_bits(), m.m12().to_bits(), m.m21().to_bits(), m.m22().to_bits(), v.x().to_bits(), v.y().to_bits()]
+}
+
+// the link each sequence is part of
+fn collect_links(storage: &Storage, key: SequenceId, link: Option<SequenceId>, links: &mut HashMap<SequenceId, SequenceId>) {
+    let link = storage.get_link(key).map(|_| key).or(link);
+    if let Some(link) = link {
+        links.insert(key, link);
+    }
+    for (_, item) in storage.get_weave(key).items() {
+        if let Item::Sequence(child) = item {
+            collect_links(storage, child, link, links);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::build;
+    use lopdf::{Document, Object, Dictionary, ObjectId, content::Content, xref::XrefEntry};
+
+    // the test document of the demo, as a PDF file
+    fn export(options: PrintOptions) -> Vec<u8> {
+        export_state(&build(), options)
+    }
+    fn export_state(state: &State, options: PrintOptions) -> Vec<u8> {
+        let mut cache = Cache::new();
+        cache.layout(&state.storage, &state.design, &state.target, state.root);
+
+        let mut pdf = Pdf::new(options);
+        for column in cache.columns.as_ref().unwrap().columns() {
+            pdf.render_page(&cache, &state.storage, &state.target, &state.design, column);
+        }
+        pdf.add_links(&cache, &state.storage, &state.target, state.root);
+        let outlines = pdf.add_outlines(&cache, &state.storage, &state.target);
+        let mut data = vec![];
+        pdf.write_to(&mut data, state.storage.metadata(), outlines).unwrap();
+        data
+    }
+
+    fn dict<'a>(doc: &'a Document, parent: &'a Dictionary, key: &[u8]) -> &'a Dictionary {
+        parent.get_deref(key, doc).and_then(Object::as_dict).unwrap()
+    }
+    fn reference(dict: &Dictionary, key: &[u8]) -> Option<ObjectId> {
+        dict.get(key).and_then(Object::as_reference).ok()
+    }
+
+    #[test]
+    fn xref_offsets() {
+        let data = export(PrintOptions::default());
+        let pos = data.windows(10).rposition(|w| w == b"startxref\n").unwrap() + 10;
+        let startxref: usize = std::str::from_utf8(&data[pos ..]).unwrap().lines().next().unwrap().parse().unwrap();
+
+        // the xref table and the trailer are ASCII.
+        // the subsection header is followed by entries of exactly 20 bytes.
+        let xref = std::str::from_utf8(&data[startxref ..]).unwrap();
+        assert!(xref.starts_with("xref\n0 "));
+        let header = xref.lines().nth(1).unwrap();
+        let size: usize = header[2 ..].parse().unwrap();
+        let table = &xref["xref\n".len() + header.len() + 1 ..];
+        let entries: Vec<&str> = (0 .. size).map(|i| &table[20 * i .. 20 * (i + 1)]).collect();
+        assert_eq!(entries[0], "0000000000 65535 f\r\n");
+        for (nr, entry) in entries.iter().enumerate().skip(1) {
+            assert!(entry.ends_with(" 00000 n\r\n"));
+            let offset: usize = entry[.. 10].parse().unwrap();
+            assert!(data[offset ..].starts_with(format!("{} 0 obj\n", nr).as_bytes()), "object {} is not at {}", nr, offset);
+        }
+
+        let doc = Document::load_mem(&data).unwrap();
+        assert_eq!(doc.reference_table.size as usize, size);
+        for (&nr, entry) in doc.reference_table.entries.iter() {
+            if let XrefEntry::Normal { offset, .. } = *entry {
+                assert_eq!(entries[nr as usize][.. 10].parse::<u32>().unwrap(), offset);
+            }
+        }
+    }
+
+    #[test]
+    fn info() {
+        let doc = Document::load_mem(&export(PrintOptions::default())).unwrap();
+        let info = dict(&doc, &doc.trailer, b"Info");
+        assert_eq!(info.get(b"Title").unwrap().as_str().unwrap(), b"Test");
+        assert_eq!(info.get(b"Author").unwrap().as_str().unwrap(), b"grafeia");
+    }
+
+    #[test]
+    fn page_stream() {
+        let doc = Document::load_mem(&export(PrintOptions::default())).unwrap();
+        let (_, &page_id) = doc.get_pages().iter().next().unwrap();
+        let page = doc.get_dictionary(page_id).unwrap();
+        let contents = doc.get_object(reference(page, b"Contents").unwrap()).and_then(Object::as_stream).unwrap();
+        assert_eq!(contents.dict.get(b"Filter").unwrap().as_name().unwrap(), b"FlateDecode");
+
+        let data = contents.decompressed_content().unwrap();
+        let content = Content::decode(&data).unwrap();
+        let operators: Vec<&str> = content.operations.iter().map(|op| op.operator.as_str()).collect();
+        assert_eq!(operators[0], "cm");
+        for op in &["BT", "Tf", "Tm", "TJ", "ET"] {
+            assert!(operators.contains(op), "no {} in the page stream", op);
+        }
+
+        // a text object per line, with the glyphs of a run in one TJ array apart by adjustments
+        let count = |name: &str| operators.iter().filter(|&&op| op == name).count();
+        assert_eq!(count("BT"), count("ET"));
+        assert!(content.operations.iter()
+            .filter(|op| op.operator == "TJ")
+            .flat_map(|op| op.operands[0].as_array().unwrap())
+            .any(|item| matches!(item, Object::Integer(_) | Object::Real(_))));
+    }
+
+    #[test]
+    fn outlines() {
+        let doc = Document::load_mem(&export(PrintOptions::default())).unwrap();
+        let catalog = doc.catalog().unwrap();
+        let root_id = reference(catalog, b"Outlines").unwrap();
+        let root = doc.get_dictionary(root_id).unwrap();
+        assert_eq!(root.get(b"Type").unwrap().as_name().unwrap(), b"Outlines");
+
+        // the top level items are open, the ones below are closed
+        let mut titles = vec![];
+        let mut visible = 0;
+        let mut prev = None;
+        let mut next = reference(root, b"First");
+        while let Some(id) = next {
+            let item = doc.get_dictionary(id).unwrap();
+            assert_eq!(reference(item, b"Parent"), Some(root_id));
+            assert_eq!(reference(item, b"Prev"), prev);
+            titles.push(item.get(b"Title").unwrap().as_str().unwrap().to_vec());
+            visible += 1 + item.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+            prev = Some(id);
+            next = reference(item, b"Next");
+        }
+        assert_eq!(reference(root, b"Last"), prev);
+        assert_eq!(root.get(b"Count").unwrap().as_i64().unwrap(), visible);
+        assert_eq!(titles[0], b"Test");
+    }
+
+    #[test]
+    fn text() {
+        let data = export(PrintOptions::default());
+        let text = pdf_extract::extract_text_from_mem(&data).unwrap();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(text.contains("The distilled spirit of Garamond"), "{}", text);
+        assert!(text.contains("The ffine fish"), "{}", text);
+
+        // the space between words is in the text of the font, not just a gap
+        let doc = Document::load_mem(&data).unwrap();
+        let pages = dict(&doc, doc.catalog().unwrap(), b"Pages");
+        let fonts = dict(&doc, dict(&doc, pages, b"Resources"), b"Font");
+        let font = doc.get_dictionary(reference(fonts, b"F0").unwrap()).unwrap();
+        let to_unicode = doc.get_object(reference(font, b"ToUnicode").unwrap()).and_then(Object::as_stream).unwrap();
+        let cmap = String::from_utf8(to_unicode.decompressed_content().unwrap()).unwrap();
+        assert!(cmap.contains("> <0020>\n"), "{}", cmap);
+    }
+
+    #[test]
+    fn icc_profile() {
+        let profile = || Some(b"icc profile".to_vec());
+        let data = export(PrintOptions { color_mode: ColorMode::Cmyk, icc_profile: profile(), .. PrintOptions::default() });
+        let doc = Document::load_mem(&data).unwrap();
+        // the file claims no conformance to a standard
+        assert!(!doc.catalog().unwrap().has(b"OutputIntents"));
+        let pages = dict(&doc, doc.catalog().unwrap(), b"Pages");
+        let spaces = dict(&doc, dict(&doc, pages, b"Resources"), b"ColorSpace");
+        let space = spaces.get(b"DefaultCMYK").and_then(Object::as_array).unwrap();
+        assert_eq!(space[0].as_name().unwrap(), b"ICCBased");
+        let stream = doc.get_object(space[1].as_reference().unwrap()).and_then(Object::as_stream).unwrap();
+        assert_eq!(stream.dict.get(b"N").and_then(Object::as_i64).unwrap(), 4);
+        assert_eq!(stream.decompressed_content().unwrap(), b"icc profile");
+
+        // the text is filled in CMYK as well
+        let (_, &page_id) = doc.get_pages().iter().next().unwrap();
+        let content = Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+        let fill = content.operations.iter().find(|op| ["g", "rg", "k"].contains(&op.operator.as_str())).unwrap();
+        assert_eq!(fill.operator, "k");
+
+        let page = doc.get_dictionary(page_id).unwrap();
+        let rect = |key: &[u8]| -> Vec<f32> {
+            page.get(key).and_then(Object::as_array).unwrap().iter().map(|v| v.as_float().unwrap()).collect()
+        };
+        let (media, bleed) = (rect(b"MediaBox"), rect(b"BleedBox"));
+        assert!(bleed[0] >= media[0] && bleed[1] >= media[1] && bleed[2] <= media[2] && bleed[3] <= media[3]);
+
+        // profiles are for printing conditions, which RGB files have none of
+        let data = export(PrintOptions { icc_profile: profile(), .. PrintOptions::default() });
+        let doc = Document::load_mem(&data).unwrap();
+        let pages = dict(&doc, doc.catalog().unwrap(), b"Pages");
+        assert!(!dict(&doc, dict(&doc, pages, b"Resources"), b"ColorSpace").has(b"DefaultRGB"));
+    }
+
+    #[test]
+    fn print_marks_off_center() {
+        let mut state = build();
+        // nearer to the top left of the media box
+        state.target.trim_box = Rect { left: Length::mm(2.), width: Length::mm(150.), top: Length::mm(5.), height: Length::mm(200.) };
+        state.target.bleed_box = Rect { left: Length::mm(-1.), width: Length::mm(156.), top: Length::mm(2.), height: Length::mm(206.) };
+        let data = export_state(&state, PrintOptions { marks: true, .. PrintOptions::default() });
+        let doc = Document::load_mem(&data).unwrap();
+        let (_, &page_id) = doc.get_pages().iter().next().unwrap();
+        let page = doc.get_dictionary(page_id).unwrap();
+        let rect = |key: &[u8]| -> Vec<f32> {
+            page.get(key).and_then(Object::as_array).unwrap().iter().map(|v| v.as_float().unwrap()).collect()
+        };
+        let (trim, bleed) = (rect(b"TrimBox"), rect(b"BleedBox"));
+
+        // the marks are in the space of the page transform
+        let content = Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+        let ops = &content.operations;
+        assert_eq!(ops[0].operator, "cm");
+        let cm: Vec<f32> = ops[0].operands.iter().map(|v| v.as_float().unwrap()).collect();
+        assert_eq!((cm[1], cm[2]), (0.0, 0.0));
+        let point = |op: &lopdf::content::Operation| {
+            let v: Vec<f32> = op.operands.iter().map(|v| v.as_float().unwrap()).collect();
+            (cm[0] * v[0] + cm[4], cm[3] * v[1] + cm[5])
+        };
+        let lines: Vec<_> = ops.windows(2)
+            .filter(|w| w[0].operator == "m" && w[1].operator == "l")
+            .map(|w| (point(&w[0]), point(&w[1])))
+            .collect();
+        let near = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        for &y in [trim[1], trim[3]].iter() {
+            assert!(lines.iter().any(|&(a, b)| near(a.1, y) && near(b.1, y)), "no crop mark at y = {}", y);
+        }
+        for &x in [trim[0], trim[2]].iter() {
+            assert!(lines.iter().any(|&(a, b)| near(a.0, x) && near(b.0, x)), "no crop mark at x = {}", x);
+        }
+        for &(x, y) in lines.iter().flat_map(|(a, b)| vec![a, b]) {
+            assert!(!(x > bleed[0] && x < bleed[2] && y > bleed[1] && y < bleed[3]), "mark at {} {} in the bleed box", x, y);
+        }
+    }
+
+    #[test]
+    fn text_color() {
+        let mut state = build();
+        let types: Vec<(TypeId, TypeDesign)> = state.design.items().map(|(typ, design)| (typ, design.clone())).collect();
+        for (typ, design) in types {
+            state.design.set_type(typ, TypeDesign { color: Color::Cmyk(0.0, 1.0, 1.0, 0.0), .. design });
+        }
+        let data = export_state(&state, PrintOptions { color_mode: ColorMode::Cmyk, .. PrintOptions::default() });
+        let doc = Document::load_mem(&data).unwrap();
+        let (_, &page_id) = doc.get_pages().iter().next().unwrap();
+        let content = Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+        let fills: Vec<Vec<f32>> = content.operations.iter()
+            .filter(|op| op.operator == "k")
+            .map(|op| op.operands.iter().map(|v| v.as_float().unwrap()).collect())
+            .collect();
+        assert!(fills.contains(&vec![0.0, 1.0, 1.0, 0.0]), "{:?}", fills);
+    }
+}
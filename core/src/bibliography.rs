@@ -0,0 +1,677 @@
+use std::error::Error;
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Name {
+    pub family: String,
+    pub given:  Option<String>,
+}
+
+/// An entry of the bibliography, found by its key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BibEntry {
+    pub key:       String,
+
+    // "article", "book", …
+    pub kind:      String,
+
+    pub authors:   Vec<Name>,
+    pub title:     String,
+    pub year:      Option<i32>,
+
+    // the journal or book it appeared in
+    pub container: Option<String>,
+    pub publisher: Option<String>,
+    pub pages:     Option<String>,
+}
+impl BibEntry {
+    /// "Knuth", "Knuth and Plass", "Knuth et al."
+    pub fn short_authors(&self) -> String {
+        match self.authors.as_slice() {
+            [] => self.title.clone(),
+            [a] => a.family.clone(),
+            [a, b] => format!("{} and {}", a.family, b.family),
+            [a, ..] => format!("{} et al.", a.family)
+        }
+    }
+
+    /// "Knuth, Donald E. and Plass, Michael F."
+    pub fn full_authors(&self) -> String {
+        let names: Vec<String> = self.authors.iter().map(|name| match name.given {
+            Some(ref given) => format!("{}, {}", name.family, given),
+            None => name.family.clone()
+        }).collect();
+        match names.split_last() {
+            None => String::new(),
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last)
+        }
+    }
+
+    /// "1984", "1984a" or "n.d."
+    pub fn year_text(&self, suffix: Option<char>) -> String {
+        let mut text = match self.year {
+            Some(year) => year.to_string(),
+            None => "n.d.".into()
+        };
+        text.extend(suffix);
+        text
+    }
+
+    /// the text of the entry in the list of references
+    pub fn reference_text(&self, suffix: Option<char>) -> String {
+        let mut text = String::new();
+        if self.authors.len() > 0 {
+            text.push_str(&self.full_authors());
+            text.push(' ');
+        }
+        text.push_str(&format!("({}). ", self.year_text(suffix)));
+        text.push_str(&self.title);
+        text.push('.');
+        if let Some(ref container) = self.container {
+            text.push(' ');
+            text.push_str(container);
+            if let Some(ref pages) = self.pages {
+                text.push_str(", ");
+                text.push_str(pages);
+            }
+            text.push('.');
+        }
+        if let Some(ref publisher) = self.publisher {
+            text.push(' ');
+            text.push_str(publisher);
+            text.push('.');
+        }
+        text
+    }
+
+    /// sorted by the first author, then by year and title
+    pub fn sort_text(&self) -> String {
+        let authors: Vec<&str> = self.authors.iter().map(|name| name.family.as_str()).collect();
+        format!("{} {:04} {}", authors.join(" "), self.year.unwrap_or(0), self.title)
+    }
+}
+
+/// Cites entries of the bibliography
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Citation {
+    pub keys:    Vec<String>,
+
+    // "p. 12"
+    pub locator: Option<String>,
+}
+impl Citation {
+    pub fn new(key: &str) -> Citation {
+        Citation {
+            keys: vec![key.into()],
+            locator: None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CitationStyle {
+    // "(Knuth 1984, p. 12)". the list is sorted by author.
+    AuthorYear,
+
+    // "[1, p. 12]". entries are numbered in the order they are first cited.
+    Numeric,
+}
+impl Default for CitationStyle {
+    fn default() -> Self {
+        CitationStyle::AuthorYear
+    }
+}
+impl CitationStyle {
+    /// the entries with their number and the letter after their year.
+    /// missing entries are shown as "??" or "?".
+    pub fn cite(self, entries: &[Option<(&BibEntry, usize, Option<char>)>], locator: Option<&str>) -> String {
+        let parts: Vec<String> = entries.iter().map(|entry| match (self, entry) {
+            (CitationStyle::AuthorYear, Some((entry, _, suffix))) => format!("{} {}", entry.short_authors(), entry.year_text(*suffix)),
+            (CitationStyle::AuthorYear, None) => "??".into(),
+            (CitationStyle::Numeric, Some((_, number, _))) => number.to_string(),
+            (CitationStyle::Numeric, None) => "?".into()
+        }).collect();
+        let separator = match self {
+            CitationStyle::AuthorYear => "; ",
+            CitationStyle::Numeric => ", "
+        };
+        let mut text = parts.join(separator);
+        if let Some(locator) = locator {
+            text.push_str(", ");
+            text.push_str(locator);
+        }
+        match self {
+            CitationStyle::AuthorYear => format!("({})", text),
+            CitationStyle::Numeric => format!("[{}]", text)
+        }
+    }
+
+    /// in front of the entry in the list
+    pub fn label(self, number: usize) -> Option<String> {
+        match self {
+            CitationStyle::AuthorYear => None,
+            CitationStyle::Numeric => Some(format!("[{}]", number))
+        }
+    }
+}
+
+/// letters after the year that tell apart entries cited with the same authors and year
+/// ("Knuth 1984a", "Knuth 1984b"), in the order of their titles. by the key of the entry.
+pub fn year_suffixes(entries: &[&BibEntry]) -> HashMap<String, char> {
+    let mut groups: HashMap<(String, Option<i32>), Vec<&BibEntry>> = HashMap::new();
+    for &entry in entries {
+        groups.entry((entry.short_authors(), entry.year)).or_default().push(entry);
+    }
+    let mut suffixes = HashMap::new();
+    for (_, mut group) in groups {
+        if group.len() < 2 {
+            continue;
+        }
+        group.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
+        for (i, entry) in group.iter().enumerate() {
+            suffixes.insert(entry.key.clone(), (b'a' + (i % 26) as u8) as char);
+        }
+    }
+    suffixes
+}
+
+/// the entries of a .bib file.
+/// @string definitions are expanded, @comment and @preamble are skipped.
+pub fn parse_bibtex(input: &str) -> Result<Vec<BibEntry>, Box<dyn Error>> {
+    let mut parser = BibParser {
+        chars: input.chars().collect(),
+        pos: 0,
+        strings: vec![]
+    };
+    let mut entries = vec![];
+    while parser.skip_to('@') {
+        let kind = parser.ident().to_lowercase();
+        parser.skip_whitespace();
+        let close = match parser.next() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => return Err(format!("expected {{ after @{}", kind).into())
+        };
+        match kind.as_str() {
+            "comment" | "preamble" => match close {
+                '}' => {
+                    parser.pos -= 1;
+                    parser.skip_group()?;
+                }
+                _ => {
+                    parser.skip_to(close);
+                }
+            },
+            "string" => {
+                let (name, value) = parser.field()?;
+                parser.strings.push((name, value));
+                parser.skip_whitespace();
+                parser.expect(close)?;
+            }
+            _ => {
+                parser.skip_whitespace();
+                let key = parser.until(&[',', close]).trim().to_owned();
+                let mut fields = vec![];
+                loop {
+                    parser.skip_whitespace();
+                    match parser.next() {
+                        Some(',') => {}
+                        Some(c) if c == close => break,
+                        _ => return Err(format!("unterminated entry {}", key).into())
+                    }
+                    parser.skip_whitespace();
+                    if parser.peek() == Some(close) {
+                        parser.pos += 1;
+                        break;
+                    }
+                    fields.push(parser.field()?);
+                }
+                entries.push(bibtex_entry(key, kind, fields));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn bibtex_entry(key: String, kind: String, fields: Vec<(String, String)>) -> BibEntry {
+    let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+    let text = |name: &str| field(name).map(latex_to_text);
+    let authors = field("author").or(field("editor"))
+        .map(|names| split_names(names).into_iter().map(|name| bibtex_name(&name)).collect())
+        .unwrap_or_default();
+    BibEntry {
+        key,
+        kind,
+        authors,
+        title: text("title").unwrap_or_default(),
+        year: field("year").and_then(|year| year.trim().parse().ok()),
+        container: text("journal").or(text("booktitle")),
+        publisher: text("publisher"),
+        pages: text("pages"),
+    }
+}
+
+struct BibParser {
+    chars:   Vec<char>,
+    pos:     usize,
+    strings: Vec<(String, String)>,
+}
+impl BibParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+    fn expect(&mut self, c: char) -> Result<(), Box<dyn Error>> {
+        match self.next() {
+            Some(found) if found == c => Ok(()),
+            found => Err(format!("expected {:?}, found {:?}", c, found).into())
+        }
+    }
+    // true if found
+    fn skip_to(&mut self, c: char) -> bool {
+        while let Some(next) = self.next() {
+            if next == c {
+                return true;
+            }
+        }
+        false
+    }
+    fn skip_whitespace(&mut self) {
+        while self.peek().map(char::is_whitespace) == Some(true) {
+            self.pos += 1;
+        }
+    }
+    fn ident(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().map(|c| c.is_alphanumeric() || "_-:.".contains(c)) == Some(true) {
+            self.pos += 1;
+        }
+        self.chars[start .. self.pos].iter().collect()
+    }
+    fn until(&mut self, end: &[char]) -> String {
+        let start = self.pos;
+        while self.peek().map(|c| !end.contains(&c)) == Some(true) {
+            self.pos += 1;
+        }
+        self.chars[start .. self.pos].iter().collect()
+    }
+    // the content of a {group} with its inner braces
+    fn skip_group(&mut self) -> Result<String, Box<dyn Error>> {
+        self.expect('{')?;
+        let start = self.pos;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some('{') => depth += 1,
+                Some('}') => depth -= 1,
+                Some(_) => {}
+                None => return Err("unbalanced braces".into())
+            }
+        }
+        Ok(self.chars[start .. self.pos - 1].iter().collect())
+    }
+    // name = "value" # {value} # string
+    fn field(&mut self) -> Result<(String, String), Box<dyn Error>> {
+        self.skip_whitespace();
+        let name = self.ident().to_lowercase();
+        self.skip_whitespace();
+        self.expect('=')?;
+        let mut value = String::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('{') => value.push_str(&self.skip_group()?),
+                Some('"') => {
+                    self.pos += 1;
+                    let start = self.pos;
+                    let mut depth = 0;
+                    loop {
+                        match self.next() {
+                            Some('{') => depth += 1,
+                            Some('}') => depth -= 1,
+                            Some('"') if depth == 0 => break,
+                            Some(_) => {}
+                            None => return Err(format!("unterminated field {}", name).into())
+                        }
+                    }
+                    value.extend(&self.chars[start .. self.pos - 1]);
+                }
+                Some(_) => {
+                    let word = self.ident();
+                    if word.is_empty() {
+                        return Err(format!("missing value of {}", name).into());
+                    }
+                    match self.strings.iter().find(|(n, _)| n.eq_ignore_ascii_case(&word)) {
+                        Some((_, s)) => value.push_str(s),
+                        None => value.push_str(&word)
+                    }
+                }
+                None => return Err(format!("missing value of {}", name).into())
+            }
+            self.skip_whitespace();
+            if self.peek() == Some('#') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        Ok((name, value))
+    }
+}
+
+// "A and B and {C and D}"
+fn split_names(names: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    for word in names.split_whitespace() {
+        if word == "and" && depth == 0 {
+            parts.push(std::mem::replace(&mut current, String::new()));
+            continue;
+        }
+        depth += word.matches('{').count() as i32 - word.matches('}').count() as i32;
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    parts.push(current);
+    parts.retain(|name| !name.is_empty());
+    parts
+}
+
+// "Knuth, Donald E." or "Donald E. Knuth". a braced name is kept as a whole.
+fn bibtex_name(name: &str) -> Name {
+    if let Some(idx) = name.find(',') {
+        return Name {
+            family: latex_to_text(name[.. idx].trim()),
+            given: Some(latex_to_text(name[idx + 1 ..].trim())).filter(|g| !g.is_empty())
+        };
+    }
+    if name.starts_with('{') && name.ends_with('}') {
+        return Name { family: latex_to_text(name), given: None };
+    }
+    match name.rfind(' ') {
+        Some(idx) => Name {
+            family: latex_to_text(&name[idx + 1 ..]),
+            given: Some(latex_to_text(&name[.. idx]))
+        },
+        None => Name { family: latex_to_text(name), given: None }
+    }
+}
+
+/// removes braces and replaces accents and dashes written in LaTeX
+fn latex_to_text(input: &str) -> String {
+    let mut out = String::new();
+    let mut chars = input.chars().peekable();
+    let mut accent = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => continue,
+            '~' => out.push('\u{a0}'),
+            '\\' => match chars.peek().cloned() {
+                Some(a) if "\"'`^~=.".contains(a) => {
+                    chars.next();
+                    accent = Some(match a {
+                        '"' => '\u{308}',
+                        '\'' => '\u{301}',
+                        '`' => '\u{300}',
+                        '^' => '\u{302}',
+                        '~' => '\u{303}',
+                        '=' => '\u{304}',
+                        _ => '\u{307}',
+                    });
+                    continue;
+                }
+                Some(a) if a.is_alphabetic() => {
+                    // the command is dropped, its argument is kept
+                    let mut command = String::new();
+                    while let Some(&c) = chars.peek().filter(|c| c.is_alphabetic()) {
+                        command.push(c);
+                        chars.next();
+                    }
+                    match command.as_str() {
+                        "TeX" | "LaTeX" => out.push_str(&command),
+                        "ss" => out.push('ß'),
+                        "o" => out.push('ø'),
+                        "O" => out.push('Ø'),
+                        "ae" => out.push('æ'),
+                        "l" => out.push('ł'),
+                        "i" => out.push('ı'),
+                        "c" => accent = Some('\u{327}'),
+                        "v" => accent = Some('\u{30c}'),
+                        "u" => accent = Some('\u{306}'),
+                        "H" => accent = Some('\u{30b}'),
+                        _ => {}
+                    }
+                    while chars.peek() == Some(&' ') {
+                        chars.next();
+                    }
+                    continue;
+                }
+                Some(a) => {
+                    chars.next();
+                    out.push(a);
+                }
+                None => {}
+            },
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                if chars.peek() == Some(&'-') {
+                    chars.next();
+                    out.push('—');
+                } else {
+                    out.push('–');
+                }
+            }
+            c => out.push(c)
+        }
+        // the accent goes on the letter that follows it
+        if let Some(mark) = accent.take() {
+            out.push(mark);
+        }
+    }
+    out.nfc().collect()
+}
+
+#[derive(Deserialize)]
+struct CslItem {
+    id: serde_json::Value,
+    #[serde(rename="type", default)]
+    kind: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    author: Vec<CslName>,
+    #[serde(default)]
+    editor: Vec<CslName>,
+    issued: Option<CslDate>,
+    #[serde(rename="container-title")]
+    container_title: Option<String>,
+    publisher: Option<String>,
+    page: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CslName {
+    family: Option<String>,
+    given: Option<String>,
+    literal: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CslDate {
+    #[serde(rename="date-parts", default)]
+    date_parts: Vec<Vec<serde_json::Value>>,
+}
+
+/// the entries of a CSL-JSON array
+pub fn parse_csl_json(input: &str) -> Result<Vec<BibEntry>, Box<dyn Error>> {
+    let items: Vec<CslItem> = serde_json::from_str(input)?;
+    Ok(items.into_iter().map(|item| {
+        // the editors stand in for missing authors
+        let names = match item.author.len() {
+            0 => item.editor,
+            _ => item.author
+        };
+        let year = item.issued
+            .and_then(|date| date.date_parts.into_iter().next())
+            .and_then(|parts| parts.into_iter().next())
+            .and_then(|year| match year {
+                serde_json::Value::Number(n) => n.as_i64().map(|n| n as i32),
+                serde_json::Value::String(s) => s.trim().parse().ok(),
+                _ => None
+            });
+        BibEntry {
+            key: match item.id {
+                serde_json::Value::String(s) => s,
+                id => id.to_string()
+            },
+            kind: item.kind,
+            authors: names.into_iter().filter_map(|name| match name {
+                CslName { family: Some(family), given, .. } => Some(Name { family, given }),
+                CslName { literal: Some(literal), .. } => Some(Name { family: literal, given: None }),
+                _ => None
+            }).collect(),
+            title: item.title,
+            year,
+            container: item.container_title,
+            publisher: item.publisher,
+            pages: item.page,
+        }
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIB: &str = r#"
+@string{tug = "TeX Users Group"}
+@comment{ not an entry }
+@book{knuth1984,
+  author    = "Knuth, Donald E.",
+  title     = {The {\TeX}book},
+  publisher = tug # " Press",
+  year      = 1984
+}
+@article{knuth1981,
+  author  = "Donald E. Knuth and Michael F. Plass",
+  title   = "Breaking Paragraphs {"into"} Lines",
+  journal = {Software: Practice and Experience},
+  year    = "1981",
+  pages   = "1119--1184",
+}
+@misc(goedel,
+  author = {G{\"o}del, Kurt and Erd\H{o}s, P{\'a}l and {Stra{\ss}e und Partner}},
+  title  = {Caf\'e}
+)
+"#;
+
+    fn entry<'a>(entries: &'a [BibEntry], key: &str) -> &'a BibEntry {
+        entries.iter().find(|e| e.key == key).unwrap()
+    }
+
+    #[test]
+    fn bibtex_strings_and_concatenation() {
+        let entries = parse_bibtex(BIB).unwrap();
+        assert_eq!(entries.len(), 3);
+        let book = entry(&entries, "knuth1984");
+        assert_eq!(book.kind, "book");
+        assert_eq!(book.title, "The TeXbook");
+        assert_eq!(book.publisher.as_deref(), Some("TeX Users Group Press"));
+        assert_eq!(book.year, Some(1984));
+    }
+
+    #[test]
+    fn bibtex_braces_inside_quotes() {
+        let entries = parse_bibtex(BIB).unwrap();
+        let article = entry(&entries, "knuth1981");
+        assert_eq!(article.title, "Breaking Paragraphs \"into\" Lines");
+        assert_eq!(article.container.as_deref(), Some("Software: Practice and Experience"));
+        assert_eq!(article.pages.as_deref(), Some("1119–1184"));
+    }
+
+    #[test]
+    fn bibtex_name_forms() {
+        let entries = parse_bibtex(BIB).unwrap();
+        let knuth = Name { family: "Knuth".into(), given: Some("Donald E.".into()) };
+        assert_eq!(entry(&entries, "knuth1984").authors, vec![knuth.clone()]);
+        assert_eq!(entry(&entries, "knuth1981").authors, vec![
+            knuth,
+            Name { family: "Plass".into(), given: Some("Michael F.".into()) }
+        ]);
+        assert_eq!(entry(&entries, "knuth1981").short_authors(), "Knuth and Plass");
+    }
+
+    #[test]
+    fn bibtex_accents() {
+        let entries = parse_bibtex(BIB).unwrap();
+        let misc = entry(&entries, "goedel");
+        let families: Vec<&str> = misc.authors.iter().map(|n| n.family.as_str()).collect();
+        assert_eq!(families, ["Gödel", "Erdős", "Straße und Partner"]);
+        assert_eq!(misc.authors[1].given.as_deref(), Some("Pál"));
+        assert_eq!(misc.title, "Café");
+        assert_eq!(misc.year, None);
+    }
+
+    #[test]
+    fn csl_json() {
+        let entries = parse_csl_json(r#"[
+            {
+                "id": "plass1981",
+                "type": "article-journal",
+                "title": "Optimal Pagination Techniques",
+                "author": [{"family": "Plass", "given": "Michael F."}, {"literal": "Xerox PARC"}],
+                "issued": {"date-parts": [[1981, 6]]},
+                "container-title": "Stanford",
+                "page": "1-10"
+            },
+            {
+                "id": 42,
+                "editor": [{"family": "Knuth"}],
+                "issued": {"date-parts": [["1986"]]}
+            }
+        ]"#).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, "plass1981");
+        assert_eq!(entries[0].kind, "article-journal");
+        assert_eq!(entries[0].authors, vec![
+            Name { family: "Plass".into(), given: Some("Michael F.".into()) },
+            Name { family: "Xerox PARC".into(), given: None }
+        ]);
+        assert_eq!(entries[0].year, Some(1981));
+        assert_eq!(entries[0].container.as_deref(), Some("Stanford"));
+        assert_eq!(entries[1].key, "42");
+        assert_eq!(entries[1].authors, vec![Name { family: "Knuth".into(), given: None }]);
+        assert_eq!(entries[1].year, Some(1986));
+    }
+
+    #[test]
+    fn author_year_disambiguation() {
+        let entries = parse_bibtex(r#"
+            @book{texbook, author = "Donald E. Knuth", title = "The TeXbook", year = 1984}
+            @article{literate, author = "Donald E. Knuth", title = "Literate Programming", year = 1984}
+            @book{metafont, author = "Donald E. Knuth", title = "The METAFONTbook", year = 1986}
+        "#).unwrap();
+        let cited: Vec<&BibEntry> = entries.iter().collect();
+        let suffixes = year_suffixes(&cited);
+        assert_eq!(suffixes.get("literate"), Some(&'a'));
+        assert_eq!(suffixes.get("texbook"), Some(&'b'));
+        assert_eq!(suffixes.get("metafont"), None);
+
+        let texbook = entry(&entries, "texbook");
+        let metafont = entry(&entries, "metafont");
+        let text = CitationStyle::AuthorYear.cite(&[Some((texbook, 1, Some('b'))), Some((metafont, 3, None))], Some("p. 94"));
+        assert_eq!(text, "(Knuth 1984b; Knuth 1986, p. 94)");
+        assert!(texbook.reference_text(Some('b')).starts_with("Knuth, Donald E. (1984b). The TeXbook."));
+    }
+}
@@ -1,10 +1,15 @@
-use crate::{Storage, TypeId, Item, Type, Document, Object, Symbol};
+use crate::{Storage, TypeId, DictId, Item, Type, Document, Object, Symbol, Term, IndexMark, MarkRange, Citation};
 
 pub struct ContentBuilder {
     document: Document,
     document_key: TypeId,
     para_key: TypeId,
     chapter_key: TypeId,
+    figure_key: TypeId,
+    caption_key: TypeId,
+    contents_key: TypeId,
+    index_key: TypeId,
+    bibliography_key: TypeId,
     items: Vec<Item>
 }
 impl ContentBuilder {
@@ -27,6 +32,26 @@ impl ContentBuilder {
                 "chapter",
                 Type::new("A Chapter")
             ),
+            figure_key: document.create_type(
+                "figure",
+                Type::new("A Figure")
+            ),
+            caption_key: document.create_type(
+                "caption",
+                Type::new("The Caption of a Figure")
+            ),
+            contents_key: document.create_type(
+                "contents",
+                Type::new("Table of Contents")
+            ),
+            index_key: document.create_type(
+                "index",
+                Type::new("Index of Terms")
+            ),
+            bibliography_key: document.create_type(
+                "bibliography",
+                Type::new("List of the cited Works")
+            ),
             document,
             items: vec![]
         }
@@ -35,6 +60,7 @@ impl ContentBuilder {
         TextBuilder {
             typ: self.chapter_key,
             nodes: vec![],
+            language: None,
             parent: self
         }
     }
@@ -42,6 +68,7 @@ impl ContentBuilder {
         TextBuilder {
             typ: self.para_key,
             nodes: vec![],
+            language: None,
             parent: self
         }
     }
@@ -50,6 +77,32 @@ impl ContentBuilder {
         self.items.push(Item::Object(key));
         self
     }
+    pub fn figure(mut self, object: Object, caption: &str) -> Self {
+        let object = self.document.create_object(object);
+        let caption: Vec<Item> = self.document.create_text(caption).collect();
+        let caption = self.document.creat_seq_with_items(self.caption_key, caption);
+        let figure = self.document.creat_seq_with_items(self.figure_key, vec![Item::Object(object), Item::Sequence(caption)]);
+        self.items.push(Item::Sequence(figure));
+        self
+    }
+    /// a table of contents. the entries are generated.
+    pub fn contents(mut self) -> Self {
+        let contents = self.document.creat_seq_with_items(self.contents_key, vec![]);
+        self.items.push(Item::Sequence(contents));
+        self
+    }
+    /// the index of all terms marked in the document
+    pub fn index(mut self) -> Self {
+        let index = self.document.creat_seq_with_items(self.index_key, vec![]);
+        self.items.push(Item::Sequence(index));
+        self
+    }
+    /// the list of the cited entries of the bibliography
+    pub fn bibliography(mut self) -> Self {
+        let bibliography = self.document.creat_seq_with_items(self.bibliography_key, vec![]);
+        self.items.push(Item::Sequence(bibliography));
+        self
+    }
     pub fn finish(mut self) -> Document {
         let root = self.document.creat_seq_with_items(self.document_key, self.items.into_iter());
         self.document.set_root(root);
@@ -60,7 +113,8 @@ impl ContentBuilder {
 pub struct TextBuilder {
     parent: ContentBuilder,
     typ:    TypeId,
-    nodes:  Vec<Item>
+    nodes:  Vec<Item>,
+    language: Option<DictId>
 }
 impl TextBuilder {
     pub fn word(mut self, w: &str) -> Self {
@@ -83,9 +137,41 @@ impl TextBuilder {
         self.nodes.push(Item::Object(key));
         self
     }
+    /// lists the page of the following item in the index
+    pub fn index_mark(self, term: Term) -> Self {
+        self.mark(term, MarkRange::Point)
+    }
+    /// starts a range of pages for the term. it ends at the following
+    /// `index_end` with the same text and sub-entry.
+    pub fn index_begin(self, term: Term) -> Self {
+        self.mark(term, MarkRange::Begin)
+    }
+    /// ends the range of pages started by `index_begin`
+    pub fn index_end(self, term: Term) -> Self {
+        self.mark(term, MarkRange::End)
+    }
+    fn mark(mut self, term: Term, range: MarkRange) -> Self {
+        let term = self.parent.document.add_term(term);
+        self.nodes.push(Item::IndexMark(IndexMark { term, range }));
+        self
+    }
+    pub fn cite(mut self, citation: Citation) -> Self {
+        let key = self.parent.document.create_citation(citation);
+        self.nodes.push(Item::Citation(key));
+        self
+    }
+
+    /// the text is in the language of the dictionary instead of the one of its type
+    pub fn language(mut self, dict: DictId) -> Self {
+        self.language = Some(dict);
+        self
+    }
 
     pub fn finish(mut self) -> ContentBuilder {
         let key = self.parent.document.creat_seq_with_items(self.typ, self.nodes.into_iter());
+        if let Some(dict) = self.language {
+            self.parent.document.set_language(key, dict);
+        }
         self.parent.items.push(Item::Sequence(key));
         self.parent
     }
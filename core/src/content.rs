@@ -20,7 +20,8 @@ Design
 
 use std::collections::{HashMap};
 use std::fmt::{self, Debug};
-use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+use std::borrow::{Borrow, Cow};
 use std::io;
 use std::path::Path;
 use std::ops::{Deref};
@@ -33,7 +34,7 @@ use hyphenation::{
     Hyphenator
 };
 
-use crate::layout::FlexMeasure;
+use crate::layout::{FlexMeasure, HyphenPenalty};
 use crate::{*};
 
 // possible design and information what it means
@@ -74,6 +75,13 @@ impl FontFace {
         let inner = FontFaceInner::from(std::fs::read(path)?);
         Ok(FontFace(Arc::new(inner)))
     }
+    pub fn data(&self) -> &[u8] {
+        &self.0.data
+    }
+    /// the face as read by the shaper. None if it is not an OpenType font.
+    pub fn shaper(&self) -> Option<rustybuzz::Face> {
+        rustybuzz::Face::from_slice(&self.0.data, 0)
+    }
 }
 impl Debug for FontFace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -95,10 +103,17 @@ impl Deref for FontFace {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Direction {
     LeftToRight,
     RightToLeft
 }
+impl Default for Direction {
+    fn default() -> Self {
+        Direction::LeftToRight
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 #[derive(Debug, Clone)]
@@ -116,6 +131,25 @@ enum Breaks<'a> {
     Extended(Vec<(usize, Option<&'a Subregion>)>)
 }
 impl<'a> Options<'a> {
+    /// break points at the given byte indices of `word`
+    pub fn manual(word: &'a str, breaks: Vec<usize>) -> Options<'a> {
+        Options {
+            word,
+            breaks: Breaks::Standard(breaks)
+        }
+    }
+    /// remove break points that leave less than `left_min` chars before
+    /// or less than `right_min` chars after the hyphen
+    pub fn limit(&mut self, left_min: usize, right_min: usize) {
+        let word = self.word;
+        let allowed = |index: usize| {
+            word[.. index].chars().count() >= left_min && word[index ..].chars().count() >= right_min
+        };
+        match self.breaks {
+            Breaks::Standard(ref mut s) => s.retain(|&index| allowed(index)),
+            Breaks::Extended(ref mut s) => s.retain(|&(index, _)| allowed(index))
+        }
+    }
     pub fn len(&self) -> usize {
         match self.breaks {
             Breaks::Standard(ref s) => s.len(),
@@ -150,7 +184,14 @@ impl<'a> Options<'a> {
     }
 }
 impl Dictionary {
-    pub fn hyphenate<'a>(&'a self, word: &'a str) -> Options<'a> {
+    /// break points of the word. an entry in `exceptions` replaces the dictionary.
+    pub fn hyphenate<'a>(&'a self, word: &'a str, exceptions: &Exceptions) -> Options<'a> {
+        if let Some(breaks) = exceptions.get(word) {
+            return Options {
+                word,
+                breaks: Breaks::Standard(breaks.to_vec())
+            };
+        }
         match *self {
             Dictionary::Standard(ref dict) => {
                 Options {
@@ -168,6 +209,80 @@ impl Dictionary {
     }
 }
 
+/// Custom break points for words.
+/// They take precedence over the dictionaries.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+pub struct Exceptions {
+    // word -> byte indices of the break points
+    words: HashMap<String, Vec<usize>>
+}
+impl Exceptions {
+    pub fn new() -> Self {
+        Exceptions::default()
+    }
+
+    /// split an entry like "hy-phen-ation" into the word and its break points
+    pub fn parse(entry: &str) -> (String, Vec<usize>) {
+        let mut word = String::with_capacity(entry.len());
+        let mut breaks = vec![];
+        for c in entry.chars() {
+            match c {
+                '-' if word.len() > 0 => breaks.push(word.len()),
+                '-' => {}
+                c => word.push(c)
+            }
+        }
+        breaks.retain(|&index| index < word.len());
+        breaks.dedup();
+        (word, breaks)
+    }
+    pub fn insert(&mut self, word: String, breaks: Vec<usize>) {
+        self.words.insert(word, breaks);
+    }
+    pub fn remove(&mut self, word: &str) {
+        self.words.remove(word);
+    }
+    pub fn get(&self, word: &str) -> Option<&[usize]> {
+        if let Some(breaks) = self.words.get(word) {
+            return Some(breaks.as_slice());
+        }
+
+        // only when the byte indices stay the same
+        let lower = word.to_lowercase();
+        if lower.len() == word.len() {
+            self.words.get(&lower).map(|breaks| breaks.as_slice())
+        } else {
+            None
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &[usize])> {
+        self.words.iter().map(|(word, breaks)| (word.as_str(), breaks.as_slice()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone)]
+pub struct Hyphenation {
+    // the minimums only apply to breaks of the dictionary, not to soft hyphens of the author.
+    // minimum number of chars before the hyphen (lefthyphenmin)
+    pub left_min:  usize,
+
+    // minimum number of chars after the hyphen (righthyphenmin)
+    pub right_min: usize,
+
+    pub penalty:   HyphenPenalty,
+}
+impl Default for Hyphenation {
+    fn default() -> Self {
+        Hyphenation {
+            left_min:  2,
+            right_min: 3,
+            penalty:   HyphenPenalty::default()
+        }
+    }
+}
+
 pub const HYPHEN_MARK: char = '\u{AD}';
 
 #[derive(Serialize, Deserialize)]
@@ -176,6 +291,60 @@ pub struct Word {
     // contains HYPHEN_MARK to indicate possible breaking points
     pub text: String,
 }
+impl Word {
+    /// direction of the first strong character, if any
+    pub fn direction(&self) -> Option<Direction> {
+        crate::text::strong_direction(&self.text)
+    }
+
+    /// the text without the HYPHEN_MARKs, as it is shaped
+    pub fn display_text(&self) -> Cow<str> {
+        if self.text.contains(HYPHEN_MARK) {
+            Cow::Owned(self.text.chars().filter(|&c| c != HYPHEN_MARK).collect())
+        } else {
+            Cow::Borrowed(&self.text)
+        }
+    }
+
+    /// the break points set by the author, as byte indices into `display_text()`
+    pub fn soft_hyphens(&self) -> Vec<usize> {
+        let mut breaks = vec![];
+        let mut removed = 0;
+        for (idx, c) in self.text.char_indices() {
+            if c == HYPHEN_MARK {
+                let pos = idx - removed;
+                if pos > 0 && breaks.last() != Some(&pos) {
+                    breaks.push(pos);
+                }
+                removed += c.len_utf8();
+            }
+        }
+        let len = self.text.len() - removed;
+        breaks.retain(|&pos| pos < len);
+        breaks
+    }
+
+    /// convert a byte index into `text` to one into `display_text()`
+    pub fn to_display_pos(&self, pos: usize) -> usize {
+        let marks = self.text[.. pos].matches(HYPHEN_MARK).count();
+        pos - marks * HYPHEN_MARK.len_utf8()
+    }
+
+    /// convert a byte index into `display_text()` to one into `text`.
+    /// positions at a break point end up before the HYPHEN_MARK.
+    pub fn to_text_pos(&self, pos: usize) -> usize {
+        let mut display_pos = 0;
+        for (idx, c) in self.text.char_indices() {
+            if display_pos == pos {
+                return idx;
+            }
+            if c != HYPHEN_MARK {
+                display_pos += c.len_utf8();
+            }
+        }
+        self.text.len()
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Symbol {
@@ -192,7 +361,93 @@ pub enum Item {
     Word(WordId),
     Symbol(SymbolId),
     Sequence(SequenceId),
-    Object(ObjectId)
+    Object(ObjectId),
+
+    // generated text about another part of the document
+    Reference(Reference),
+
+    // the index lists the page of this place under the term
+    IndexMark(IndexMark),
+
+    // generated text for entries of the bibliography
+    Citation(CitationId)
+}
+
+/// A term of the index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Term {
+    pub text:     String,
+
+    // listed below the term
+    pub sub:      Option<String>,
+
+    // "see also …"
+    pub see_also: Option<String>,
+}
+impl Term {
+    pub fn new(text: &str) -> Term {
+        Term {
+            text: text.into(),
+            sub: None,
+            see_also: None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct IndexMark {
+    pub term:  TermId,
+    pub range: MarkRange,
+}
+
+/// A range of pages is marked by a Begin and the following End of a term with the same text and sub-entry
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum MarkRange {
+    Point,
+    Begin,
+    End,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct Reference {
+    pub target: Tag,
+    pub show:   Show,
+}
+
+/// What a reference shows of its target
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Show {
+    // the counter value of the target sequence ("Figure 3")
+    Counter,
+
+    // the number of the page the target is on
+    Page,
+
+    // the text of the target sequence
+    Title,
+}
+
+/// Where a sequence links to
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Link {
+    // an address outside the document
+    Uri(String),
+
+    // another part of the document
+    Internal(Tag),
+}
+
+/// What the document is, for the metadata of exported files
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -200,7 +455,7 @@ pub enum Item {
 pub struct Attribute;
 
 #[derive(Serialize, Deserialize)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Tag {
     Start(SequenceId),
     Item(SequenceId, Id),
@@ -231,6 +486,24 @@ pub struct Design {
     name: String,
     map: HashMap<TypeId, TypeDesign>,
     default: TypeDesign,
+
+    // tried in order for characters the font of the type does not have
+    fallback: Vec<FontId>,
+
+    // baselines are placed on multiples of this
+    baseline_grid: Option<Length>,
+
+    // fixed widths of the columns of tables by type. None takes the width of the content.
+    column_widths: HashMap<TypeId, Vec<Option<Length>>>,
+
+    // by name ("chapter", "figure", …)
+    counters: Vec<(String, Counter)>,
+
+    // listed in a table of contents. the first type is the top level.
+    contents_types: Vec<TypeId>,
+
+    // how citations and the bibliography look
+    citation_style: CitationStyle,
 }
 impl Design {
     pub fn new(name: String, default: TypeDesign) -> Self {
@@ -238,8 +511,57 @@ impl Design {
             name,
             map: HashMap::new(),
             default,
+            fallback: vec![],
+            baseline_grid: None,
+            column_widths: HashMap::new(),
+            counters: vec![],
+            contents_types: vec![],
+            citation_style: CitationStyle::default(),
         }
     }
+    pub fn set_baseline_grid(&mut self, grid: Option<Length>) {
+        self.baseline_grid = grid;
+    }
+    pub fn baseline_grid(&self) -> Option<Length> {
+        self.baseline_grid
+    }
+    pub fn set_column_widths(&mut self, key: TypeId, widths: Vec<Option<Length>>) {
+        self.column_widths.insert(key, widths);
+    }
+    pub fn column_widths(&self, key: TypeId) -> &[Option<Length>] {
+        self.column_widths.get(&key).map(|w| w.as_slice()).unwrap_or(&[])
+    }
+    /// replaces the counter of the same name
+    pub fn set_counter(&mut self, name: &str, counter: Counter) {
+        match self.counters.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = counter,
+            None => self.counters.push((name.into(), counter))
+        }
+    }
+    pub fn counter(&self, name: &str) -> Option<&Counter> {
+        self.counters.iter().find(|(n, _)| n == name).map(|(_, c)| c)
+    }
+    pub fn counters(&self) -> &[(String, Counter)] {
+        &self.counters
+    }
+    pub fn set_contents_types(&mut self, types: Vec<TypeId>) {
+        self.contents_types = types;
+    }
+    pub fn contents_types(&self) -> &[TypeId] {
+        &self.contents_types
+    }
+    pub fn set_citation_style(&mut self, style: CitationStyle) {
+        self.citation_style = style;
+    }
+    pub fn citation_style(&self) -> CitationStyle {
+        self.citation_style
+    }
+    pub fn set_fallback(&mut self, fonts: Vec<FontId>) {
+        self.fallback = fonts;
+    }
+    pub fn fallback(&self) -> &[FontId] {
+        &self.fallback
+    }
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
@@ -267,7 +589,188 @@ pub enum Display {
     Inline,
 
     // Indent
-    Paragraph(units::Length, VerticalPadding)
+    Paragraph(units::Length, VerticalPadding),
+
+    // taken out of the flow and placed at the top or bottom of a column
+    Float(Float),
+
+    // rows of cells. the sequences in it are the rows, theirs are the cells.
+    Table(Table),
+
+    // generated list of the sequences of the types in Design::contents_types
+    Contents(Contents),
+
+    // generated list of the terms of all index marks
+    Index(Index),
+
+    // generated list of the cited entries of the bibliography
+    Bibliography(Bibliography)
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone)]
+pub struct Bibliography {
+    pub padding: VerticalPadding,
+
+    // between the entries
+    pub gap:     Length,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone)]
+pub struct Index {
+    pub padding: VerticalPadding,
+
+    // of the sub-entries
+    pub indent:  Length,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone)]
+pub struct Contents {
+    pub padding: VerticalPadding,
+
+    // for each level
+    pub indent:  Length,
+
+    // repeated between the title and the page number
+    pub leader:  SymbolId,
+}
+
+/// Counts the sequences of some types. The value is shown in front of them,
+/// or in front of the caption for floats.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct Counter {
+    // a sequence of one of these types increments it
+    pub types:  Vec<TypeId>,
+
+    // within a sequence of one of these types it starts again at 1.
+    // the previous value returns after it.
+    pub reset:  Vec<TypeId>,
+
+    pub style:  NumberStyle,
+    pub prefix: String,
+    pub suffix: String,
+}
+impl Counter {
+    pub fn new(types: Vec<TypeId>, style: NumberStyle) -> Counter {
+        Counter {
+            types,
+            reset: vec![],
+            style,
+            prefix: String::new(),
+            suffix: String::new()
+        }
+    }
+    /// "Figure 3"
+    pub fn format(&self, n: usize) -> String {
+        format!("{}{}{}", self.prefix, self.style.format(n), self.suffix)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NumberStyle {
+    Arabic,
+    LowerRoman,
+    UpperRoman,
+    LowerAlpha,
+    UpperAlpha,
+}
+impl NumberStyle {
+    pub fn format(self, n: usize) -> String {
+        match self {
+            NumberStyle::Arabic => n.to_string(),
+            NumberStyle::LowerRoman => roman(n).to_lowercase(),
+            NumberStyle::UpperRoman => roman(n),
+            NumberStyle::LowerAlpha => alphabetic(n, b'a'),
+            NumberStyle::UpperAlpha => alphabetic(n, b'A'),
+        }
+    }
+}
+
+fn roman(mut n: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"),
+        (100, "C"), (90, "XC"), (50, "L"), (40, "XL"),
+        (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I")
+    ];
+    let mut s = String::new();
+    for &(value, numeral) in NUMERALS.iter() {
+        while n >= value {
+            s.push_str(numeral);
+            n -= value;
+        }
+    }
+    s
+}
+
+// a … z, aa, ab …
+fn alphabetic(mut n: usize, a: u8) -> String {
+    let mut letters = vec![];
+    while n > 0 {
+        n -= 1;
+        letters.push((a + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone)]
+pub struct Table {
+    // space above and below the table
+    pub padding:     VerticalPadding,
+
+    // between the columns
+    pub column_gap:  Length,
+
+    // between the rows
+    pub row_gap:     Length,
+
+    // the first rows are repeated at the top of every column the table continues in
+    pub header_rows: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone)]
+pub struct Float {
+    pub placement: Placement,
+
+    // space between the float and the text
+    pub padding:   VerticalPadding,
+
+    // floats with a label are numbered ("Figure 3") in front of the caption,
+    // unless a counter of the design counts their type
+    pub label:     Option<SymbolId>,
+
+    // between a float beside the text and the text
+    pub gap:       Length,
+
+    // the text follows the outline of the object instead of its box
+    pub contour:   bool,
+}
+
+/// Where a float goes in the column of its anchor.
+/// If it does not fit there, it moves to the next column.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Placement {
+    Top,
+    Bottom,
+
+    // beside the following lines, which wrap around it
+    Left,
+    Right
+}
+impl Placement {
+    pub fn is_beside(self) -> bool {
+        match self {
+            Placement::Left | Placement::Right => true,
+            _ => false
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -283,10 +786,41 @@ pub struct TypeDesign {
     pub display:        Display,
     pub font:           Font,
     pub word_space:     FlexMeasure,
+
+    // added between the clusters of a word. stretches and shrinks with the line.
+    pub letter_spacing: FlexMeasure,
+
+    // minimum, lines with taller items grow
     pub line_height:    Length,
-    pub dictionary:     DictId,
+
+    // None uses the language of the enclosing sequence
+    pub language:       Option<DictId>,
+
+    // None uses the direction of the enclosing sequence
+    pub direction:      Option<Direction>,
     pub hyphen:         Option<SymbolId>,
+    pub hyphenation:    Hyphenation,
     pub indent:         Length,
+
+    // the first letter of the sequence as a drop cap
+    pub initial:        Option<Initial>,
+
+    // of the glyphs
+    pub color:          Color,
+}
+
+/// A large initial letter that sits on the baseline of a later line.
+/// Its top aligns with the ascent of the first line.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone)]
+pub struct Initial {
+    pub font_face: FontId,
+
+    // number of lines it spans
+    pub lines:     u16,
+
+    // between the initial and the text
+    pub gap:       Length,
 }
 
 pub enum VerticalAlign {
@@ -299,9 +833,161 @@ pub enum VerticalAlign {
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct Font {
     pub font_face: FontId,
-    pub size: Length // height of 1em
+    pub size: Length, // height of 1em
+    pub features: Features,
+    pub variations: Variations,
+    pub synthetic: Synthetic,
+}
+
+/// OpenType features that can be switched on or off.
+pub const FEATURE_TAGS: [&str; 42] = [
+    "liga", "clig", "dlig", "hlig", "calt", "kern",
+    "smcp", "c2sc", "pcap", "c2pc",
+    "onum", "lnum", "pnum", "tnum", "frac", "sups", "subs", "ordn", "zero", "case",
+    "swsh", "salt",
+    "ss01", "ss02", "ss03", "ss04", "ss05", "ss06", "ss07", "ss08", "ss09", "ss10",
+    "ss11", "ss12", "ss13", "ss14", "ss15", "ss16", "ss17", "ss18", "ss19", "ss20",
+];
+
+/// Selection of OpenType features (one bit per entry in FEATURE_TAGS).
+/// Features that are neither enabled nor disabled keep the default of the font.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Default)]
+pub struct Features {
+    enabled:  u64,
+    disabled: u64,
+}
+impl Features {
+    pub fn new() -> Self {
+        Features::default()
+    }
+    fn bit(tag: &str) -> Option<u64> {
+        let bit = FEATURE_TAGS.iter().position(|&t| t == tag).map(|i| 1 << i);
+        if bit.is_none() {
+            warn!("unsupported feature {:?}", tag);
+        }
+        bit
+    }
+    pub fn enable(mut self, tag: &str) -> Self {
+        if let Some(bit) = Features::bit(tag) {
+            self.enabled |= bit;
+            self.disabled &= !bit;
+        }
+        self
+    }
+    pub fn disable(mut self, tag: &str) -> Self {
+        if let Some(bit) = Features::bit(tag) {
+            self.disabled |= bit;
+            self.enabled &= !bit;
+        }
+        self
+    }
+    pub fn is_enabled(&self, tag: &str) -> bool {
+        FEATURE_TAGS.iter().position(|&t| t == tag).map_or(false, |i| self.enabled & (1 << i) != 0)
+    }
+    pub fn is_disabled(&self, tag: &str) -> bool {
+        FEATURE_TAGS.iter().position(|&t| t == tag).map_or(false, |i| self.disabled & (1 << i) != 0)
+    }
+    /// the selected features and whether they are switched on
+    pub fn iter(&self) -> impl Iterator<Item=(&'static str, bool)> {
+        let Features { enabled, disabled } = *self;
+        FEATURE_TAGS.iter().enumerate().filter_map(move |(i, &tag)| {
+            let bit = 1u64 << i;
+            match (enabled & bit != 0, disabled & bit != 0) {
+                (true, _) => Some((tag, true)),
+                (_, true) => Some((tag, false)),
+                _ => None
+            }
+        })
+    }
 }
 
+/// Axis values for variable fonts. None keeps the default of the font.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Variations {
+    pub weight: Option<f32>, // wght
+    pub width: Option<f32>, // wdth
+    pub optical_size: OpticalSize, // opsz
+}
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OpticalSize {
+    Default,
+
+    // follow the font size (in points)
+    Auto,
+    Value(f32),
+}
+impl Default for OpticalSize {
+    fn default() -> Self {
+        OpticalSize::Default
+    }
+}
+impl Variations {
+    pub fn is_default(&self) -> bool {
+        self.weight.is_none() && self.width.is_none() && self.optical_size == OpticalSize::Default
+    }
+    /// (axis tag, value) of all set axes, for a font of the given size
+    pub fn axes(&self, size: Length) -> Vec<(&'static str, f32)> {
+        let mut axes = vec![];
+        if let Some(weight) = self.weight {
+            axes.push(("wght", weight));
+        }
+        if let Some(width) = self.width {
+            axes.push(("wdth", width));
+        }
+        match self.optical_size {
+            OpticalSize::Default => {},
+            OpticalSize::Auto => axes.push(("opsz", size.value * 72.0 / 25.4)),
+            OpticalSize::Value(opsz) => axes.push(("opsz", opsz)),
+        }
+        axes
+    }
+    fn bits(&self) -> [u32; 4] {
+        let (opsz_kind, opsz) = match self.optical_size {
+            OpticalSize::Default => (0, 0),
+            OpticalSize::Auto => (1, 0),
+            OpticalSize::Value(v) => (2, v.to_bits()),
+        };
+        let bits = |v: Option<f32>| v.map_or(u32::max_value(), f32::to_bits);
+        [bits(self.weight), bits(self.width), opsz_kind, opsz]
+    }
+}
+impl Hash for Variations {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits().hash(state);
+    }
+}
+impl PartialEq for Variations {
+    fn eq(&self, other: &Variations) -> bool {
+        self.bits() == other.bits()
+    }
+}
+impl Eq for Variations {}
+
+/// Fake styles for faces without an italic or bold variant.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Synthetic {
+    // horizontal shift per unit of height (tan of the slant angle). 0 for none.
+    pub oblique: f32,
+
+    // width of the added stroke, in em. 0 for none.
+    pub embolden: f32,
+}
+impl Hash for Synthetic {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.oblique.to_bits());
+        state.write_u32(self.embolden.to_bits());
+    }
+}
+impl PartialEq for Synthetic {
+    fn eq(&self, other: &Synthetic) -> bool {
+        self.oblique.to_bits() == other.oblique.to_bits() && self.embolden.to_bits() == other.embolden.to_bits()
+    }
+}
+impl Eq for Synthetic {}
 
 /// Describes a physical print target.
 /// The author usually has only few choices here
@@ -320,6 +1006,9 @@ pub struct Target {
     // the printed media gets trimmed to this
     pub trim_box: Rect,
 
+    // graphics that reach the edge of the trim box extend to here, to allow for inaccurate trimming
+    pub bleed_box: Rect,
+
     // the color of an empty page.
     // we want to know this to show a properly preview
     pub page_color: Color,
@@ -356,3 +1045,49 @@ pub fn hyphenate(w: &mut Writer, word: Word, hyphenator: &Hyphenator) {
     }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exceptions_parse() {
+        assert_eq!(Exceptions::parse("hy-phen-ation"), ("hyphenation".to_string(), vec![2, 6]));
+        // hyphens at the ends and doubled ones are no break points of their own
+        assert_eq!(Exceptions::parse("-ex--tra-"), ("extra".to_string(), vec![2]));
+        assert_eq!(Exceptions::parse("word"), ("word".to_string(), vec![]));
+    }
+
+    #[test]
+    fn exceptions_get() {
+        let mut exceptions = Exceptions::new();
+        let (word, breaks) = Exceptions::parse("hy-phen-ation");
+        exceptions.insert(word, breaks);
+        assert_eq!(exceptions.get("hyphenation"), Some(&[2, 6][..]));
+        // capitalized words use the entry in lower case
+        assert_eq!(exceptions.get("Hyphenation"), Some(&[2, 6][..]));
+        assert_eq!(exceptions.get("hyphen"), None);
+        exceptions.remove("hyphenation");
+        assert_eq!(exceptions.get("hyphenation"), None);
+    }
+
+    #[test]
+    fn word_soft_hyphens() {
+        let word = Word { text: "\u{AD}hy\u{AD}phen\u{AD}\u{AD}ation\u{AD}".into() };
+        // none at the start or end, doubled ones once
+        assert_eq!(word.soft_hyphens(), vec![2, 6]);
+        assert_eq!(Word { text: "plain".into() }.soft_hyphens(), vec![]);
+    }
+
+    #[test]
+    fn word_text_pos() {
+        let word = Word { text: "hy\u{AD}phen".into() };
+        // a position at the break is before the mark
+        assert_eq!(word.to_text_pos(2), 2);
+        assert_eq!(word.to_text_pos(3), 5);
+        assert_eq!(word.to_text_pos(6), word.text.len());
+        for pos in 0 ..= 6 {
+            assert_eq!(word.to_display_pos(word.to_text_pos(pos)), pos);
+        }
+    }
+}
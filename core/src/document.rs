@@ -88,7 +88,7 @@ macro_rules! id {
     )* )
 }
 
-id!(WordId, SymbolId, ObjectId, SequenceId, TypeId, FontId, DictId);
+id!(WordId, SymbolId, ObjectId, SequenceId, TypeId, FontId, DictId, TermId, CitationId);
 
 #[derive(Serialize, Deserialize)]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
@@ -260,7 +260,15 @@ pub enum DocumentOp {
     CreateType(TypeId, String, Type),
     CreateFont(FontId, FontFace),
     CreateObject(ObjectId, Object),
-    CreateDictionary(DictId, Dictionary),
+    CreateTerm(TermId, Term),
+    CreateCitation(CitationId, Citation),
+    AddBibEntry(BibEntry),
+    SetLink(SequenceId, Link),
+    SetMetadata(Metadata),
+    CreateDictionary(DictId, String, Dictionary),
+    AddException(String, Vec<usize>),
+    RemoveException(String),
+    SetLanguage(SequenceId, DictId),
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -269,10 +277,18 @@ pub struct Storage {
     words:   Map<WordId,     Word>,
     symbols: Map<SymbolId,   Symbol>,
     objects: Map<ObjectId,   Object>,
+    terms:   Map<TermId,     Term>,
+    citations: Map<CitationId, Citation>,
+    bibliography: HashMap<String, BibEntry>,
+    links:   HashMap<SequenceId, Link>,
+    metadata: Metadata,
     types:   Map<TypeId,     Type>,
     type_names: HashMap<String, TypeId>,
     fonts:   Map<FontId,     FontFace>,
     dicts:   Map<DictId,     Dictionary>,
+    languages: HashMap<String, DictId>,
+    sequence_languages: HashMap<SequenceId, DictId>,
+    exceptions: Exceptions,
     sites:   Clock<u32>
 }
 
@@ -283,10 +299,18 @@ impl Storage {
             words: Map::new(),
             symbols: Map::new(),
             objects: Map::new(),
+            terms: Map::new(),
+            citations: Map::new(),
+            bibliography: HashMap::new(),
+            links: HashMap::new(),
+            metadata: Metadata::default(),
             types: Map::new(),
             type_names: HashMap::new(),
             fonts: Map::new(),
             dicts: Map::new(),
+            languages: HashMap::new(),
+            sequence_languages: HashMap::new(),
+            exceptions: Exceptions::new(),
             sites: Clock::new()
         }
     }
@@ -307,6 +331,21 @@ impl Storage {
             DocumentOp::CreateObject(id, object) => {
                 self.objects.insert(id, object);
             }
+            DocumentOp::CreateTerm(id, term) => {
+                self.terms.insert(id, term);
+            }
+            DocumentOp::CreateCitation(id, citation) => {
+                self.citations.insert(id, citation);
+            }
+            DocumentOp::AddBibEntry(entry) => {
+                self.bibliography.insert(entry.key.clone(), entry);
+            }
+            DocumentOp::SetLink(id, link) => {
+                self.links.insert(id, link);
+            }
+            DocumentOp::SetMetadata(metadata) => {
+                self.metadata = metadata;
+            }
             DocumentOp::CreateFont(id, font) => {
                 self.fonts.insert(id, font);
             }
@@ -314,8 +353,18 @@ impl Storage {
                 self.types.insert(id, typ);
                 self.type_names.insert(name, id);
             }
-            DocumentOp::CreateDictionary(id, dict) => {
+            DocumentOp::CreateDictionary(id, tag, dict) => {
                 self.dicts.insert(id, dict);
+                self.languages.insert(tag, id);
+            }
+            DocumentOp::AddException(word, breaks) => {
+                self.exceptions.insert(word, breaks);
+            }
+            DocumentOp::RemoveException(word) => {
+                self.exceptions.remove(&word);
+            }
+            DocumentOp::SetLanguage(id, dict) => {
+                self.sequence_languages.insert(id, dict);
             }
         }
     }
@@ -327,6 +376,9 @@ impl Storage {
             Item::Symbol(key) => self.symbols.get(key).unwrap().text.as_str(),
             Item::Sequence(_) => "<seq>",
             Item::Object(_) => "<obj>",
+            Item::Reference(_) => "<ref>",
+            Item::IndexMark(_) => "<index>",
+            Item::Citation(_) => "<cite>",
         };
 
         for atom in weave.atoms.iter() {
@@ -350,6 +402,22 @@ impl Storage {
     pub fn get_object(&self, id: ObjectId) -> &Object {
         self.objects.get(id).unwrap()
     }
+    pub fn get_term(&self, id: TermId) -> &Term {
+        self.terms.get(id).unwrap()
+    }
+    pub fn get_citation(&self, id: CitationId) -> &Citation {
+        self.citations.get(id).unwrap()
+    }
+    pub fn get_bib_entry(&self, key: &str) -> Option<&BibEntry> {
+        self.bibliography.get(key)
+    }
+    /// where the sequence links to, if it is a link
+    pub fn get_link(&self, id: SequenceId) -> Option<&Link> {
+        self.links.get(&id)
+    }
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
     pub fn get_font_face(&self, id: FontId) -> &FontFace {
         self.fonts.get(id).unwrap()
     }
@@ -359,6 +427,13 @@ impl Storage {
     pub fn get_dict(&self, id: DictId) -> &Dictionary {
         self.dicts.get(id).unwrap()
     }
+    pub fn exceptions(&self) -> &Exceptions {
+        &self.exceptions
+    }
+    /// the language set on the sequence itself, if any
+    pub fn get_language(&self, id: SequenceId) -> Option<DictId> {
+        self.sequence_languages.get(&id).cloned()
+    }
 
     pub fn get_last(&self, seq_id: SequenceId) -> Option<(Tag, Item)> {
         self.weaves.get(seq_id).unwrap().items()
@@ -372,6 +447,13 @@ impl Storage {
     pub fn find_type(&self, name: &str) -> Option<TypeId> {
         self.type_names.get(name).cloned()
     }
+    pub fn find_language(&self, tag: &str) -> Option<DictId> {
+        self.languages.get(&tag.to_lowercase()).cloned()
+    }
+    /// the language tag the dictionary was added with
+    pub fn language_tag(&self, id: DictId) -> Option<&str> {
+        self.languages.iter().find(|&(_, &dict)| dict == id).map(|(tag, _)| tag.as_str())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -383,7 +465,7 @@ pub struct State<'a> {
 }
 impl<'a> State<'a> {
     const MAGIC: [u8; 8] = *b"grafeia\0";
-    const VERSION: u32 = 6;
+    const VERSION: u32 = 27;
     pub fn borrowed<'b: 'a>(&'b self) -> State<'b> {
         use std::borrow::Borrow;
         State {
@@ -628,6 +710,49 @@ impl Document {
         self.pending.push(DocumentOp::CreateObject(id, object));
         id
     }
+    pub fn add_term(&mut self, term: Term) -> TermId {
+        let id = self.storage.terms.create(self.site, term.clone());
+        self.pending.push(DocumentOp::CreateTerm(id, term));
+        id
+    }
+    pub fn create_citation(&mut self, citation: Citation) -> CitationId {
+        let id = self.storage.citations.create(self.site, citation.clone());
+        self.pending.push(DocumentOp::CreateCitation(id, citation));
+        id
+    }
+    /// replaces the entry of the same key
+    pub fn add_bib_entry(&mut self, entry: BibEntry) {
+        self.storage.bibliography.insert(entry.key.clone(), entry.clone());
+        self.pending.push(DocumentOp::AddBibEntry(entry));
+    }
+    /// add all entries of a .bib file. returns how many there were.
+    pub fn load_bibtex(&mut self, input: &str) -> Result<usize, Box<dyn Error>> {
+        let entries = bibliography::parse_bibtex(input)?;
+        let count = entries.len();
+        for entry in entries {
+            self.add_bib_entry(entry);
+        }
+        Ok(count)
+    }
+    /// add all entries of a CSL-JSON file. returns how many there were.
+    pub fn load_csl_json(&mut self, input: &str) -> Result<usize, Box<dyn Error>> {
+        let entries = bibliography::parse_csl_json(input)?;
+        let count = entries.len();
+        for entry in entries {
+            self.add_bib_entry(entry);
+        }
+        Ok(count)
+    }
+    /// makes the sequence a link
+    pub fn set_link(&mut self, id: SequenceId, link: Link) {
+        self.storage.links.insert(id, link.clone());
+        self.pending.push(DocumentOp::SetLink(id, link));
+    }
+    /// title and author
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.storage.metadata = metadata.clone();
+        self.pending.push(DocumentOp::SetMetadata(metadata));
+    }
     pub fn add_font(&mut self, data: impl Into<Vec<u8>>) -> FontId {
         let font = FontFace::from_data(data.into());
         let id = self.storage.fonts.create(self.site, font.clone());
@@ -640,18 +765,45 @@ impl Document {
         self.pending.push(DocumentOp::CreateSymbol(id, symbol));
         id
     }
-    pub fn add_dict(&mut self, dict: Dictionary) -> DictId {
+    pub fn add_dict(&mut self, tag: &str, dict: Dictionary) -> DictId {
+        let tag = tag.to_lowercase();
         let id = self.storage.dicts.create(self.site, dict.clone());
-        self.pending.push(DocumentOp::CreateDictionary(id, dict));
+        self.storage.languages.insert(tag.clone(), id);
+        self.pending.push(DocumentOp::CreateDictionary(id, tag, dict));
         id
     }
-    pub fn load_dict(&mut self, data: &[u8]) -> DictId {
+    pub fn load_dict(&mut self, tag: &str, data: &[u8]) -> DictId {
         use hyphenation::{Standard, Load};
         use std::io::Cursor;
         self.add_dict(
+            tag,
             Dictionary::Standard(Standard::any_from_reader(&mut Cursor::new(data)).unwrap())
         )
     }
+    /// add the dictionary for the language tag from the registry, unless it is already present
+    pub fn load_language(&mut self, registry: &LanguageRegistry, tag: &str) -> Result<DictId, Box<dyn Error>> {
+        if let Some(id) = self.storage.find_language(tag) {
+            return Ok(id);
+        }
+        let dict = registry.load(tag)?;
+        Ok(self.add_dict(tag, dict))
+    }
+    /// add custom break points for a word, written like "hy-phen-ation"
+    pub fn add_exception(&mut self, entry: &str) {
+        let (word, breaks) = Exceptions::parse(entry);
+        self.storage.exceptions.insert(word.clone(), breaks.clone());
+        self.pending.push(DocumentOp::AddException(word, breaks));
+    }
+    /// the word is hyphenated by the dictionary again
+    pub fn remove_exception(&mut self, word: &str) {
+        self.storage.exceptions.remove(word);
+        self.pending.push(DocumentOp::RemoveException(word.into()));
+    }
+    /// the text of the sequence is in the given language, regardless of its type
+    pub fn set_language(&mut self, id: SequenceId, dict: DictId) {
+        self.storage.sequence_languages.insert(id, dict);
+        self.pending.push(DocumentOp::SetLanguage(id, dict));
+    }
     pub fn find_symbol(&self, text: &str) -> Option<SymbolId> {
         self.symbols.get(text).cloned()
     }
@@ -1,8 +1,12 @@
 use crate::*;
-use crate::layout::{Writer, Glue, ColumnLayout, FlexMeasure, Column, Columns, ItemMeasure};
+use crate::layout::{Writer, Glue, ColumnLayout, ParagraphLayout, FlexMeasure, Column, Columns, Line, ItemMeasure, StreamVec};
 use crate::units::Length;
-use crate::text::{grapheme_indices, build_gids};
-use std::collections::hash_map::{HashMap};
+use crate::bibliography::year_suffixes;
+use crate::text::{shape, font_runs, strong_direction, variable_outlines, collator, bidi_levels, embedding_level, visual_order};
+use unicode_segmentation::UnicodeSegmentation;
+use std::collections::hash_map::{HashMap, Entry};
+use std::ops::Range;
+use std::hash::Hash;
 use font;
 use vector::{PathStyle, Surface, PathBuilder, FillRule, Paint};
 use pathfinder_geometry::{
@@ -11,9 +15,16 @@ use pathfinder_geometry::{
     transform2d::Transform2F
 };
 use pathfinder_content::outline::Outline;
+use pathfinder_content::stroke::{OutlineStrokeToFill, StrokeStyle, LineCap, LineJoin};
 use pathfinder_renderer::scene::Scene;
 use instant::Instant;
 
+// shown by references whose target is not laid out
+const UNRESOLVED: &str = "??";
+
+// references can change the layout they depend on. it is repeated at most this often.
+const MAX_LAYOUT_PASSES: usize = 4;
+
 #[inline]
 fn select<T>(cond: bool, a: T, b: T) -> T {
     if cond { a } else { b }
@@ -31,18 +42,177 @@ struct Context<'a> {
 #[derive(Debug)]
 pub struct Layout {
     advance: Vector2F,
-    glyphs: Vec<(font::GlyphId, Transform2F)>,
+
+    // font, glyph, transform and index of the cluster
+    glyphs: Vec<(FontId, font::GlyphId, Transform2F, usize)>,
+
+    // start of each cluster (byte index, position), in visual order
+    clusters: Vec<(usize, Vector2F)>,
+
+    text: String,
+
+    // clusters are in descending order
+    rtl: bool,
+
+    // embedding level of each byte of the text, if it was part of a resolved paragraph
+    levels: Vec<u8>,
+
+    // outlines of variable fonts at the requested axis values, by (font, glyph id)
+    outlines: HashMap<(FontId, u32), Outline>,
+
+    // width of the synthetic bold stroke
+    embolden: f32,
+
+    // baseline relative to the position of the item. only drop caps move it.
+    origin: Vector2F,
 }
 impl Layout {
-    pub fn render(&self, font: &FontFace, root_tr: Transform2F) -> Outline {
+    // number of places letter spacing is added
+    fn gaps(&self) -> usize {
+        self.clusters.len().saturating_sub(1)
+    }
+
+    /// width of the text with the given letter spacing
+    pub fn measure(&self, letter_spacing: FlexMeasure) -> FlexMeasure {
+        FlexMeasure::fixed(Length::mm(self.advance.x())) + letter_spacing * self.gaps() as f32
+    }
+
+    /// the letter spacing that makes the text `width` wide
+    pub fn spacing_for(&self, width: f32) -> f32 {
+        match self.gaps() {
+            0 => 0.0,
+            n => (width - self.advance.x()) / n as f32
+        }
+    }
+
+    /// width of the text with the given letter spacing between the clusters
+    pub fn width(&self, spacing: f32) -> f32 {
+        self.advance.x() + spacing * self.gaps() as f32
+    }
+
+    // (start, end, left, right) of each cluster
+    fn spans(&self, spacing: f32) -> Vec<(usize, usize, f32, f32)> {
+        let mut starts: Vec<usize> = self.clusters.iter().map(|&(idx, _)| idx).collect();
+        starts.sort();
+
+        // the spacing goes to the right of each cluster but the last
+        self.clusters.iter().enumerate().map(|(i, &(start, pos))| {
+            let end = starts.iter().cloned().find(|&idx| idx > start).unwrap_or(self.text.len());
+            let left = pos.x() + spacing * i as f32;
+            let right = match self.clusters.get(i+1) {
+                Some(&(_, p)) => p.x() + spacing * i as f32,
+                None => self.width(spacing)
+            };
+            (start, end, left, right)
+        }).collect()
+    }
+
+    /// position of the caret before the byte at `byte_pos`.
+    /// positions inside of a cluster (like a ligature) are interpolated.
+    pub fn caret(&self, byte_pos: usize, spacing: f32) -> Vector2F {
+        for (start, end, left, right) in self.spans(spacing) {
+            if start <= byte_pos && byte_pos < end {
+                let f = (byte_pos - start) as f32 / (end - start) as f32;
+                let x = match self.rtl {
+                    false => left + (right - left) * f,
+                    true => right - (right - left) * f
+                };
+                return Vector2F::new(x, 0.0);
+            }
+        }
+
+        // end of the text
+        Vector2F::new(select(self.rtl, 0.0, self.width(spacing)), 0.0)
+    }
+
+    /// the cluster boundary closest to `x`, as (position, byte index)
+    pub fn hit(&self, x: f32, spacing: f32) -> (Vector2F, usize) {
+        let mut best = (select(self.rtl, self.width(spacing), 0.0), 0);
+        for (start, end, left, right) in self.spans(spacing) {
+            let (idx_left, idx_right) = select(self.rtl, (end, start), (start, end));
+            for &(edge, idx) in [(left, idx_left), (right, idx_right)].iter() {
+                if (edge - x).abs() < (best.0 - x).abs() {
+                    best = (edge, idx);
+                }
+            }
+        }
+        (Vector2F::new(best.0, 0.0), best.1)
+    }
+    /// positions of copies that fill `x .. x + width`. they are placed on multiples
+    /// of the advance, so the copies on different lines line up.
+    pub fn repeat(&self, x: f32, width: f32) -> impl Iterator<Item=f32> {
+        let advance = self.advance.x();
+        let (first, last) = match advance > 0.0 {
+            true => ((x / advance).ceil() as i32, ((x + width) / advance).floor() as i32 - 1),
+            false => (1, 0)
+        };
+        (first ..= last).map(move |i| i as f32 * advance)
+    }
+    /// area of a drop cap placed at `p`, one em above its baseline
+    pub fn initial_rect(&self, p: Vector2F, font: Font) -> RectF {
+        let em = font.size.value;
+        RectF::new(p + self.origin - Vector2F::new(0.0, em), Vector2F::new(self.advance.x(), em))
+    }
+    /// synthetic bold and variable instances are not in any font file
+    pub fn needs_outlines(&self) -> bool {
+        self.embolden > 0.0 || !self.outlines.is_empty()
+    }
+    /// the text in reading order, if the glyphs show some of it right to left
+    pub fn actual_text(&self) -> Option<&str> {
+        match self.rtl || self.levels.iter().any(|&level| level % 2 == 1) {
+            true => Some(&self.text),
+            false => None
+        }
+    }
+    /// (font, glyph, transform of the em square, text) of each glyph.
+    /// the text of a cluster goes to its first glyph, the others get an empty string.
+    pub fn glyphs(&self, storage: &Storage, root_tr: Transform2F, spacing: f32) -> Vec<(FontId, font::GlyphId, Transform2F, &str)> {
+        let root_tr = root_tr * Transform2F::from_translation(self.origin);
+        let spans = self.spans(0.0);
+        let mut last_cluster = None;
+        self.glyphs.iter().map(|&(font_id, gid, tr, cluster)| {
+            let em = storage.get_font_face(font_id).font_matrix().inverse();
+            let tr = root_tr * Transform2F::from_translation(Vector2F::new(spacing * cluster as f32, 0.0)) * tr * em;
+            let text = match last_cluster.replace(cluster) {
+                Some(last) if last == cluster => "",
+                _ => &self.text[spans[cluster].0 .. spans[cluster].1]
+            };
+            (font_id, gid, tr, text)
+        }).collect()
+    }
+    pub fn render(&self, storage: &Storage, root_tr: Transform2F, spacing: f32) -> Outline {
+        let root_tr = root_tr * Transform2F::from_translation(self.origin);
         let mut outline = Outline::new();
-        for &(gid, tr) in self.glyphs.iter() {
-            if let Some(glyph) = font.glyph(gid) {
-                for contour in glyph.path.contours() {
-                    let mut contour = contour.clone();
-                    contour.transform(&(root_tr * tr));
-                    outline.push_contour(contour);
+        for &(font_id, gid, tr, cluster) in self.glyphs.iter() {
+            let tr = Transform2F::from_translation(Vector2F::new(spacing * cluster as f32, 0.0)) * tr;
+            let glyph;
+            let path = match self.outlines.get(&(font_id, gid.0)) {
+                Some(path) => path,
+                None => match storage.get_font_face(font_id).glyph(gid) {
+                    Some(g) => {
+                        glyph = g;
+                        &glyph.path
+                    }
+                    None => continue
                 }
+            };
+            for contour in path.contours() {
+                let mut contour = contour.clone();
+                contour.transform(&(root_tr * tr));
+                outline.push_contour(contour);
+            }
+        }
+
+        if self.embolden > 0.0 {
+            let style = StrokeStyle {
+                line_width: self.embolden,
+                line_cap: LineCap::Butt,
+                line_join: LineJoin::Round
+            };
+            let mut stroke = OutlineStrokeToFill::new(&outline, style);
+            stroke.offset();
+            for contour in stroke.into_outline().contours() {
+                outline.push_contour(contour.clone());
             }
         }
         outline
@@ -62,16 +232,58 @@ pub enum RenderedWord<R> {
     After(R, u16),
     Both(R, R, u16)
 }
+impl<R: Copy> RenderedWord<R> {
+    // record where a part of the word was placed. parts placed again
+    // (in repeated table headers) keep their first place.
+    fn place(words: &mut HashMap<Tag, RenderedWord<R>>, tag: Tag, part: WordPart, rect: R) {
+        match (part, words.entry(tag)) {
+            (WordPart::Full, Entry::Vacant(e)) => {
+                e.insert(RenderedWord::Full(rect));
+            }
+            (WordPart::Before(idx), Entry::Vacant(e)) |
+            (WordPart::Initial(idx), Entry::Vacant(e)) => {
+                e.insert(RenderedWord::Before(rect, idx));
+            }
+            (WordPart::After(idx), Entry::Vacant(e)) => {
+                e.insert(RenderedWord::After(rect, idx));
+            }
+            (WordPart::After(idx), Entry::Occupied(mut e)) => {
+                if let RenderedWord::Before(before, idx2) = *e.get() {
+                    if idx == idx2 {
+                        e.insert(RenderedWord::Both(before, rect, idx));
+                    }
+                }
+            }
+            (WordPart::Before(idx), Entry::Occupied(mut e)) => {
+                if let RenderedWord::After(after, idx2) = *e.get() {
+                    if idx == idx2 {
+                        e.insert(RenderedWord::Both(rect, after, idx));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
 
 pub struct Page {
     scene: Scene,
     items: Vec<(f32, Vec<(f32, Tag)>)>,
+
+    // items that span several lines (drop caps)
+    boxes: Vec<(RectF, Tag)>,
 }
 impl Page {
     pub fn scene(&self) -> &Scene {
         &self.scene
     }
     pub fn find(&self, p: Vector2F) -> Option<(Tag, Vector2F)> {
+        for &(rect, tag) in self.boxes.iter() {
+            if rect.contains_point(p) {
+                return Some((tag, rect.lower_left()));
+            }
+        }
+
         // find the first line with y value greater than p.y
         for &(y, ref line) in self.items.iter() {
             if y > p.y() {
@@ -91,6 +303,15 @@ pub enum RenderItem {
     Word(WordId, WordPart, Font),
     Symbol(SymbolId, Font),
     Object(ObjectId),
+
+    // text that is not in the document (numbers, …). index into Cache::generated
+    Generated(usize),
+
+    // a table row. index into Cache::rows
+    Row(usize),
+
+    // the symbol repeated over the width of the item
+    Leader(SymbolId, Font),
     Empty,
 }
 
@@ -98,15 +319,73 @@ pub enum RenderItem {
 pub enum WordPart {
     Full,
     Before(u16),
-    After(u16)
+    After(u16),
+
+    // the drop cap, up to the given index
+    Initial(u16)
+}
+
+/// A laid out table row
+pub struct Row {
+    // offset and layout of each cell
+    pub cells: Vec<(Length, ParagraphLayout)>,
+
+    // of the tallest cell
+    pub height: Length,
+
+    // space below the row
+    pub gap: Length,
 }
 
 pub struct Cache {
     pub word_layout_cache: HashMap<(Font, WordId, WordPart), Layout>,
     pub symbol_layout_cache: HashMap<(Font, SymbolId), Layout>,
+
+    // the fallback fonts the cached layouts were shaped with
+    fallback: Vec<FontId>,
+
     pub columns: Option<Columns>,
     pub positions: HashMap<Tag, (u32, RectF)>,
     pub word_positions: HashMap<Tag, RenderedWord<(u32, RectF)>>,
+
+    // font and length of the drop caps
+    pub initials: HashMap<Tag, (Font, u16)>,
+
+    // embedding level of each byte of the words and symbols, resolved over their paragraph
+    levels: HashMap<Tag, Vec<u8>>,
+
+    // layout of each float and the space above it
+    pub floats: Vec<(ParagraphLayout, Length)>,
+
+    // value of each counter of the design and the formatted labels
+    counters: Vec<usize>,
+    labels: Vec<String>,
+
+    // the label of each numbered sequence. index into labels
+    sequence_labels: HashMap<SequenceId, usize>,
+
+    // number of floats numbered by each label
+    float_labels: HashMap<SymbolId, usize>,
+
+    // text of each reference as of the last pass, and the references rendered in this pass
+    resolved: HashMap<Reference, String>,
+    references: Vec<Reference>,
+
+    // (level, sequence) of the entries of a table of contents
+    contents: Vec<(usize, SequenceId)>,
+
+    // index marks in the order of the document, and the language and lines of the index rendered in this pass
+    marks: Vec<(Tag, IndexMark)>,
+    index: Option<(Option<DictId>, Vec<(usize, String)>)>,
+
+    // keys of the bibliography in the order they are first cited,
+    // and the letter after the year of those cited with the same authors and year
+    cited: Vec<String>,
+    year_suffixes: HashMap<String, char>,
+
+    pub rows: Vec<Row>,
+
+    pub generated: Vec<Layout>,
 }
 impl Default for Cache {
     fn default() -> Self {
@@ -118,69 +397,222 @@ pub struct DrawCtx<'a> {
     pub design: &'a Design,
     pub target: &'a Target,
     pub type_design: &'a TypeDesign,
+    pub language: Option<DictId>,
+    pub direction: Direction,
     pub width: Length,
     pub indent: Length,
+
+    // label of the float this is the caption of. index into Cache::labels
+    pub label: Option<usize>,
 }
 impl Cache {
     pub fn new() -> Cache {
         Cache {
             word_layout_cache: HashMap::new(),
             symbol_layout_cache: HashMap::new(),
+            fallback: Vec::new(),
             columns: None,
             positions: HashMap::new(),
             word_positions: HashMap::new(),
+            initials: HashMap::new(),
+            levels: HashMap::new(),
+            floats: Vec::new(),
+            counters: Vec::new(),
+            labels: Vec::new(),
+            sequence_labels: HashMap::new(),
+            float_labels: HashMap::new(),
+            resolved: HashMap::new(),
+            references: Vec::new(),
+            contents: Vec::new(),
+            marks: Vec::new(),
+            index: None,
+            cited: Vec::new(),
+            year_suffixes: HashMap::new(),
+            rows: Vec::new(),
+            generated: Vec::new(),
+        }
+    }
+
+    // the language set on the sequence, then the one of its type, then the enclosing one
+    fn language(ctx: &DrawCtx, seq_id: SequenceId, type_design: &TypeDesign) -> Option<DictId> {
+        ctx.storage.get_language(seq_id).or(type_design.language).or(ctx.language)
+    }
+
+    // the text of a paragraph: the words and symbols of the sequence and of the inline sequences
+    // within, with a space between them. other sequences are paragraphs of their own.
+    fn paragraph_text(storage: &Storage, design: &Design, seq_id: SequenceId, text: &mut String, parts: &mut Vec<(Tag, Range<usize>)>) {
+        for (item_id, item) in storage.get_weave(seq_id).items() {
+            let tag = Tag::Item(seq_id, item_id);
+            let (part, glue) = match item {
+                Item::Word(key) => (storage.get_word(key).display_text().into_owned(), true),
+                Item::Symbol(key) => {
+                    let symbol = storage.get_symbol(key);
+                    (symbol.text.clone(), !symbol.trailing)
+                }
+                Item::Object(_) => ("\u{FFFC}".into(), true),
+                Item::Sequence(child) => {
+                    match design.get_type_or_default(storage.get_weave(child).typ()).display {
+                        Display::Inline => Cache::paragraph_text(storage, design, child, text, parts),
+                        _ => text.push('\u{2029}')
+                    }
+                    continue;
+                }
+                _ => continue
+            };
+            if glue && text.len() > 0 && !text.ends_with('\u{2029}') {
+                text.push(' ');
+            }
+            parts.push((tag, text.len() .. text.len() + part.len()));
+            text.push_str(&part);
+        }
+    }
+
+    // runs the bidi algorithm over the paragraph of the sequence
+    fn resolve_bidi(&mut self, storage: &Storage, design: &Design, seq_id: SequenceId, direction: Direction) {
+        let mut text = String::new();
+        let mut parts = vec![];
+        Cache::paragraph_text(storage, design, seq_id, &mut text, &mut parts);
+        if text.is_empty() {
+            return;
+        }
+        let levels = bidi_levels(&text, direction);
+        for (tag, range) in parts {
+            self.levels.insert(tag, levels[range].to_vec());
+        }
+    }
+
+    // levels of the bytes `range` of the item, if its paragraph was resolved
+    fn part_levels(&self, tag: Tag, range: Range<usize>) -> Option<&[u8]> {
+        self.levels.get(&tag).and_then(|levels| levels.get(range)).filter(|levels| levels.len() > 0)
+    }
+
+    // level of text that is not in the document, by its first strong character
+    fn text_level(ctx: &DrawCtx, text: &str) -> Option<u8> {
+        strong_direction(text).map(|direction| embedding_level(direction, embedding_level(ctx.direction, 0)))
+    }
+
+    // level of the bytes `range` of the item as a whole: the lowest level in it.
+    // parts of other levels are ordered within the word.
+    fn item_level(&self, ctx: &DrawCtx, tag: Tag, text: &str, range: Range<usize>) -> Option<u8> {
+        match self.part_levels(tag, range) {
+            Some(levels) => levels.iter().cloned().min(),
+            None => Cache::text_level(ctx, text)
+        }
+    }
+
+    // (ascent, descent) of the font
+    fn font_metrics(storage: &Storage, font: Font) -> (Length, Length) {
+        let face = storage.get_font_face(font.font_face);
+        match face.vmetrics() {
+            Some(metrics) => {
+                let matrix = face.font_matrix().matrix;
+                let ascent = (matrix * Vector2F::new(0.0, metrics.ascent)).y();
+                let descent = (matrix * Vector2F::new(0.0, metrics.descent)).y();
+                (font.size * ascent, font.size * descent.abs())
+            }
+            None => (font.size * 0.8, font.size * 0.2)
         }
     }
 
+    // height and depth of text. the line height of the type is the minimum.
+    fn text_height(ctx: &DrawCtx, font: Font) -> (Length, Length) {
+        let (ascent, descent) = Cache::font_metrics(ctx.storage, font);
+        ((ctx.type_design.line_height - descent).max(ascent), descent)
+    }
+
     fn measure_word_part(&mut self, ctx: &DrawCtx, tag: Tag, font: Font, key: WordId, text: &str, part: WordPart) -> ItemMeasure {
-        let layout = self.word_layout_cache.entry((font, key, part))
-            .or_insert_with(|| {
-                let face = ctx.storage.get_font_face(font.font_face);
-                Cache::build_word_layout(text, face, font.size.value)
-            });
-        let width = layout.advance.x();
+        let start = match part {
+            WordPart::After(idx) => idx as usize,
+            _ => 0
+        };
+        let range = start .. start + text.len();
+        let level = self.item_level(ctx, tag, text, range.clone());
+        let levels = self.part_levels(tag, range).map(|levels| levels.to_vec()).unwrap_or_default();
+
+        // the same word can be in paragraphs of another direction
+        let layout = match self.word_layout_cache.entry((font, key, part)) {
+            Entry::Occupied(e) if e.get().levels == levels => e.into_mut(),
+            Entry::Occupied(mut e) => {
+                e.insert(Cache::build_word_layout(text, &levels, ctx.storage, ctx.design, font));
+                e.into_mut()
+            }
+            Entry::Vacant(e) => e.insert(Cache::build_word_layout(text, &levels, ctx.storage, ctx.design, font))
+        };
+        let content = layout.measure(ctx.type_design.letter_spacing);
+        let (height, depth) = Cache::text_height(ctx, font);
         ItemMeasure {
             left: FlexMeasure::zero(),
-            content: FlexMeasure::fixed(Length::mm(width)),
+            content,
             right: FlexMeasure::zero(),
-            height: ctx.type_design.line_height
+            height,
+            depth,
+            level
         }
     }
 
     fn render_word(&mut self, writer: &mut Writer, ctx: &DrawCtx, tag: Tag, key: WordId) {
-        let dict = ctx.storage.get_dict(ctx.type_design.dictionary);
         let font = ctx.type_design.font;
         let word = ctx.storage.get_word(key);
+        let text = word.display_text();
+        self.render_hyphenated(writer, ctx, tag, &text, word.soft_hyphens(), |cache, text, part| {
+            let measure = cache.measure_word_part(ctx, tag, font, key, text, part);
+            (measure, RenderItem::Word(key, part, font))
+        });
+    }
+
+    // a word with every way to hyphenate it. `item` gives the measure and item of each part.
+    fn render_hyphenated(&mut self, writer: &mut Writer, ctx: &DrawCtx, tag: Tag, text: &str, marks: Vec<usize>,
+        mut item: impl FnMut(&mut Cache, &str, WordPart) -> (ItemMeasure, RenderItem))
+    {
+        let font = ctx.type_design.font;
         let space = Glue::Space {
             measure: ctx.type_design.word_space,
             line_break: Some(0.0),
             column_break: Some(-1.0)
         };
         
-        let hyphen_glue = Glue::Newline {
+        let hyphenation = ctx.type_design.hyphenation;
+
+        // the hyphen carries the penalties, the following part only allows the break
+        let hyphen_glue = Glue::hyphen(hyphenation.penalty);
+        let after_glue = Glue::Newline {
             height: Length::zero(),
-            column_break: None,
-            fill: false
+            column_break: Some(0.0),
+            fill: false,
+            hyphen: None
         };
 
-        let text = &word.text;
-        if let Some(hyphen_id) = ctx.type_design.hyphen {
+        // the hyphen belongs to the word
+        let level = self.item_level(ctx, tag, text, 0 .. text.len());
+
+        // soft hyphens set by the author replace the dictionary and are kept as they are.
+        // breaks of the dictionary keep the minimum lengths.
+        let options = if marks.len() > 0 {
+            Some(Options::manual(text, marks))
+        } else if let Some(dict_id) = ctx.language {
+            let dict = ctx.storage.get_dict(dict_id);
+            let mut options = dict.hyphenate(text, ctx.storage.exceptions());
+            options.limit(hyphenation.left_min, hyphenation.right_min);
+            Some(options)
+        } else {
+            None
+        };
+
+        if let (Some(hyphen_id), Some(options)) = (ctx.type_design.hyphen, options) {
             let hyphen = ctx.storage.get_symbol(hyphen_id);
-            let options = dict.hyphenate(text);
 
             writer.branch2(options.len() + 1, |gen| {
-                let part = WordPart::Full;
-                let measure = self.measure_word_part(ctx, tag, font, key, text, part);
-                gen.add(|writer| writer.item(space, space, measure, RenderItem::Word(key, part, font), tag));
+                let (measure, full) = item(self, text, WordPart::Full);
+                gen.add(|writer| writer.item(space, space, measure, full, tag));
 
                 options.for_each(|index, before, after| {
                     gen.add(|writer| {
-                        let part = WordPart::Before(index as u16);
-                        let measure = self.measure_word_part(ctx, tag, font, key, before, part);
-                        writer.item(space, Glue::None, measure, RenderItem::Word(key, part, font), tag);
+                        let (measure, before) = item(self, before, WordPart::Before(index as u16));
+                        writer.item(space, Glue::None, measure, before, tag);
                         
                         // hyphen
-                        let width = Length::mm(self.symbol_layout(hyphen_id, &ctx.storage, font).advance.x());
+                        let (height, depth) = Cache::text_height(ctx, font);
+                        let width = Length::mm(self.symbol_layout(hyphen_id, ctx.storage, ctx.design, font).advance.x());
                         writer.item(
                             Glue::None,
                             hyphen_glue,
@@ -188,22 +620,81 @@ impl Cache {
                                 left: FlexMeasure::fixed(width * hyphen.overflow_left),
                                 content: FlexMeasure::fixed(width),
                                 right: FlexMeasure::fixed(width * hyphen.overflow_right),
-                                height: ctx.type_design.line_height
+                                height,
+                                depth,
+                                level
                             },
                             RenderItem::Symbol(hyphen_id, font),
                             tag
                         );
 
-                        let part = WordPart::After(index as u16);
-                        let measure = self.measure_word_part(ctx, tag, font, key, after, part);
-                        writer.item(hyphen_glue, space, measure, RenderItem::Word(key, part, font), tag);
+                        let (measure, after) = item(self, after, WordPart::After(index as u16));
+                        writer.item(after_glue, space, measure, after, tag);
                     });
                 });
             });
         } else {
-            let part = WordPart::Full;
-            let measure = self.measure_word_part(ctx, tag, font, key, text, part);
-            writer.item(space, space, measure, RenderItem::Word(key, part, font), tag);
+            let (measure, full) = item(self, text, WordPart::Full);
+            writer.item(space, space, measure, full, tag);
+        }
+    }
+
+    // the first grapheme of the word as a drop cap and the lines next to it shortened
+    fn render_initial(&mut self, writer: &mut Writer, ctx: &DrawCtx, tag: Tag, key: WordId, initial: Initial) {
+        let font = ctx.type_design.font;
+        let word = ctx.storage.get_word(key);
+        let text = word.display_text();
+        let text = &*text;
+        let split = text.grapheme_indices(true).nth(1).map(|(idx, _)| idx).unwrap_or(text.len());
+
+        // from the ascent of the first line to the baseline of the last
+        let lines = initial.lines.max(1);
+        let (height, depth) = Cache::text_height(ctx, font);
+        let (ascent, _) = Cache::font_metrics(ctx.storage, font);
+        let drop = (height + depth) * (lines - 1) as f32;
+        let (unit_ascent, _) = Cache::font_metrics(ctx.storage, Font { font_face: initial.font_face, size: Length::mm(1.0), .. font });
+        let initial_font = Font {
+            font_face: initial.font_face,
+            size: Length::mm((drop + ascent).value / unit_ascent.value),
+            .. font
+        };
+
+        let part = WordPart::Initial(split as u16);
+        let levels = self.part_levels(tag, 0 .. split).map(|levels| levels.to_vec()).unwrap_or_default();
+        let mut layout = Cache::build_word_layout(&text[.. split], &levels, ctx.storage, ctx.design, initial_font);
+        layout.origin = Vector2F::new(0.0, drop.value);
+        let width = Length::mm(layout.advance.x()) + initial.gap;
+        self.word_layout_cache.insert((initial_font, key, part), layout);
+        self.initials.insert(tag, (initial_font, split as u16));
+
+        // it must not make the first line taller
+        let space = Glue::Space {
+            measure: ctx.type_design.word_space,
+            line_break: Some(0.0),
+            column_break: Some(-1.0)
+        };
+        let measure = ItemMeasure {
+            left: FlexMeasure::zero(),
+            content: FlexMeasure::fixed(width),
+            right: FlexMeasure::zero(),
+            height: Length::zero(),
+            depth: Length::zero(),
+            level: self.item_level(ctx, tag, &text[.. split], 0 .. split)
+        };
+        writer.item(space, Glue::None, measure, RenderItem::Word(key, part, initial_font), tag);
+
+        if lines > 1 {
+            let indent = match ctx.direction {
+                Direction::LeftToRight => ctx.indent + width,
+                Direction::RightToLeft => ctx.indent
+            };
+            writer.set_width_for(indent, ctx.width - width, lines - 1);
+        }
+
+        if split < text.len() {
+            let part = WordPart::After(split as u16);
+            let measure = self.measure_word_part(ctx, tag, font, key, &text[split ..], part);
+            writer.item(Glue::None, space, measure, RenderItem::Word(key, part, font), tag);
         }
     }
 
@@ -211,7 +702,8 @@ impl Cache {
         let symbol = ctx.storage.get_symbol(key);
         let font = ctx.type_design.font;
         let space = Glue::Space { measure: ctx.type_design.word_space, line_break: Some(0.0), column_break: Some(0.0) };
-        let width = Length::mm(self.symbol_layout(key, &ctx.storage, font).advance.x());
+        let width = Length::mm(self.symbol_layout(key, ctx.storage, ctx.design, font).advance.x());
+        let (height, depth) = Cache::text_height(ctx, font);
         writer.item(
             select(symbol.trailing, Glue::None, space),
             select(symbol.leading, Glue::None, space),
@@ -219,7 +711,9 @@ impl Cache {
                 left: FlexMeasure::fixed(width * symbol.overflow_left),
                 content: FlexMeasure::fixed(width),
                 right: FlexMeasure::fixed(width * symbol.overflow_right),
-                height: ctx.type_design.line_height
+                height,
+                depth,
+                level: self.item_level(ctx, tag, &symbol.text, 0 .. symbol.text.len())
             },
             RenderItem::Symbol(key, font),
             tag
@@ -239,65 +733,754 @@ impl Cache {
                     typ: ctx.storage.get_weave(tag.seq()).typ()
                 };
 
-                let (width, height) = obj.size(ctx);
+                let (width, height, depth) = obj.size(ctx);
                 let measure = ItemMeasure {
                     left: FlexMeasure::zero(),
                     content: width,
                     right: FlexMeasure::zero(),
-                    height
+                    height,
+                    depth,
+                    level: None
                 };
                 writer.item(Glue::any(), Glue::any(), measure, RenderItem::Object(key), tag);
             }
             Item::Sequence(key) => self.render_sequence(writer, ctx, key),
+            Item::Reference(reference) => self.render_reference(writer, ctx, tag, reference),
+            Item::Citation(key) => self.render_citation(writer, ctx, tag, key),
+
+            // only its position is needed. it sticks to the following item.
+            Item::IndexMark(_) => {
+                let measure = ItemMeasure {
+                    left: FlexMeasure::zero(),
+                    content: FlexMeasure::zero(),
+                    right: FlexMeasure::zero(),
+                    height: Length::zero(),
+                    depth: Length::zero(),
+                    level: None
+                };
+                writer.item(Glue::any(), Glue::None, measure, RenderItem::Empty, tag);
+            }
+        }
+    }
+    // increments the counters of the type. the label of the first one is returned.
+    fn count(&mut self, counters: &[(String, Counter)], seq_id: SequenceId, typ: TypeId) -> Option<usize> {
+        let mut label = None;
+        for (i, (_, counter)) in counters.iter().enumerate() {
+            if counter.types.contains(&typ) {
+                self.counters[i] += 1;
+                if label.is_none() {
+                    self.labels.push(counter.format(self.counters[i]));
+                    label = Some(self.labels.len() - 1);
+                }
+            }
+        }
+        if let Some(label) = label {
+            self.sequence_labels.insert(seq_id, label);
+        }
+        label
+    }
+    // "Figure 3": the text of the label and the number of floats with it so far
+    fn label_float(&mut self, storage: &Storage, seq_id: SequenceId, key: SymbolId) -> usize {
+        let count = self.float_labels.entry(key).or_insert(0);
+        *count += 1;
+        self.labels.push(format!("{} {}", storage.get_symbol(key).text, count));
+        self.sequence_labels.insert(seq_id, self.labels.len() - 1);
+        self.labels.len() - 1
+    }
+    // counters start again within the type. returns the values to restore after it.
+    fn reset_counters(&mut self, counters: &[(String, Counter)], typ: TypeId) -> Vec<(usize, usize)> {
+        let mut saved = vec![];
+        for (i, (_, counter)) in counters.iter().enumerate() {
+            if counter.reset.contains(&typ) {
+                saved.push((i, self.counters[i]));
+                self.counters[i] = 0;
+            }
+        }
+        saved
+    }
+    fn restore_counters(&mut self, saved: Vec<(usize, usize)>) {
+        for (i, value) in saved {
+            self.counters[i] = value;
+        }
+    }
+
+    // "Figure 3"
+    fn render_label(&mut self, writer: &mut Writer, ctx: &DrawCtx, label: usize, tag: Tag) {
+        let text = self.labels[label].clone();
+        self.render_generated(writer, ctx, &text, tag);
+    }
+
+    // the text as of the previous pass
+    fn render_reference(&mut self, writer: &mut Writer, ctx: &DrawCtx, tag: Tag, reference: Reference) {
+        self.references.push(reference);
+        let text = self.resolved.get(&reference).cloned().unwrap_or_else(|| UNRESOLVED.into());
+        match reference.show {
+            // titles can be broken like text
+            Show::Title => for word in text.split_whitespace() {
+                self.render_generated(writer, ctx, word, tag);
+            },
+            _ => self.render_generated(writer, ctx, &text, tag)
+        }
+    }
+
+    // takes up the space left on the line
+    fn render_leader(&mut self, writer: &mut Writer, ctx: &DrawCtx, key: SymbolId, tag: Tag) {
+        let font = ctx.type_design.font;
+        self.symbol_layout(key, ctx.storage, ctx.design, font);
+        let (height, depth) = Cache::text_height(ctx, font);
+        let measure = ItemMeasure {
+            left: FlexMeasure::zero(),
+            content: FlexMeasure {
+                shrink:  Length::zero(),
+                length:  Length::zero(),
+                stretch: ctx.width
+            },
+            right: FlexMeasure::zero(),
+            height,
+            depth,
+            level: None
+        };
+        let space = Glue::nbspace(ctx.type_design.word_space);
+        writer.item(space, space, measure, RenderItem::Leader(key, font), tag);
+    }
+
+    // a line for each entry: title, leader and page number
+    fn render_contents(&mut self, writer: &mut Writer, ctx: &DrawCtx, seq_id: SequenceId, contents: Contents) {
+        let weave = ctx.storage.get_weave(seq_id);
+        let type_design = ctx.design.get_type_or_default(weave.typ());
+        let inner_ctx = DrawCtx {
+            type_design,
+            language: Cache::language(ctx, seq_id, type_design),
+            direction: type_design.direction.unwrap_or(ctx.direction),
+            label: None,
+            .. *ctx
+        };
+        let tag = Tag::Start(seq_id);
+
+        // the leader stretches, the line does not need to be filled
+        let line_end = Glue::Newline {
+            fill: false,
+            height: Length::zero(),
+            column_break: Some(0.0),
+            hyphen: None
+        };
+
+        writer.promote(Glue::hfill(contents.padding.above));
+        for (level, entry) in self.contents.clone() {
+            let indent = contents.indent * level as f32;
+            writer.set_width(ctx.indent + indent, ctx.width - indent);
+
+            let target = Tag::Start(entry);
+            self.render_reference(writer, &inner_ctx, tag, Reference { target, show: Show::Title });
+            self.render_leader(writer, &inner_ctx, contents.leader, tag);
+            self.render_reference(writer, &inner_ctx, tag, Reference { target, show: Show::Page });
+            writer.promote(line_end);
+        }
+        writer.set_width(ctx.indent, ctx.width);
+        writer.promote(Glue::hfill(contents.padding.below));
+    }
+
+    // a line for each term and sub-entry with its pages
+    fn render_index(&mut self, writer: &mut Writer, ctx: &DrawCtx, seq_id: SequenceId, index: Index) {
+        let weave = ctx.storage.get_weave(seq_id);
+        let type_design = ctx.design.get_type_or_default(weave.typ());
+        let inner_ctx = DrawCtx {
+            type_design,
+            language: Cache::language(ctx, seq_id, type_design),
+            direction: type_design.direction.unwrap_or(ctx.direction),
+            label: None,
+            .. *ctx
+        };
+        let tag = Tag::Start(seq_id);
+
+        let lines = self.index_lines(ctx.storage, inner_ctx.language);
+        writer.promote(Glue::hfill(index.padding.above));
+        for &(level, ref text) in lines.iter() {
+            let indent = index.indent * level as f32;
+            writer.set_width(ctx.indent + indent, ctx.width - indent);
+            for word in text.split_whitespace() {
+                self.render_generated(writer, &inner_ctx, word, tag);
+            }
+            writer.promote(Glue::hfill(Length::zero()));
+        }
+        writer.set_width(ctx.indent, ctx.width);
+        writer.promote(Glue::hfill(index.padding.below));
+        self.index = Some((inner_ctx.language, lines));
+    }
+
+    // (level, text) of the lines of the index, with the pages of the last pass
+    // terms are sorted by the rules of the language
+    fn index_lines(&self, storage: &Storage, language: Option<DictId>) -> Vec<(usize, String)> {
+        // page ranges and "see also" of each (term, sub-entry)
+        let mut entries: HashMap<(String, Option<String>), (Vec<(usize, usize)>, Vec<String>)> = HashMap::new();
+        let mut pages = vec![];
+        for &(tag, mark) in self.marks.iter() {
+            let term = storage.get_term(mark.term);
+            if term.sub.is_some() {
+                entries.entry((term.text.clone(), None)).or_default();
+            }
+            let key = (term.text.clone(), term.sub.clone());
+            let entry = entries.entry(key.clone()).or_default();
+            if let Some(ref see_also) = term.see_also {
+                if !entry.1.contains(see_also) {
+                    entry.1.push(see_also.clone());
+                }
+            }
+
+            // marks that were not laid out have no page
+            if let Some(page) = self.page_of(tag) {
+                pages.push((key, mark.range, page + 1));
+            }
+        }
+        // every mark has a term of its own. ranges are paired by the text.
+        for (key, ranges) in Cache::page_ranges(pages) {
+            entries.entry(key).or_default().0.extend(ranges);
+        }
+
+        let collator = collator(language.and_then(|id| storage.language_tag(id)));
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        entries.sort_by(|((text_a, sub_a), _), ((text_b, sub_b), _)| {
+            collator.compare(text_a, text_b).then_with(|| match (sub_a, sub_b) {
+                (Some(a), Some(b)) => collator.compare(a, b),
+                (a, b) => a.is_some().cmp(&b.is_some())
+            })
+        });
+        entries.into_iter().map(|((text, sub), (pages, see_also))| {
+            let level = select(sub.is_some(), 1, 0);
+            let mut line = sub.unwrap_or(text);
+            if pages.len() > 0 {
+                line.push_str(", ");
+                line.push_str(&Cache::format_pages(pages));
+            }
+            if see_also.len() > 0 {
+                line.push_str("; see also ");
+                line.push_str(&see_also.join(", "));
+            }
+            (level, line)
+        }).collect()
+    }
+
+    // the ranges of the marks of each key, in the order of the document.
+    // a begin without an end, or an end without a begin, marks its page alone.
+    fn page_ranges<K: Hash + Eq + Clone>(marks: Vec<(K, MarkRange, usize)>) -> HashMap<K, Vec<(usize, usize)>> {
+        let mut ranges: HashMap<K, Vec<(usize, usize)>> = HashMap::new();
+        let mut open: HashMap<K, usize> = HashMap::new();
+        for (key, range, page) in marks {
+            match range {
+                MarkRange::Point => ranges.entry(key).or_default().push((page, page)),
+                MarkRange::Begin => {
+                    // a begin before the end of the last one stands alone
+                    if let Some(start) = open.insert(key.clone(), page) {
+                        ranges.entry(key).or_default().push((start, start));
+                    }
+                }
+                MarkRange::End => {
+                    let start = open.remove(&key).unwrap_or(page);
+                    ranges.entry(key).or_default().push((start, page));
+                }
+            }
+        }
+        for (key, start) in open {
+            ranges.entry(key).or_default().push((start, start));
+        }
+        ranges
+    }
+
+    // "3, 5–7, 12"
+    fn format_pages(mut ranges: Vec<(usize, usize)>) -> String {
+        ranges.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end))
+            }
+        }
+        let parts: Vec<String> = merged.into_iter().map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}–{}", start, end)
+        }).collect();
+        parts.join(", ")
+    }
+
+    // index marks in the order of the document
+    fn collect_marks(storage: &Storage, key: SequenceId, marks: &mut Vec<(Tag, IndexMark)>) {
+        for (item_id, item) in storage.get_weave(key).items() {
+            match item {
+                Item::IndexMark(mark) => marks.push((Tag::Item(key, item_id), mark)),
+                Item::Sequence(child) => Cache::collect_marks(storage, child, marks),
+                _ => {}
+            }
         }
     }
+
+    // cited keys of the bibliography in the order of the document. missing entries are left out.
+    fn collect_citations(storage: &Storage, key: SequenceId, cited: &mut Vec<String>) {
+        for (_, item) in storage.get_weave(key).items() {
+            match item {
+                Item::Citation(citation) => for key in storage.get_citation(citation).keys.iter() {
+                    if storage.get_bib_entry(key).is_some() && !cited.contains(key) {
+                        cited.push(key.clone());
+                    }
+                },
+                Item::Sequence(child) => Cache::collect_citations(storage, child, cited),
+                _ => {}
+            }
+        }
+    }
+
+    // "(Knuth 1984)" or "[1]"
+    fn render_citation(&mut self, writer: &mut Writer, ctx: &DrawCtx, tag: Tag, key: CitationId) {
+        let citation = ctx.storage.get_citation(key);
+        let entries: Vec<_> = citation.keys.iter().map(|key| {
+            let entry = ctx.storage.get_bib_entry(key)?;
+            let number = self.cited.iter().position(|k| k == key)? + 1;
+            Some((entry, number, self.year_suffixes.get(key).cloned()))
+        }).collect();
+        let text = ctx.design.citation_style().cite(&entries, citation.locator.as_ref().map(|s| s.as_str()));
+        self.render_generated_text(writer, ctx, &text, tag);
+    }
+
+    // the cited entries, sorted by author or numbered in the order they are cited
+    fn render_bibliography(&mut self, writer: &mut Writer, ctx: &DrawCtx, seq_id: SequenceId, bibliography: Bibliography) {
+        let weave = ctx.storage.get_weave(seq_id);
+        let type_design = ctx.design.get_type_or_default(weave.typ());
+        let inner_ctx = DrawCtx {
+            type_design,
+            language: Cache::language(ctx, seq_id, type_design),
+            direction: type_design.direction.unwrap_or(ctx.direction),
+            label: None,
+            .. *ctx
+        };
+        let tag = Tag::Start(seq_id);
+        let style = ctx.design.citation_style();
+
+        let mut entries: Vec<(usize, &BibEntry)> = self.cited.iter().enumerate()
+            .filter_map(|(i, key)| Some((i + 1, ctx.storage.get_bib_entry(key)?)))
+            .collect();
+        if style == CitationStyle::AuthorYear {
+            let collator = collator(inner_ctx.language.and_then(|id| ctx.storage.language_tag(id)));
+            entries.sort_by(|(_, a), (_, b)| collator.compare(&a.sort_text(), &b.sort_text()));
+        }
+
+        writer.promote(Glue::hfill(bibliography.padding.above));
+        for (number, entry) in entries {
+            let text = match style.label(number) {
+                Some(label) => format!("{} {}", label, entry.reference_text(None)),
+                None => entry.reference_text(self.year_suffixes.get(&entry.key).cloned())
+            };
+            self.render_generated_text(writer, &inner_ctx, &text, tag);
+            writer.promote(Glue::hfill(bibliography.gap));
+        }
+        writer.promote(Glue::hfill(bibliography.padding.below));
+    }
+
+    // (level, sequence) of the sequences of the given types in the order of the document
+    fn collect_contents(storage: &Storage, types: &[TypeId], key: SequenceId, entries: &mut Vec<(usize, SequenceId)>) {
+        for (_, item) in storage.get_weave(key).items() {
+            if let Item::Sequence(child) = item {
+                let typ = storage.get_weave(child).typ();
+                if let Some(level) = types.iter().position(|&t| t == typ) {
+                    entries.push((level, child));
+                }
+                Cache::collect_contents(storage, types, child, entries);
+            }
+        }
+    }
+
+    fn render_generated(&mut self, writer: &mut Writer, ctx: &DrawCtx, text: &str, tag: Tag) {
+        let font = ctx.type_design.font;
+        let layout = Cache::build_word_layout(text, &[], ctx.storage, ctx.design, font);
+        let width = Length::mm(layout.advance.x());
+        self.generated.push(layout);
+
+        let space = Glue::Space { measure: ctx.type_design.word_space, line_break: Some(0.0), column_break: Some(-1.0) };
+        let (height, depth) = Cache::text_height(ctx, font);
+        let measure = ItemMeasure {
+            left: FlexMeasure::zero(),
+            content: FlexMeasure::fixed(width),
+            right: FlexMeasure::zero(),
+            height,
+            depth,
+            level: Cache::text_level(ctx, text)
+        };
+        writer.item(space, space, measure, RenderItem::Generated(self.generated.len() - 1), tag);
+    }
+
+    // text that is not in the document, broken into words that can be hyphenated
+    fn render_generated_text(&mut self, writer: &mut Writer, ctx: &DrawCtx, text: &str, tag: Tag) {
+        let font = ctx.type_design.font;
+        for word in text.split_whitespace() {
+            self.render_hyphenated(writer, ctx, tag, word, vec![], |cache, text, _| {
+                let layout = Cache::build_word_layout(text, &[], ctx.storage, ctx.design, font);
+                let width = Length::mm(layout.advance.x());
+                cache.generated.push(layout);
+
+                let (height, depth) = Cache::text_height(ctx, font);
+                let measure = ItemMeasure {
+                    left: FlexMeasure::zero(),
+                    content: FlexMeasure::fixed(width),
+                    right: FlexMeasure::zero(),
+                    height,
+                    depth,
+                    level: Cache::text_level(ctx, text)
+                };
+                (measure, RenderItem::Generated(cache.generated.len() - 1))
+            });
+        }
+    }
+
+    // the float is laid out on its own. the stream only gets the anchor.
+    fn render_float(&mut self, writer: &mut Writer, ctx: &DrawCtx, seq_id: SequenceId, float: Float) {
+        let weave = ctx.storage.get_weave(seq_id);
+        let type_design = ctx.design.get_type_or_default(weave.typ());
+
+        let mut label = match (self.count(ctx.design.counters(), seq_id, weave.typ()), float.label) {
+            (None, Some(key)) => Some(self.label_float(ctx.storage, seq_id, key)),
+            (label, _) => label
+        };
+        let inner_ctx = DrawCtx {
+            type_design,
+            language: Cache::language(ctx, seq_id, type_design),
+            label: None,
+            .. *ctx
+        };
+
+        // floats beside the text are as wide as their widest object
+        let beside = float.placement.is_beside();
+        let object_ctx = || ObjectCtx {
+            storage: ctx.storage,
+            target: ctx.target,
+            design: ctx.design,
+            typ: weave.typ()
+        };
+        let objects: Vec<ObjectId> = weave.items().filter_map(|(_, item)| match item {
+            Item::Object(key) => Some(key),
+            _ => None
+        }).collect();
+        let width = match beside {
+            true => objects.iter()
+                .map(|&key| ctx.storage.get_object(key).size(object_ctx()).0.length)
+                .fold(Length::zero(), |a, b| a.max(b))
+                .min(ctx.width),
+            false => ctx.width
+        };
+        let indent = match float.placement {
+            Placement::Right => ctx.indent + ctx.width - width,
+            _ => ctx.indent
+        };
+
+        let mut float_writer = Writer::new();
+        float_writer.set_width(indent, width);
+
+        let measure = ItemMeasure {
+            left: FlexMeasure::zero(),
+            content: FlexMeasure::zero(),
+            right: FlexMeasure::zero(),
+            height: Length::zero(),
+            depth: Length::zero(),
+            level: None
+        };
+        float_writer.item(Glue::any(), Glue::None, measure, RenderItem::Empty, Tag::Start(seq_id));
+        for (item_id, item) in weave.items() {
+            match item {
+                // the first sequence is the caption
+                Item::Sequence(key) => {
+                    let caption_ctx = DrawCtx { label: label.take(), .. inner_ctx };
+                    self.render_sequence(&mut float_writer, &caption_ctx, key);
+                }
+                _ => self.render_item(&mut float_writer, &inner_ctx, Tag::Item(seq_id, item_id), item)
+            }
+        }
+        if let Some(label) = label {
+            self.render_label(&mut float_writer, &inner_ctx, label, Tag::End(seq_id));
+        }
+        float_writer.item(Glue::None, Glue::any(), measure, RenderItem::Empty, Tag::End(seq_id));
+
+        let layout = ParagraphLayout::new(float_writer.finish(), width);
+        let height = float.padding.above + layout.height() + float.padding.below;
+        self.floats.push((layout, float.padding.above));
+        writer.float(self.floats.len() - 1, height, float.placement);
+
+        if beside && width > Length::zero() {
+            let (step, widths) = Cache::wrap_widths(ctx, float, width, height, objects.first().map(|&key| {
+                let object = ctx.storage.get_object(key);
+                let (w, h, _) = object.size(object_ctx());
+                (object, Vector2F::new(w.length.value, h.value))
+            }));
+            writer.wrap(height, step, widths);
+        }
+    }
+
+    // height of a band and the indent and width of the lines in each band next to a float
+    // beside the text. bands are one line of the enclosing type high. the lines keep their
+    // own heights, and take the narrowest of the bands they reach into.
+    fn wrap_widths(ctx: &DrawCtx, float: Float, width: Length, height: Length, object: Option<(&Object, Vector2F)>) -> (Length, Vec<(Length, Length)>) {
+        let (line_height, line_depth) = Cache::text_height(ctx, ctx.type_design.font);
+        let pitch = line_height + line_depth;
+        let n = (height / pitch).ceil() as usize;
+
+        // relative to the top of the object
+        let bands: Vec<(f32, f32)> = (0 .. n).map(|i| (
+            (pitch * i as f32 - float.padding.above).value,
+            (pitch * (i + 1) as f32 - float.padding.above).value
+        )).collect();
+        let contour = match object {
+            Some((object, size)) if float.contour => object.contour(size, &bands).map(|c| (c, size)),
+            _ => None
+        };
+
+        let widths = (0 .. n).map(|i| {
+            // how much of the column the float takes in this band
+            let taken = match contour {
+                Some((ref extents, size)) if bands[i].0 < size.y() => match (extents[i], float.placement) {
+                    (Some((_, right)), Placement::Left) => Length::mm(right),
+                    (Some((left, _)), _) => width - Length::mm(left),
+                    (None, _) => Length::zero()
+                },
+                _ => width
+            };
+            if taken <= Length::zero() {
+                return (ctx.indent, ctx.width);
+            }
+            let taken = taken + float.gap;
+            match float.placement {
+                Placement::Left => (ctx.indent + taken, ctx.width - taken),
+                _ => (ctx.indent, ctx.width - taken)
+            }
+        }).collect();
+        (pitch, widths)
+    }
+
+    // every row is one item as tall as its tallest cell. the cells are laid out on their own.
+    fn render_table(&mut self, writer: &mut Writer, ctx: &DrawCtx, seq_id: SequenceId, table: Table) {
+        let weave = ctx.storage.get_weave(seq_id);
+        let type_design = ctx.design.get_type_or_default(weave.typ());
+
+        // cells start at the left of the table. they are moved into their column later.
+        let cell_ctx = DrawCtx {
+            type_design,
+            language: Cache::language(ctx, seq_id, type_design),
+            direction: type_design.direction.unwrap_or(ctx.direction),
+            indent: Length::zero(),
+            label: None,
+            .. *ctx
+        };
+        let mut rows: Vec<(SequenceId, Vec<StreamVec>)> = Vec::new();
+        for (_, item) in weave.items() {
+            let row_id = match item {
+                Item::Sequence(key) => key,
+                _ => continue
+            };
+            let mut cells = Vec::new();
+            for (_, item) in ctx.storage.get_weave(row_id).items() {
+                if let Item::Sequence(cell_id) = item {
+                    let mut cell_writer = Writer::new();
+                    self.render_sequence(&mut cell_writer, &cell_ctx, cell_id);
+                    cells.push(cell_writer.finish());
+                }
+            }
+            rows.push((row_id, cells));
+        }
+
+        // (widest item, natural width) of each column
+        let num_columns = rows.iter().map(|(_, cells)| cells.len()).max().unwrap_or(0);
+        let mut content = vec![(Length::zero(), Length::zero()); num_columns];
+        for (_, cells) in rows.iter() {
+            for (column, cell) in content.iter_mut().zip(cells) {
+                let (widest, natural) = cell.natural_widths();
+                *column = (column.0.max(widest), column.1.max(natural));
+            }
+        }
+        let gaps = table.column_gap * num_columns.saturating_sub(1) as f32;
+        let widths = Cache::column_widths(ctx.width - gaps, ctx.design.column_widths(weave.typ()), &content);
+        let mut offsets = Vec::with_capacity(num_columns);
+        let mut x = ctx.indent;
+        for &width in widths.iter() {
+            offsets.push(x);
+            x += width + table.column_gap;
+        }
+        let table_width = widths.iter().fold(gaps, |sum, &width| sum + width);
+
+        let header_rows = (table.header_rows as usize).min(rows.len());
+        let first_row = self.rows.len();
+        let mut header_height = Length::zero();
+
+        writer.promote(Glue::hfill(table.padding.above));
+        for (n, (row_id, cells)) in rows.into_iter().enumerate() {
+            let cells: Vec<(Length, ParagraphLayout)> = cells.into_iter().enumerate()
+                .map(|(i, cell)| (offsets[i], ParagraphLayout::new(cell, widths[i])))
+                .collect();
+            let height = cells.iter().fold(Length::zero(), |h, (_, cell)| h.max(cell.height()));
+            self.rows.push(Row { cells, height, gap: table.row_gap });
+
+            if n < header_rows {
+                header_height += height + table.row_gap;
+            } else if header_rows > 0 {
+                writer.header(first_row, header_rows, header_height);
+            }
+
+            let measure = ItemMeasure {
+                left: FlexMeasure::zero(),
+                content: FlexMeasure::fixed(table_width),
+                right: FlexMeasure::zero(),
+                height,
+                depth: Length::zero(),
+                level: None
+            };
+            writer.item(Glue::hfill(Length::zero()), Glue::hfill(table.row_gap), measure, RenderItem::Row(self.rows.len() - 1), Tag::Start(row_id));
+        }
+        writer.promote(Glue::hfill(table.padding.below));
+    }
+
+    // fixed columns keep their width. the others get their natural width if that fits,
+    // and otherwise their widest item and a share of the rest.
+    fn column_widths(available: Length, fixed: &[Option<Length>], content: &[(Length, Length)]) -> Vec<Length> {
+        let fixed_at = |i: usize| fixed.get(i).cloned().unwrap_or(None);
+        let mut remaining = available;
+        let mut widest_sum = Length::zero();
+        let mut natural_sum = Length::zero();
+        for (i, &(widest, natural)) in content.iter().enumerate() {
+            match fixed_at(i) {
+                Some(width) => remaining -= width,
+                None => {
+                    widest_sum += widest;
+                    natural_sum += natural;
+                }
+            }
+        }
+        let f = if natural_sum <= remaining {
+            1.0
+        } else if remaining > widest_sum {
+            (remaining - widest_sum) / (natural_sum - widest_sum)
+        } else {
+            0.0
+        };
+        content.iter().enumerate()
+            .map(|(i, &(widest, natural))| fixed_at(i).unwrap_or(widest + (natural - widest) * f))
+            .collect()
+    }
+
     fn render_sequence(&mut self, writer: &mut Writer, ctx: &DrawCtx, seq_id: SequenceId) {
         let weave = ctx.storage.get_weave(seq_id);
         let type_design = ctx.design.get_type_or_default(weave.typ());
 
         match type_design.display {
+            Display::Float(float) => return self.render_float(writer, ctx, seq_id, float),
+            Display::Table(table) => return self.render_table(writer, ctx, seq_id, table),
+            Display::Contents(contents) => return self.render_contents(writer, ctx, seq_id, contents),
+            Display::Index(index) => return self.render_index(writer, ctx, seq_id, index),
+            Display::Bibliography(bibliography) => return self.render_bibliography(writer, ctx, seq_id, bibliography),
             Display::Block(v) => writer.promote(Glue::hfill(v.above)),
-            Display::Paragraph(indent, v) => writer.space(Glue::hfill(v.above), Glue::None, FlexMeasure::fixed(indent), None, None),
+            Display::Paragraph(indent, v) => {
+                // no indent next to a drop cap
+                let indent = select(type_design.initial.is_some(), Length::zero(), indent);
+                writer.space(Glue::hfill(v.above), Glue::None, FlexMeasure::fixed(indent), None, None)
+            }
             _ => {}
         }
+        // a caption shows the label of its float
+        let own_label = self.count(ctx.design.counters(), seq_id, weave.typ());
+        let label = ctx.label.or(own_label);
+        let saved = self.reset_counters(ctx.design.counters(), weave.typ());
+
         let inner_ctx = DrawCtx {
             type_design,
+            language: Cache::language(ctx, seq_id, type_design),
+            direction: type_design.direction.unwrap_or(ctx.direction),
             width: ctx.width - type_design.indent * 2.0,
             indent: ctx.indent + type_design.indent,
+            label: None,
             .. *ctx
         };
         if !type_design.indent.is_zero() {
             writer.set_width(inner_ctx.indent, inner_ctx.width);
         }
+        if inner_ctx.direction != ctx.direction {
+            writer.set_direction(inner_ctx.direction);
+        }
+        match type_design.display {
+            Display::Inline => {}
+            _ => self.resolve_bidi(ctx.storage, ctx.design, seq_id, inner_ctx.direction)
+        }
 
         let measure = ItemMeasure {
             left: FlexMeasure::zero(),
             content: FlexMeasure::zero(),
             right: FlexMeasure::zero(),
-            height: Length::zero()
+            height: Length::zero(),
+            depth: Length::zero(),
+            level: None
         };
         writer.item(Glue::any(), Glue::None, measure, RenderItem::Empty, Tag::Start(seq_id));
-        for (item_id, item) in weave.items() {
-            self.render_item(writer, &inner_ctx, Tag::Item(seq_id, item_id), item);
+        if let Some(label) = label {
+            self.render_label(writer, &inner_ctx, label, Tag::Start(seq_id));
+        }
+        for (n, (item_id, item)) in weave.items().enumerate() {
+            let tag = Tag::Item(seq_id, item_id);
+            match (n, item, type_design.initial) {
+                (0, Item::Word(key), Some(initial)) => self.render_initial(writer, &inner_ctx, tag, key, initial),
+                _ => self.render_item(writer, &inner_ctx, tag, item)
+            }
         }
         writer.item(Glue::None, Glue::any(), measure, RenderItem::Empty, Tag::End(seq_id));
+        self.restore_counters(saved);
 
         match type_design.display {
             Display::Block(v) | Display::Paragraph(_, v) => writer.promote(Glue::hfill(v.below)),
             _ => {}
         }
-        if !type_design.indent.is_zero() {
+        // also ends the lines shortened by a drop cap
+        if !type_design.indent.is_zero() || type_design.initial.is_some() {
             writer.set_width(ctx.indent, ctx.width);
         }
+        if inner_ctx.direction != ctx.direction {
+            writer.set_direction(ctx.direction);
+        }
     }
 
+    /// references show the values of the previous pass.
+    /// the layout is repeated until they no longer change.
     pub fn layout(&mut self, storage: &Storage, design: &Design, target: &Target, root: SequenceId) {
+        for _ in 0 .. MAX_LAYOUT_PASSES {
+            self.layout_pass(storage, design, target, root);
+            if !self.resolve_generated(storage) {
+                break;
+            }
+        }
+    }
+
+    fn layout_pass(&mut self, storage: &Storage, design: &Design, target: &Target, root: SequenceId) {
         let t0 = Instant::now();
 
         let mut stream = self.columns.take().map(|columns| columns.into_stream()).unwrap_or_default();
         stream.clear();
 
         let mut writer = Writer::with_stream(stream);
+        if self.fallback != design.fallback() {
+            self.word_layout_cache.clear();
+            self.symbol_layout_cache.clear();
+            self.fallback = design.fallback().to_vec();
+        }
+        self.initials.clear();
+        self.levels.clear();
+        self.floats.clear();
+        self.counters = vec![0; design.counters().len()];
+        self.labels.clear();
+        self.sequence_labels.clear();
+        self.float_labels.clear();
+        self.references.clear();
+        self.rows.clear();
+        self.contents.clear();
+        Cache::collect_contents(storage, design.contents_types(), root, &mut self.contents);
+        self.marks.clear();
+        self.index = None;
+        Cache::collect_marks(storage, root, &mut self.marks);
+        self.cited.clear();
+        Cache::collect_citations(storage, root, &mut self.cited);
+        let cited: Vec<&BibEntry> = self.cited.iter().filter_map(|key| storage.get_bib_entry(key)).collect();
+        self.year_suffixes = year_suffixes(&cited);
+        self.generated.clear();
 
         let type_design = design.default();
         let ctx = DrawCtx {
@@ -305,15 +1488,19 @@ impl Cache {
             design,
             target,
             type_design,
+            language: storage.get_language(root).or(type_design.language),
+            direction: type_design.direction.unwrap_or_default(),
             width: target.content_box.width - type_design.indent * 2.0,
-            indent: type_design.indent
+            indent: type_design.indent,
+            label: None
         };
+        self.resolve_bidi(storage, design, root, ctx.direction);
         self.render_sequence(&mut writer, &ctx, root);
         let stream = writer.finish();
 
         let t1 = Instant::now();
 
-        let layout = ColumnLayout::new(stream, target.content_box.width, target.content_box.height);
+        let layout = ColumnLayout::new(stream, target.content_box.width, target.content_box.height, design.baseline_grid());
         self.columns = Some(layout.columns());
 
         let t2 = Instant::now();
@@ -328,45 +1515,99 @@ impl Cache {
         info!("layout: location map: {}ms", (t3 - t2).as_millis());
     }
 
+    // the text of the references and of the index rendered in the last pass.
+    // true if any of them changed.
+    fn resolve_generated(&mut self, storage: &Storage) -> bool {
+        let mut changed = match self.index.take() {
+            Some((language, lines)) => lines != self.index_lines(storage, language),
+            None => false
+        };
+        // only the references still in the document are kept
+        let mut resolved = HashMap::new();
+        for reference in std::mem::replace(&mut self.references, vec![]) {
+            let text = self.reference_text(storage, reference).unwrap_or_else(|| UNRESOLVED.into());
+            if self.resolved.get(&reference) != Some(&text) {
+                changed = true;
+            }
+            resolved.insert(reference, text);
+        }
+        self.resolved = resolved;
+        changed
+    }
+    fn reference_text(&self, storage: &Storage, reference: Reference) -> Option<String> {
+        // a sequence is found by its start
+        let target = match reference.target {
+            Tag::Item(..) => match storage.get_item(reference.target)? {
+                Item::Sequence(key) => Tag::Start(key),
+                _ => reference.target
+            },
+            tag => tag
+        };
+
+        // the target was deleted if it was not laid out
+        let page = self.page_of(target)?;
+        match reference.show {
+            Show::Counter => self.sequence_labels.get(&target.seq()).map(|&label| self.labels[label].clone()),
+            Show::Page => Some((page + 1).to_string()),
+            Show::Title => Some(Cache::sequence_text(storage, target.seq()))
+        }
+    }
+    fn page_of(&self, tag: Tag) -> Option<usize> {
+        if let Some(&(page_nr, _)) = self.positions.get(&tag) {
+            return Some(page_nr as usize);
+        }
+        match *self.word_positions.get(&tag)? {
+            RenderedWord::Full((page_nr, _)) |
+            RenderedWord::Before((page_nr, _), _) |
+            RenderedWord::After((page_nr, _), _) |
+            RenderedWord::Both((page_nr, _), _, _) => Some(page_nr as usize)
+        }
+    }
+    // words and symbols of the sequence and of those within, separated by spaces
+    pub fn sequence_text(storage: &Storage, key: SequenceId) -> String {
+        let mut parts = vec![];
+        for (_, item) in storage.get_weave(key).items() {
+            match item {
+                Item::Word(key) => parts.push(storage.get_word(key).display_text().into()),
+                Item::Symbol(key) => parts.push(storage.get_symbol(key).text.clone()),
+                Item::Sequence(key) => parts.push(Cache::sequence_text(storage, key)),
+                _ => {}
+            }
+        }
+        parts.join(" ")
+    }
+
+    /// (level, sequence) of the sequences of the types in Design::contents_types, in the order of the document
+    pub fn contents(&self) -> &[(usize, SequenceId)] {
+        &self.contents
+    }
+
     fn build_locations(&mut self, target: &Target) {
         let content_box: RectF = target.content_box.into();
 
         let columns = self.columns.as_ref().unwrap();
+        let layouts = &self.word_layout_cache;
+        let floats = &self.floats;
+        let rows = &self.rows;
         let positions = &mut self.positions;
         let word_positions = &mut self.word_positions;
         positions.clear();
         word_positions.clear();
 
         for (page_nr, column) in columns.columns().enumerate() {
-            for (y, line) in column {
+            // repeated table headers are not where their words are
+            for (y, line) in Cache::place_lines(floats, rows, column, false) {
                 for (x, size, item, tag) in line {
                     let size: Vector2F = size.into();
                     let p = content_box.origin() + Vector2F::new(x.value as f32, y.value as f32);
                     let rect = (page_nr as u32, RectF::new(p - Vector2F::new(0.0, size.y()), size));
                     match item {
                         RenderItem::Word(key, part, font) => {
-                            use std::collections::hash_map::Entry;
-                            match (part, word_positions.entry(tag)) {
-                                (WordPart::Full, Entry::Vacant(e)) => {
-                                    e.insert(RenderedWord::Full(rect));
-                                }
-                                (WordPart::Before(idx), Entry::Vacant(e)) => {
-                                    e.insert(RenderedWord::Before(rect, idx));
-                                }
-                                (WordPart::After(idx), Entry::Vacant(e)) => {
-                                    e.insert(RenderedWord::After(rect, idx));
-                                }
-                                (WordPart::After(idx), Entry::Occupied(mut e)) => {
-                                    match *e.get() {
-                                        RenderedWord::Before(prev_rect, idx2) => {
-                                            assert_eq!(idx, idx2);
-                                            e.insert(RenderedWord::Both(prev_rect, rect, idx));
-                                        }
-                                        _ => panic!("invalid state")
-                                    }
-                                },
-                                p => panic!("{:?}", p),
-                            }
+                            let rect = match part {
+                                WordPart::Initial(_) => (rect.0, layouts[&(font, key, part)].initial_rect(p, font)),
+                                _ => rect
+                            };
+                            RenderedWord::place(word_positions, tag, part, rect);
                         }
                         RenderItem::Symbol(key, font) => {
                             positions.insert(tag, rect);
@@ -374,6 +1615,8 @@ impl Cache {
                         RenderItem::Object(key) => {
                             positions.insert(tag, rect);
                         }
+                        RenderItem::Generated(_) | RenderItem::Leader(..) => {}
+                        RenderItem::Row(_) |
                         RenderItem::Empty => {
                             positions.insert(tag, rect);
                         }
@@ -390,67 +1633,68 @@ impl Cache {
         scene.set_bounds(target.media_box.into());
         scene.set_view_box(target.trim_box.into());
 
+        let (r, g, b) = target.page_color.to_rgb8();
         let page_style = scene.build_style(PathStyle {
-            fill: Some(Paint::white()),
+            fill: Some(Paint::Solid((r, g, b, 255))),
             stroke: Some((Paint::black(), 0.25)),
             fill_rule: FillRule::NonZero
         });
-        let glyph_style = scene.build_style(PathStyle {
-            fill: Some(Paint::black()),
-            stroke: None,
-            fill_rule: FillRule::NonZero
-        });
+        // the glyphs of each color of the types
+        let mut glyph_styles: Vec<(Color, _)> = vec![];
         let mut pb = PathBuilder::new();
         pb.rect(target.trim_box.into());
         
         scene.draw_path(pb.into_outline(), &page_style, None);
 
         let mut line_indices = Vec::new();
+        let mut boxes = Vec::new();
         let mut positions = HashMap::new();
         let mut word_positions = HashMap::new();
 
         let content_box: RectF = target.content_box.into();
-        for (y, line) in column {
+        for (y, line) in self.column_lines(column) {
             let mut line_items = Vec::new();
             for (x, size, item, tag) in line {
                 let size: Vector2F = size.into();
                 let p = content_box.origin() + Vector2F::new(x.value as f32, y.value as f32);
                 let rect = RectF::new(p - Vector2F::new(0.0, size.y()), size);
+                let color = &design.get_type_or_default(storage.get_weave(tag.seq()).typ()).color;
+                let n = match glyph_styles.iter().position(|(c, _)| c == color) {
+                    Some(n) => n,
+                    None => {
+                        let (r, g, b) = color.to_rgb8();
+                        let style = scene.build_style(PathStyle {
+                            fill: Some(Paint::Solid((r, g, b, 255))),
+                            stroke: None,
+                            fill_rule: FillRule::NonZero
+                        });
+                        glyph_styles.push((color.clone(), style));
+                        glyph_styles.len() - 1
+                    }
+                };
+                let glyph_style = &glyph_styles[n].1;
                 match item {
                     RenderItem::Word(key, part, font) => {
                         let layout = self.word_layout_cache.get(&(font, key, part)).unwrap();
-                        let font = storage.get_font_face(font.font_face);
-                        let outline = layout.render(font, Transform2F::from_translation(p));
-                        scene.draw_path(outline, &glyph_style, None);
-
-                        use std::collections::hash_map::Entry;
-                        match (part, word_positions.entry(tag)) {
-                            (WordPart::Full, Entry::Vacant(e)) => {
-                                e.insert(RenderedWord::Full(rect));
-                            }
-                            (WordPart::Before(idx), Entry::Vacant(e)) => {
-                                e.insert(RenderedWord::Before(rect, idx));
-                            }
-                            (WordPart::After(idx), Entry::Vacant(e)) => {
-                                e.insert(RenderedWord::After(rect, idx));
+                        let spacing = layout.spacing_for(size.x());
+                        let outline = layout.render(storage, Transform2F::from_translation(p), spacing);
+                        scene.draw_path(outline, glyph_style, None);
+
+                        let rect = match part {
+                            WordPart::Initial(_) => {
+                                let rect = layout.initial_rect(p, font);
+                                boxes.push((rect, tag));
+                                rect
                             }
-                            (WordPart::After(idx), Entry::Occupied(mut e)) => {
-                                match *e.get() {
-                                    RenderedWord::Before(prev_rect, idx2) => {
-                                        assert_eq!(idx, idx2);
-                                        e.insert(RenderedWord::Both(prev_rect, rect, idx));
-                                    }
-                                    _ => panic!("invalid state")
-                                }
-                            },
-                            _ => panic!()
-                        }
+                            _ => rect
+                        };
+
+                        RenderedWord::place(&mut word_positions, tag, part, rect);
                     }
                     RenderItem::Symbol(key, font) => {
                         let layout = self.symbol_layout_cache.get(&(font, key)).unwrap();
-                        let font = storage.get_font_face(font.font_face);
-                        let outline = layout.render(font, Transform2F::from_translation(p));
-                        scene.draw_path(outline, &glyph_style, None);
+                        let outline = layout.render(storage, Transform2F::from_translation(p), 0.0);
+                        scene.draw_path(outline, glyph_style, None);
                         positions.insert(tag, rect);
                     }
                     RenderItem::Object(key) => {
@@ -462,15 +1706,70 @@ impl Cache {
                         };
                         storage.get_object(key).draw(ctx, p, size.into(), &mut scene);
                     }
+                    RenderItem::Generated(index) => {
+                        let outline = self.generated[index].render(storage, Transform2F::from_translation(p), 0.0);
+                        scene.draw_path(outline, glyph_style, None);
+                    }
+                    RenderItem::Leader(key, font) => {
+                        let layout = self.symbol_layout_cache.get(&(font, key)).unwrap();
+                        for x in layout.repeat(p.x(), size.x()) {
+                            let outline = layout.render(storage, Transform2F::from_translation(Vector2F::new(x, p.y())), 0.0);
+                            scene.draw_path(outline, glyph_style, None);
+                        }
+                    }
+                    RenderItem::Row(_) |
                     RenderItem::Empty => {
                     }
                 };
-                line_items.push((p.x(), tag));
+
+                // drop caps are found by their box
+                match item {
+                    RenderItem::Word(_, WordPart::Initial(_), _) => {}
+                    _ => line_items.push((p.x(), tag))
+                }
             }
             line_indices.push((y.value + content_box.origin().y(), line_items));
         }
 
-        Page { scene, items: line_indices }
+        Page { scene, items: line_indices, boxes }
+    }
+
+    /// the lines of the column, of the floats and of the table cells in it, from top to bottom
+    pub fn column_lines(&self, column: Column) -> Vec<(Length, Line)> {
+        Cache::place_lines(&self.floats, &self.rows, column, true)
+    }
+    fn place_lines(floats: &[(ParagraphLayout, Length)], rows: &[Row], mut column: Column, header: bool) -> Vec<(Length, Line)> {
+        // floats beside the text are only known once their line is reached
+        let mut lines: Vec<_> = column.by_ref().collect();
+        for &(top, index) in column.floats() {
+            let (ref layout, above) = floats[index];
+            lines.extend(layout.lines().map(|(y, line)| (top + above + y, line)));
+        }
+
+        // (top, index) of the table rows
+        let mut placed = vec![];
+        if let (Some((top, first, count)), true) = (column.header(), header) {
+            let mut y = top;
+            for index in first .. first + count {
+                placed.push((y, index));
+                y += rows[index].height + rows[index].gap;
+            }
+        }
+        for &(y, ref line) in lines.iter() {
+            for &(_, _, item, _) in line.items() {
+                if let RenderItem::Row(index) = item {
+                    placed.push((y - rows[index].height, index));
+                }
+            }
+        }
+        for (top, index) in placed {
+            for &(x, ref cell) in rows[index].cells.iter() {
+                lines.extend(cell.lines().map(|(y, line)| (top + y, line.shift(x))));
+            }
+        }
+
+        lines.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        lines
     }
 
     pub fn get_position(&self, storage: &Storage, design: &Design, tag: Tag, byte_pos: usize) -> Option<(usize, Vector2F)> {
@@ -479,7 +1778,7 @@ impl Cache {
                 let seq = storage.get_weave(tag.seq());
                 let type_design = design.get_type_or_default(seq.typ());
                 let word = storage.get_word(key);
-                let face = storage.get_font_face(type_design.font.font_face);
+                let byte_pos = word.to_display_pos(byte_pos);
 
                 let (page_nr, off, rect, part) = match *self.word_positions.get(&tag)? {
                     RenderedWord::Full((n, rect)) => (n, 0, rect, WordPart::Full),
@@ -493,27 +1792,10 @@ impl Cache {
                 }
                 let byte_pos = byte_pos - off;
 
-                if byte_pos == 0 {
-                    return Some((page_nr as usize, rect.lower_left()));
-                }
-                let grapheme_indices = grapheme_indices(face, &word.text);
-                let layout = self.word_layout_cache.get(&(type_design.font, key, part)).unwrap();
-
-                let interpolate = |(idx_a, pos_a), (idx_b, pos_b), idx| -> Vector2F {
-                    if idx == idx_b { return pos_b; }
-                    let f = (idx - idx_a) as f32 / (idx_b - idx_a) as f32;
-                    pos_a + (pos_b - pos_a).scale(f)
-                };
-
-                let mut last = (0, Vector2F::default());
-                for (&n, &(_gid, offset)) in grapheme_indices.iter().zip(layout.glyphs.iter()) {
-                    if n >= byte_pos {
-                        return Some((page_nr as usize, rect.lower_left() + interpolate(last, (n, offset.vector,), byte_pos)));
-                    }
-                    last = (n, offset.vector);
-                }
-
-                Some((page_nr as usize, rect.lower_left() + interpolate(last, (word.text.len(), layout.advance), byte_pos)))
+                let (font, part) = self.part_font(tag, type_design.font, part);
+                let layout = self.word_layout_cache.get(&(font, key, part))?;
+                let spacing = layout.spacing_for(rect.width());
+                Some((page_nr as usize, rect.lower_left() + layout.caret(byte_pos, spacing)))
             }
             Item::Symbol(_) => {
                 let &(page_nr, rect) = self.positions.get(&tag)?;
@@ -527,61 +1809,202 @@ impl Cache {
             _ => None
         }
     }
-    pub fn find(&self, storage: &Storage, design: &Design, offset: f32, tag: Tag) -> Option<(Vector2F, usize)> {
+    // drop caps have their own font
+    fn part_font(&self, tag: Tag, font: Font, part: WordPart) -> (Font, WordPart) {
+        match (part, self.initials.get(&tag)) {
+            (WordPart::Before(idx), Some(&(initial, split))) if idx == split => (initial, WordPart::Initial(idx)),
+            _ => (font, part)
+        }
+    }
+
+    /// the caret position and text position closest to `p` within the word at `tag` on page `page_nr`
+    pub fn find(&self, storage: &Storage, design: &Design, tag: Tag, page_nr: usize, p: Vector2F) -> Option<(Vector2F, usize)> {
         if let Some(Item::Word(key)) = storage.get_item(tag) {
             let seq = storage.get_weave(tag.seq());
             let type_design = design.get_type_or_default(seq.typ());
             let word = storage.get_word(key);
-            let face = storage.get_font_face(type_design.font.font_face);
-            let grapheme_indices = grapheme_indices(face, &word.text);
 
-            let layout = self.word_layout_cache.get(&(type_design.font, key, WordPart::Full)).unwrap();
+            // (rect, offset into the text, part) of each part of the word
+            let parts = match *self.word_positions.get(&tag)? {
+                RenderedWord::Full(r) => vec![(r, 0, WordPart::Full)],
+                RenderedWord::Before(r, idx) => vec![(r, 0, WordPart::Before(idx))],
+                RenderedWord::After(r, idx) => vec![(r, idx as usize, WordPart::After(idx))],
+                RenderedWord::Both(r1, r2, idx) => vec![(r1, 0, WordPart::Before(idx)), (r2, idx as usize, WordPart::After(idx))]
+            };
+            let distance = |rect: RectF| {
+                let d = (rect.origin() - p).max(p - rect.lower_right()).max(Vector2F::default());
+                d.x() + d.y()
+            };
+            let (rect, off, part) = parts.into_iter()
+                .filter(|&((n, _), _, _)| n as usize == page_nr)
+                .map(|((_, rect), off, part)| (rect, off, part))
+                .min_by(|a, b| distance(a.0).partial_cmp(&distance(b.0)).unwrap())?;
 
-            if offset >= layout.advance.x() {
-                return Some((layout.advance, word.text.len()));
-            }
-            for (&n, (_gid, tr)) in grapheme_indices.iter().rev().zip(layout.glyphs.iter().rev()) {
-                if tr.vector.x() < offset {
-                    println!("{}|{}", &word.text[0..n], &word.text[n..]);
-                    return Some((tr.vector, n));
-                }
-            }
+            let (font, part) = self.part_font(tag, type_design.font, part);
+            let layout = self.word_layout_cache.get(&(font, key, part))?;
+            let spacing = layout.spacing_for(rect.width());
+            let (pos, n) = layout.hit(p.x() - rect.min_x(), spacing);
+            return Some((rect.lower_left() + pos, word.to_text_pos(n + off)));
         }
         None
     }
-    fn symbol_layout(&mut self, symbol: SymbolId, storage: &Storage, font: Font) -> &Layout {
+    fn symbol_layout(&mut self, symbol: SymbolId, storage: &Storage, design: &Design, font: Font) -> &Layout {
         self.symbol_layout_cache.entry((font, symbol))
             .or_insert_with(|| {
                 let text = &storage.get_symbol(symbol).text;
-                let face = storage.get_font_face(font.font_face);
-                Cache::build_word_layout(text, face, font.size.value)
+                Cache::build_word_layout(text, &[], storage, design, font)
             })
     }
 
-    fn build_word_layout(text: &str, face: &FontFace, size: f32) -> Layout {
-        let mut last_gid = None;
-        let gids = build_gids(face, text);
+    // `levels` are the embedding levels of the bytes of the text. without them
+    // the direction of the first strong character is used for all of it.
+    fn build_word_layout(text: &str, levels: &[u8], storage: &Storage, design: &Design, font: Font) -> Layout {
+        let mut face_ids = vec![font.font_face];
+        face_ids.extend(design.fallback().iter().filter(|&&id| id != font.font_face));
+        let faces: Vec<&FontFace> = face_ids.iter().map(|&id| storage.get_font_face(id)).collect();
+
+        // runs of one face and one level, in visual order
+        let (rtl, runs) = match levels.len() == text.len() {
+            true => {
+                let mut runs = vec![];
+                for (face_nr, range) in font_runs(&faces, text) {
+                    let mut start = range.start;
+                    for idx in range.clone().filter(|&idx| text.is_char_boundary(idx)) {
+                        if levels[idx] != levels[start] {
+                            runs.push((face_nr, start .. idx, Some(levels[start])));
+                            start = idx;
+                        }
+                    }
+                    runs.push((face_nr, start .. range.end, Some(levels[start])));
+                }
+                let run_levels: Vec<u8> = runs.iter().map(|r| r.2.unwrap_or(0)).collect();
+                let order = visual_order(&run_levels);
+                let lowest = run_levels.iter().cloned().min().unwrap_or(0);
+                (lowest % 2 == 1, order.into_iter().map(|n| runs[n].clone()).collect())
+            }
+            false => {
+                let rtl = strong_direction(text) == Some(Direction::RightToLeft);
+                let mut runs: Vec<_> = font_runs(&faces, text).into_iter().map(|(face_nr, range)| (face_nr, range, None)).collect();
+                if rtl {
+                    runs.reverse();
+                }
+                (rtl, runs)
+            }
+        };
+
+        // synthetic styles
+        let skew = Transform2F::row_major(1.0, font.synthetic.oblique, 0.0, 1.0, 0.0, 0.0);
+        let embolden = font.synthetic.embolden * font.size.value;
+        let axes = font.variations.axes(font.size);
 
-        let transform = Transform2F::from_scale(Vector2F::splat(size))
-         * Transform2F::from_scale(Vector2F::new(1.0, -1.0))
-         * face.font_matrix();
-        
         let mut offset = Vector2F::default();
-        let mut glyphs = Vec::with_capacity(gids.len());
-        for &gid in gids.iter() {
-            if let Some(glyph) = face.glyph(gid) {
-                if let Some(left) = last_gid.replace(gid) {
-                    offset = offset + Vector2F::new(face.kerning(left, gid), 0.0);
+        let mut glyphs = Vec::with_capacity(text.len());
+        let mut clusters: Vec<(usize, Vector2F)> = Vec::with_capacity(text.len());
+        let mut outlines = HashMap::new();
+        for (face_nr, range, level) in runs {
+            let face = faces[face_nr];
+            let face_id = face_ids[face_nr];
+            let transform = Transform2F::from_scale(Vector2F::splat(font.size.value))
+                * Transform2F::from_scale(Vector2F::new(1.0, -1.0))
+                * skew
+                * face.font_matrix();
+
+            let direction = level.map(|level| select(level % 2 == 1, Direction::RightToLeft, Direction::LeftToRight));
+            let shaped = shape(face, &text[range.clone()], font, direction);
+            if !font.variations.is_default() {
+                if let Some(varied) = variable_outlines(face, &axes, shaped.iter().map(|g| g.gid)) {
+                    outlines.extend(varied.into_iter().map(|(gid, outline)| ((face_id, gid), outline)));
                 }
-                glyphs.push((gid, transform * Transform2F::from_translation(offset)));
+            }
 
-                offset = offset + glyph.metrics.advance;
+            for glyph in shaped {
+                let cluster = range.start + glyph.cluster;
+                if clusters.last().map(|&(idx, _)| idx) != Some(cluster) {
+                    clusters.push((cluster, Vector2F::new(offset.x(), 0.0)));
+                }
+                let tr = Transform2F::from_translation(offset) * transform * Transform2F::from_translation(glyph.offset);
+                glyphs.push((face_id, glyph.gid, tr, clusters.len() - 1));
+
+                // the skew must not change the advance
+                let advance = (transform.matrix * Vector2F::new(glyph.advance, 0.0)).x();
+                offset = offset + Vector2F::new(advance + embolden, 0.0);
             }
         }
 
         Layout {
-            advance: transform * offset,
-            glyphs
+            advance: offset,
+            glyphs,
+            clusters,
+            text: text.into(),
+            levels: levels.to_vec(),
+            rtl,
+            outlines,
+            embolden,
+            origin: Vector2F::default()
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(marks: &[(&'static str, MarkRange, usize)]) -> Vec<(&'static str, String)> {
+        let mut ranges: Vec<_> = Cache::page_ranges(marks.to_vec()).into_iter()
+            .map(|(key, ranges)| (key, Cache::format_pages(ranges)))
+            .collect();
+        ranges.sort();
+        ranges
+    }
+
+    #[test]
+    fn index_range_merging() {
+        // overlapping and adjacent ranges join, points within a range disappear
+        assert_eq!(Cache::format_pages(vec![(5, 7), (3, 3), (6, 9), (10, 10), (12, 12), (8, 8)]), "3, 5–10, 12");
+        assert_eq!(Cache::format_pages(vec![(4, 4), (4, 4)]), "4");
+        assert_eq!(Cache::format_pages(vec![]), "");
+    }
+
+    #[test]
+    fn counters_reset_and_restore() {
+        let chapter = TypeId::new(SiteId(0), 1);
+        let figure = TypeId::new(SiteId(0), 2);
+        let mut counter = Counter::new(vec![figure], NumberStyle::Arabic);
+        counter.reset = vec![chapter];
+        let counters = vec![("figure".to_string(), counter)];
+        let seq = |n| SequenceId::new(SiteId(0), n);
+
+        let mut cache = Cache::new();
+        cache.counters = vec![0; counters.len()];
+        cache.count(&counters, seq(0), figure);
+        cache.count(&counters, seq(1), figure);
+        // within the chapter the figures start at 1, after it they go on
+        let saved = cache.reset_counters(&counters, chapter);
+        cache.count(&counters, seq(2), figure);
+        cache.restore_counters(saved);
+        cache.count(&counters, seq(3), figure);
+
+        let labels: Vec<&str> = (0 .. 4).map(|n| cache.labels[cache.sequence_labels[&seq(n)]].as_str()).collect();
+        assert_eq!(labels, ["1", "2", "1", "3"]);
+        assert!(cache.reset_counters(&counters, figure).is_empty());
+        assert_eq!(cache.count(&counters, seq(4), chapter), None);
+    }
+
+    #[test]
+    fn index_ranges() {
+        use MarkRange::*;
+        assert_eq!(ranges(&[("a", Begin, 3), ("b", Point, 4), ("a", End, 7), ("a", Point, 9)]),
+            vec![("a", "3–7, 9".to_string()), ("b", "4".to_string())]);
+    }
+
+    #[test]
+    fn index_unmatched_marks() {
+        use MarkRange::*;
+        // a begin without an end and an end without a begin mark their pages
+        assert_eq!(ranges(&[("a", Begin, 3), ("b", End, 5)]),
+            vec![("a", "3".to_string()), ("b", "5".to_string())]);
+        // the first of two begins
+        assert_eq!(ranges(&[("a", Begin, 2), ("a", Begin, 6), ("a", End, 8)]),
+            vec![("a", "2, 6–8".to_string())]);
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::error::Error;
+use hyphenation::{Standard, Load, extended::Extended};
+use crate::Dictionary;
+
+const SUFFIXES: [&str; 2] = [".standard.bincode", ".extended.bincode"];
+
+/// Finds hyphenation dictionaries in a directory at runtime.
+///
+/// The files are expected to be named after their language tag,
+/// like `en-gb.standard.bincode` or `hu.extended.bincode`.
+pub struct LanguageRegistry {
+    dir: PathBuf
+}
+impl LanguageRegistry {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LanguageRegistry { dir: dir.into() }
+    }
+
+    /// language tags of all dictionaries in the directory
+    pub fn languages(&self) -> io::Result<Vec<String>> {
+        let mut tags = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let name = match name.to_str() {
+                Some(name) => name,
+                None => continue
+            };
+            let tag = SUFFIXES.iter()
+                .find(|suffix| name.ends_with(*suffix))
+                .map(|suffix| &name[.. name.len() - suffix.len()]);
+            if let Some(tag) = tag {
+                if !tags.iter().any(|t| t == tag) {
+                    tags.push(tag.to_owned());
+                }
+            }
+        }
+        tags.sort();
+        Ok(tags)
+    }
+
+    /// load the dictionary for the given language tag.
+    /// an extended dictionary is preferred over a standard one.
+    pub fn load(&self, tag: &str) -> Result<Dictionary, Box<dyn Error>> {
+        let tag = tag.to_lowercase();
+
+        let extended = self.dir.join(format!("{}.extended.bincode", tag));
+        if extended.is_file() {
+            let mut reader = BufReader::new(File::open(extended)?);
+            return Ok(Dictionary::Extended(Extended::any_from_reader(&mut reader)?));
+        }
+
+        let standard = self.dir.join(format!("{}.standard.bincode", tag));
+        let mut reader = BufReader::new(File::open(standard)?);
+        Ok(Dictionary::Standard(Standard::any_from_reader(&mut reader)?))
+    }
+}
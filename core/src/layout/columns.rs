@@ -1,9 +1,10 @@
-use super::{Entry, StreamVec, FlexMeasure, ItemMeasure};
+use super::{Entry, StreamVec, FlexMeasure, ItemMeasure, HyphenPenalty};
 //use layout::style::{Style};
 use crate::units::{Length, Size};
 use std::fmt::{self, Debug};
-use crate::content::{Font, Tag};
+use crate::content::{Font, Tag, Direction, Placement};
 use crate::draw::RenderItem;
+use crate::text::{embedding_level, visual_order};
 
 #[derive(Copy, Clone, Debug, Default)]
 struct LineBreak {
@@ -11,15 +12,51 @@ struct LineBreak {
     path:   u64, // one bit for each branch taken (1) or not (0)
     factor: f32,
     score:  f32,
-    height: Length,
+    height: Length, // above the baseline
+    depth:  Length, // below the baseline
     width:  Length,
     indent: Length,
+    hyphen: Option<HyphenPenalty>, // set if the line ends with a hyphen
+    direction: Direction, // base direction of the line
+    base:   Direction, // base direction for the following line
+    shape:  Shape, // indent and width for the following line
+}
+
+/// indent and width of lines
+#[derive(Copy, Clone, Debug, Default)]
+struct Shape {
+    indent:  Length,
+    width:   Length,
+    lines:   u16, // number of lines left, 0 for all
+    restore: (Length, Length), // indent and width when no lines are left
+    wrap:    Option<(usize, Option<Length>)>, // Entry::Wrap and how far below the top of the float the line starts
+}
+impl Shape {
+    fn new(indent: Length, width: Length) -> Shape {
+        Shape {
+            indent,
+            width,
+            lines: 0,
+            restore: (indent, width),
+            wrap: None
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default)]
 struct ColumnBreak {
     prev:   usize, // index to previous column-break
     score:  f32,
+    defer:  usize, // floats anchored from here to the break go to the next column
+    alone:  bool,  // the floats carried into this column take a column of their own before it
+}
+
+#[derive(Copy, Clone, Debug)]
+struct FloatAnchor {
+    pos:       usize, // in the stream
+    index:     usize,
+    height:    Length,
+    placement: Placement,
 }
     
 #[derive(Copy, Clone, Debug, Default)]
@@ -38,11 +75,14 @@ pub struct ParagraphStyle {
 pub struct ParagraphLayout {
     items:      Vec<Entry>,
     nodes:      Vec<Option<LineBreak>>,
-    last:       usize
+    last:       usize,
+    grid:       Option<Length>, // baseline grid
 }
 pub struct ColumnLayout {
     para:       ParagraphLayout,
     nodes_col:  Vec<Option<ColumnBreak>>,
+    floats:     Vec<FloatAnchor>, // ordered by position
+    beside:     Vec<FloatAnchor>, // floats beside the text, ordered by position
     height:     Length
 }
 impl Debug for ColumnLayout {
@@ -60,38 +100,51 @@ struct Context {
     measure:    FlexMeasure,
     overflow:   FlexMeasure, // how much to overflow into the margin
     height:     Length,
-    indent:     Length,
-    width:      Length,
+    depth:      Length,
+    shape:      Shape,
+    line_shape: Option<(Length, Length)>, // indent and width at the first item of the line
     path:       u64,    // one bit for each branch on this line
     begin:      usize,  // begin of line or branch
     pos:        usize,  // calculation starts here
     score:      f32,    // score at pos
     branches:   u8,     // number of branches so far (<= 64)
+    hyphen:     Option<HyphenPenalty>, // the previous line ended with a hyphen
+    direction:  Direction,
+    line_direction: Option<Direction>, // direction at the first item of the line
 }
 impl Context {
-    fn new(start: usize, score: f32, indent: Length, width: Length) -> Context {
+    // the line after `b`
+    fn new(start: usize, b: &LineBreak) -> Context {
         Context {
             measure:    FlexMeasure::zero(),
             overflow:   FlexMeasure::zero(),
             height:     Length::zero(),
-            indent,
-            width,
+            depth:      Length::zero(),
+            shape:      b.shape,
+            line_shape: None,
             path:       0,
             begin:      start,
             pos:        start,
             branches:   0,
-            score:      score,
+            score:      b.score,
+            hyphen:     b.hyphen,
+            direction:  b.base,
+            line_direction: None,
         }
     }
     fn add_item(&mut self, item: ItemMeasure, is_first: bool) {
         if is_first {
             self.measure -= item.left;
         }
+        if self.line_direction.is_none() {
+            self.line_direction = Some(self.direction);
+        }
         self.measure += item.content;
         self.overflow = item.right;
 
-        // TODO: vertical alignment
+        // items are aligned at the baseline
         self.height = self.height.max(item.height);
+        self.depth = self.depth.max(item.depth);
     }
     fn add_space(&mut self, s: FlexMeasure) {
         self.measure += s;
@@ -100,6 +153,10 @@ impl Context {
     fn line(&self) -> FlexMeasure {
         self.measure - self.overflow
     }
+    // (indent, width) of the line
+    fn line_width(&self) -> (Length, Length) {
+        self.line_shape.unwrap_or((self.shape.indent, self.shape.width))
+    }
     fn fill(&mut self, width: Length) {
         self.measure.extend(width);
         self.overflow = FlexMeasure::zero();
@@ -112,26 +169,65 @@ impl ParagraphLayout {
         let mut nodes = vec![None; limit+1];
         nodes[0] = Some(LineBreak {
             width,
+            shape: Shape::new(Length::zero(), width),
             .. LineBreak::default()
         });
 
         let mut layout = ParagraphLayout {
             nodes,
             items: items.0,
-            last: 0
+            last: 0,
+            grid: None
         };
         layout.run();
         layout
     }
+    // the line starts with the current shape. count it.
+    fn begin_line(&self, c: &mut Context) {
+        if let Some((pos, Some(y))) = c.shape.wrap {
+            c.shape = match self.wrap_at(pos, y) {
+                Some((indent, width)) => Shape { indent, width, .. c.shape },
+                None => Shape::new(c.shape.restore.0, c.shape.restore.1)
+            };
+        }
+        let shape = c.shape;
+        c.line_shape = Some((shape.indent, shape.width));
+        c.shape = match shape.lines {
+            0 => shape,
+            1 => Shape::new(shape.restore.0, shape.restore.1),
+            n => Shape { lines: n - 1, .. shape }
+        };
+    }
+
+    // indent and width of a line that starts `y` below the top of the float of the
+    // Entry::Wrap at `pos`: the narrowest of the bands it reaches into. None below the float.
+    fn wrap_at(&self, pos: usize, y: Length) -> Option<(Length, Length)> {
+        match self.items[pos] {
+            Entry::Wrap(height, step, ref widths) if y < height && widths.len() > 0 => {
+                let first = ((y / step).max(0.0) as usize).min(widths.len() - 1);
+                let last = (((y + step) / step - 1e-3).ceil() as usize).min(widths.len()).max(first + 1);
+                let indent = widths[first .. last].iter().fold(Length::zero(), |a, w| a.max(w.0));
+                let end = widths[first .. last].iter().map(|w| w.0 + w.1).fold(widths[first].0 + widths[first].1, Length::min);
+                Some((indent, end - indent))
+            }
+            _ => None
+        }
+    }
+
+    // whether the line after a break with this shape is still beside a float
+    fn is_wrapped(&self, shape: &Shape) -> bool {
+        match shape.wrap {
+            Some((pos, Some(y))) => self.wrap_at(pos, y).is_some(),
+            _ => false
+        }
+    }
+
     fn run(&mut self) {
         let mut last = 0;
         for start in 0 .. self.items.len() {
             match self.nodes[start] {
                 Some(b) => {
-                    last = self.complete_line(
-                        start,
-                        Context::new(start, b.score, b.indent, b.width)
-                    );
+                    last = self.complete_line(start, Context::new(start, &b));
                 },
                 None => {}
             }
@@ -156,11 +252,16 @@ impl ParagraphLayout {
             let n = c.pos;
             let is_first = start == n;
             match self.items[n] {
-                Entry::Item(m, _, _) => c.add_item(m, is_first),
+                Entry::Item(m, _, _) => {
+                    if c.line_shape.is_none() {
+                        self.begin_line(&mut c);
+                    }
+                    c.add_item(m, is_first);
+                }
                 Entry::Space(s, Some(penalty), _) => {
                     // breaking case:
                     // width is not added yet!
-                    self.maybe_update(&c, n+1, penalty);
+                    self.maybe_update(&c, n+1, penalty, None);
                     last = n+1;
                     
                     // add width now.
@@ -170,27 +271,47 @@ impl ParagraphLayout {
                     c.add_space(s);
                 }
 
-                Entry::Linebreak(fill, _, _) => {
+                Entry::Linebreak(fill, _, _, hyphen) => {
                     if fill {
-                        c.fill(c.width);
+                        c.fill(c.line_width().1);
                     }
+                    let penalty = newline_penalty(fill, hyphen, c.hyphen);
                     
-                    self.maybe_update(&c, n+1, 0.0);
+                    self.maybe_update(&c, n+1, penalty, hyphen);
                     last = n+1;
                     break;
                 },
 
                 Entry::Column => {
-                    self.maybe_update(&c, n+1, 0.0);
+                    self.maybe_update(&c, n+1, 0.0, None);
                     last = n+1;
                     break;
                 }
 
-                Entry::SetWidth(indent, width) => {
-                    c.indent = indent;
-                    c.width = width;
+                Entry::SetWidth(indent, width, lines) => {
+                    // a temporary shape returns to the width from before
+                    let restore = match c.shape.lines {
+                        0 => (c.shape.indent, c.shape.width),
+                        _ => c.shape.restore
+                    };
+                    c.shape = Shape { indent, width, lines, restore, wrap: None };
+                }
+
+                Entry::Wrap(..) => {
+                    // the float starts below this line
+                    let restore = match c.shape.lines {
+                        0 => (c.shape.indent, c.shape.width),
+                        _ => c.shape.restore
+                    };
+                    c.shape = Shape { lines: 0, restore, wrap: Some((n, None)), .. c.shape };
+                }
+
+                Entry::SetDirection(direction) => {
+                    c.direction = direction;
                 }
 
+                Entry::Float(..) | Entry::Header(..) => {}
+
                 Entry::BranchEntry(len) => {
                     // b
                     let b_last = self.complete_line(
@@ -215,7 +336,7 @@ impl ParagraphLayout {
                 }
             }
             
-            if c.measure.shrink > c.width {
+            if c.measure.shrink > c.line_width().1 {
                 break; // too full
             }
             
@@ -225,12 +346,24 @@ impl ParagraphLayout {
         last
     }
 
-    fn maybe_update(&mut self, c: &Context, n: usize, penalty: f32) {
-        let (factor, score) = match c.line().factor(c.width) {
+    fn maybe_update(&mut self, c: &Context, n: usize, penalty: f32, hyphen: Option<HyphenPenalty>) {
+        let (indent, width) = c.line_width();
+        let (factor, score) = match c.line().factor(width) {
             Some(factor) => (factor, -factor * factor),
             None => (1.0, -1000.)
         };
 
+        // the next line starts below this one, by its actual height
+        let mut shape = c.shape;
+        if let Some((pos, y)) = shape.wrap {
+            let skip = match self.items[n-1] {
+                Entry::Linebreak(_, skip, _, _) => skip,
+                _ => Length::zero()
+            };
+            let y = y.map(|y| y + c.height + c.depth + skip).unwrap_or_default();
+            shape.wrap = Some((pos, Some(y)));
+        }
+
         let break_score = c.score + score + penalty;
         let break_point = LineBreak {
             factor: factor,
@@ -238,8 +371,13 @@ impl ParagraphLayout {
             prev:   c.begin,
             path:   c.path,
             height: c.height,
-            width:  c.width,
-            indent: c.indent,
+            depth:  c.depth,
+            width,
+            indent,
+            hyphen,
+            direction: c.line_direction.unwrap_or(c.direction),
+            base:   c.direction,
+            shape,
         };
         self.nodes[n] = Some(match self.nodes[n] {
             Some(line) if break_score <= line.score => line,
@@ -247,11 +385,18 @@ impl ParagraphLayout {
         });
     }
     pub fn lines(&self) -> Column {
-        Column::new(0, self.last, self)
+        Column::new(0, self.last, self, vec![], Length::zero())
+    }
+
+    /// the height of all lines
+    pub fn height(&self) -> Length {
+        let mut column = self.lines();
+        while column.next().is_some() {}
+        column.y
     }
 }
 impl ColumnLayout {
-    pub fn new(items: StreamVec, width: Length, height: Length) -> ColumnLayout {
+    pub fn new(items: StreamVec, width: Length, height: Length, grid: Option<Length>) -> ColumnLayout {
         let limit = items.0.len();
         debug!("{} items", limit);
         let mut nodes = vec![None; limit+1];
@@ -259,17 +404,27 @@ impl ColumnLayout {
 
         nodes[0] = Some(LineBreak {
             width,
+            shape: Shape::new(Length::zero(), width),
             .. LineBreak::default()
         });
         nodes_col[0] = Some(ColumnBreak::default());
 
+        // floats beside the text only take the height the lines next to them don't
+        let (beside, floats): (Vec<_>, Vec<_>) = items.0.iter().enumerate().filter_map(|(pos, entry)| match *entry {
+            Entry::Float(index, height, placement) => Some(FloatAnchor { pos, index, height, placement }),
+            _ => None
+        }).partition(|f| f.placement.is_beside());
+
         let mut layout = ColumnLayout {
             para: ParagraphLayout {
                 nodes,
                 items: items.0,
-                last: 0
+                last: 0,
+                grid
             },
             nodes_col,
+            floats,
+            beside,
             height,
         };
         layout.run();
@@ -283,10 +438,7 @@ impl ColumnLayout {
         for start in 0 .. self.para.items.len() {
             match self.para.nodes[start] {
                 Some(b) => {
-                    last = self.para.complete_line(
-                        start,
-                        Context::new(start, b.score, b.indent, b.width)
-                    );
+                    last = self.para.complete_line(start, Context::new(start, &b));
                     self.compute_column(start, false);
                 },
                 None => {}
@@ -294,18 +446,6 @@ impl ColumnLayout {
         }
         self.compute_column(last, true);
 
-        if self.nodes_col[last].is_none() {
-            for i in 0 .. last {
-                println!("{:3} {:?}", i, self.para.items[i]);
-                if let Some(b) = self.para.nodes[i] {
-                    println!("     {:?}", b);
-                }
-                if let Some(l) = self.nodes_col[i] {
-                    println!("     {:?}", l);
-                }
-            }
-        }
-
         self.para.last = last;
     }
 
@@ -320,12 +460,54 @@ impl ColumnLayout {
         -10.0 * ((self.height - fill) / self.height)
     }
 
+    // floats anchored in the entries start .. end
+    fn anchored(floats: &[FloatAnchor], start: usize, end: usize) -> &[FloatAnchor] {
+        let index = |pos: usize| match floats.binary_search_by_key(&pos, |f| f.pos) {
+            Ok(i) | Err(i) => i
+        };
+        let (a, b) = (index(start), index(end));
+        &floats[a .. b.max(a)]
+    }
+    fn floats_in(&self, start: usize, end: usize) -> &[FloatAnchor] {
+        ColumnLayout::anchored(&self.floats, start, end)
+    }
+    // the y below the lines (given from the end) when the first starts at `top`,
+    // or below the floats beside them if those reach further.
+    // baselines snap to the grid from the top of the column, as in Column::next.
+    fn lines_bottom(&self, top: Length, lines: &[(usize, usize, Length, Length)]) -> Length {
+        let mut y = top;
+        let mut bottom = top;
+        for &(start, end, height, below) in lines.iter().rev() {
+            y = snap(y + height, self.para.grid) + below;
+            for float in ColumnLayout::anchored(&self.beside, start, end) {
+                bottom = bottom.max(y + float.height);
+            }
+        }
+        y.max(bottom)
+    }
+    // height taken by the floats, the repeated table header and the lines
+    fn fill(&self, carried: &[FloatAnchor], own: &[FloatAnchor], header: Length, lines: &[(usize, usize, Length, Length)]) -> Length {
+        let (top, bottom) = carried.iter().chain(own.iter())
+            .fold((header, Length::zero()), |(top, bottom), f| match f.placement {
+                Placement::Bottom => (top, bottom + f.height),
+                _ => (top + f.height, bottom)
+            });
+        self.lines_bottom(top, lines) + bottom
+    }
+    // table header repeated at the top of a column that starts at `pos`
+    fn header_at(&self, pos: usize) -> Option<(usize, usize, Length)> {
+        match self.para.items.get(pos) {
+            Some(&Entry::Header(first, count, height)) if pos > 0 => Some((first, count, height)),
+            _ => None
+        }
+    }
+
     fn compute_column(&mut self, n: usize, is_last: bool) -> bool {
         //                                        measure:
         let mut num_lines_before_end = 0;      // - lines before the break; reset between paragraphs
         let mut num_lines_at_last_break = 0;   // - lines after the previous break; count until the last paragraph starts
         let mut is_last_paragraph = true;
-        let mut height = Length::zero();
+        let mut lines = vec![];                // (start, end, height, space below) of each line, from the end
         let mut last = n;
         let mut found = false;
         
@@ -334,11 +516,21 @@ impl ColumnLayout {
             let last_node = self.para.nodes[last].unwrap();
             
             let penalty = if last > 0 {
+                let mut below = last_node.depth;
                 let penalty = match self.para.items[last-1] {
-                    Entry::Linebreak(_, skip, col_break) => {
+                    Entry::Linebreak(_, _, col_break, Some(_)) => {
+                        // broken at a hyphen. still within the paragraph
+                        num_lines_before_end += 1;
+
+                        if is_last_paragraph {
+                            num_lines_at_last_break += 1;
+                        }
+                        col_break
+                    }
+                    Entry::Linebreak(_, skip, col_break, None) => {
                         is_last_paragraph = false;
                         num_lines_before_end = 0;
-                        height += skip;
+                        below += skip;
                         col_break
                     },
                     Entry::Space(_, _, col_break) => {
@@ -351,10 +543,18 @@ impl ColumnLayout {
                     }
                     ref e => panic!("found: {:?}", e)
                 };
+
+                // the lines beside a float stay in its column
+                let penalty = match self.para.is_wrapped(&last_node.shape) {
+                    true => None,
+                    false => penalty
+                };
                 
-                height += last_node.height;
+                lines.push((last_node.prev, last, last_node.height, below));
 
-                if height > self.height {
+                // without any break that fits, keep looking for one to overflow
+                let found = found || self.nodes_col[n].is_some();
+                if found && self.lines_bottom(Length::zero(), &lines) > self.height {
                     break;
                 }
                 
@@ -364,20 +564,55 @@ impl ColumnLayout {
             };
 
             if let (Some(penalty), Some(column)) = (penalty, self.nodes_col[last]) {
+                // floats that did not fit into the previous column come first.
+                // the last floats of this column move on until the rest fits.
+                // at the end they get columns of their own.
+                let carried = self.floats_in(column.defer, last);
+                let header = self.header_at(last).map(|h| h.2).unwrap_or_default();
+                let own = self.floats_in(last, n);
+                let fit = |carried: &[FloatAnchor]| {
+                    let mut kept = own.len();
+                    let mut fill = self.fill(carried, &own[.. kept], header, &lines);
+                    while kept > 0 && fill > self.height {
+                        kept -= 1;
+                        fill = self.fill(carried, &own[.. kept], header, &lines);
+                    }
+                    (kept, fill)
+                };
+                let (mut kept, mut fill) = fit(carried);
+
+                // carried floats that leave no room for the text go into a column before it,
+                // which they may overflow.
+                let alone = fill > self.height && carried.len() > 0;
                 let mut score = column.score + penalty
                     + self.num_lines_penalty(num_lines_at_last_break)
                     + self.num_lines_penalty(num_lines_before_end);
-                
-                if !is_last {
-                    score += self.fill_penalty(height);
+                if alone {
+                    let (k, f) = fit(&[]);
+                    kept = k;
+                    fill = f;
+                    score += self.fill_penalty(Length::zero());
                 }
-            
+                let defer = own.get(kept).map(|f| f.pos).unwrap_or(n);
+
+                // a column that does not fit is only taken if there is nothing else
+                let overflow = fill > self.height;
+                if overflow {
+                    score -= 1000.0;
+                } else if !is_last {
+                    score += self.fill_penalty(fill);
+                }
+
                 match self.nodes_col[n] {
+                    Some(_) if overflow => {},
+                    _ if overflow && found => {},
                     Some(column) if column.score > score => {},
                     _ => {
                         self.nodes_col[n] = Some(ColumnBreak {
                             prev: last,
-                            score: score
+                            score,
+                            defer,
+                            alone
                         });
                         
                         found = true;
@@ -398,15 +633,43 @@ impl ColumnLayout {
 #[derive(Debug)]
 pub struct Columns {
     layout:     ColumnLayout,
-    columns:    Vec<usize>
+    columns:    Vec<(usize, usize, usize, usize)> // first and last entry, and the floats anchored in between, from the end
 }
 impl Columns {
     fn new(layout: ColumnLayout) -> Self {
         let mut columns = Vec::new();
         let mut last = layout.para.last;
+        let end = layout.para.items.len();
+
+        // floats left after the text fill columns of their own
+        let defer = layout.nodes_col[last].map(|c| c.defer).unwrap_or(last);
+        let rest = layout.floats_in(defer, end);
+        let mut groups = vec![];
+        let mut start = 0;
+        let mut height = Length::zero();
+        for (i, float) in rest.iter().enumerate() {
+            if i > start && height + float.height > layout.height {
+                groups.push((rest[start].pos, float.pos));
+                start = i;
+                height = Length::zero();
+            }
+            height += float.height;
+        }
+        if start < rest.len() {
+            groups.push((rest[start].pos, end));
+        }
+        columns.extend(groups.into_iter().rev().map(|(from, to)| (last, last, from, to)));
+
         while last > 0 {
-            columns.push(last);
-            last = layout.nodes_col[last].unwrap().prev;
+            let column = layout.nodes_col[last].unwrap();
+            let start = layout.nodes_col[column.prev].unwrap();
+            if column.alone {
+                columns.push((column.prev, last, column.prev, column.defer));
+                columns.push((column.prev, column.prev, start.defer, column.prev));
+            } else {
+                columns.push((column.prev, last, start.defer, column.defer));
+            }
+            last = column.prev;
         }
         Columns {
             layout: layout,
@@ -416,19 +679,41 @@ impl Columns {
     pub fn get_column(&self, n: usize) -> Column {
         let len = self.columns.len();
         assert!(n < len);
-        let last = self.columns[len - 1 - n];
-        Column::new(
-            self.layout.nodes_col[last].unwrap().prev,
-            last,
-            &self.layout.para
-        )
+        self.column(self.columns[len - 1 - n])
     }
     pub fn columns(&self) -> impl Iterator<Item=Column> {
-        self.columns.iter().rev().map(move |&last| Column::new(
-            self.layout.nodes_col[last].unwrap().prev,
-            last,
-            &self.layout.para
-        ))
+        self.columns.iter().rev().map(move |&column| self.column(column))
+    }
+    fn column(&self, (first, last, from, to): (usize, usize, usize, usize)) -> Column {
+        let layout = &self.layout;
+
+        // the floats carried over from the previous column and those of this one,
+        // stacked from the top and from the bottom.
+        let mut floats = vec![];
+        let mut top = Length::zero();
+        let mut bottom = layout.height;
+        for float in layout.floats_in(from, to) {
+            match float.placement {
+                Placement::Top => {
+                    floats.push((top, float.index));
+                    top += float.height;
+                }
+                Placement::Bottom => {
+                    bottom -= float.height;
+                    floats.push((bottom, float.index));
+                }
+                Placement::Left | Placement::Right => {}
+            }
+        }
+        let mut column = Column::new(first, last, &layout.para, floats, top);
+        match layout.header_at(first) {
+            Some((row, count, height)) if first < last => {
+                column.header = Some((top, row, count));
+                column.y += height;
+            }
+            _ => {}
+        }
+        column
     }
     pub fn len(&self) -> usize {
         self.columns.len()
@@ -442,10 +727,12 @@ impl Columns {
 pub struct Column<'a> {
     lines:      Vec<usize>, // points to the end of each line
     layout:     &'a ParagraphLayout,
-    y:          Length
+    y:          Length,
+    floats:     Vec<(Length, usize)>, // top and index of each float
+    header:     Option<(Length, usize, usize)>, // top, first and number of the repeated table rows
 }
 impl<'a> Column<'a> {
-    fn new(first: usize, mut last: usize, layout: &'a ParagraphLayout) -> Self {
+    fn new(first: usize, mut last: usize, layout: &'a ParagraphLayout, floats: Vec<(Length, usize)>, top: Length) -> Self {
         let mut lines = Vec::new();
         while last > first {
             lines.push(last);
@@ -455,90 +742,309 @@ impl<'a> Column<'a> {
         Column {
             lines: lines,
             layout: layout,
-            y: Length::zero()
+            y: top,
+            floats,
+            header: None
         }
     }
+
+    /// (top, index) of the floats placed in this column.
+    /// Floats beside the text are only known after the lines before them.
+    pub fn floats(&self) -> &[(Length, usize)] {
+        &self.floats
+    }
+
+    /// (top, first row, number of rows) of the table header repeated at the top
+    pub fn header(&self) -> Option<(Length, usize, usize)> {
+        self.header
+    }
 }
 impl<'a> Iterator for Column<'a> {
-    type Item = (Length, Line<'a>);
+    type Item = (Length, Line);
     
     fn next(&mut self) -> Option<Self::Item> {
         self.lines.pop().map(|last| {
             let b = self.layout.nodes[last].unwrap();
-            self.y += b.height;
+            self.y = snap(self.y + b.height, self.layout.grid);
             let y = self.y;
+            self.y += b.depth;
 
-            if let Entry::Linebreak(_, skip, _) = self.layout.items[last-1] {
+            if let Entry::Linebreak(_, skip, _, _) = self.layout.items[last-1] {
                 self.y += skip;
             }
+
+            // floats beside the text start at the next line
+            for entry in &self.layout.items[b.prev .. last] {
+                if let Entry::Float(index, _, placement) = *entry {
+                    if placement.is_beside() {
+                        self.floats.push((self.y, index));
+                    }
+                }
+            }
             
-            (y, Line {
-                layout:   self.layout,
-                pos:      b.prev,
-                branches: 0,
-                measure:  FlexMeasure::zero(),
-                line:     b,
-                end:      last-1,
-            })
+            (y, Line::new(self.layout, b, last-1))
         })
     }
 }
 
-#[derive(Debug)]
-pub struct Line<'a> {
-    layout:     &'a ParagraphLayout,
-    pos:        usize,
-    end:        usize,
-    branches:   usize,
-    measure:    FlexMeasure,
-    line:       LineBreak,
-}
-impl<'a> Line<'a> {
-    pub fn height(&self) -> Length {
-        self.line.height
+// round up to the next multiple of the grid
+fn snap(length: Length, grid: Option<Length>) -> Length {
+    match grid {
+        Some(grid) if grid > Length::zero() => {
+            // allow for rounding errors
+            let n = (length / grid - 1e-3).ceil().max(0.0);
+            grid * n
+        }
+        _ => length
     }
 }
-impl<'a> Iterator for Line<'a> {
-    type Item = (Length, Size, RenderItem, Tag);
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.pos < self.end {
-            let pos = self.pos;
-            self.pos += 1;
-            let is_first = self.pos != self.line.prev;
 
-            match self.layout.items[pos] {
+// an item on a line
+struct Run {
+    x:      Length, // offset from the start of the line
+    size:   Size,
+    item:   RenderItem,
+    tag:    Tag,
+    level:  u8,     // embedding level
+}
+
+#[derive(Debug)]
+pub struct Line {
+    items:  Vec<(Length, Size, RenderItem, Tag)>, // in visual order
+    pos:    usize,
+    line:   LineBreak,
+}
+impl Line {
+    fn new(layout: &ParagraphLayout, line: LineBreak, end: usize) -> Line {
+        let mut runs = Vec::new();
+        let mut levels = Vec::new();
+        let mut measure = FlexMeasure::zero();
+        let mut branches = 0;
+        let mut is_first = true;
+        let mut pos = line.prev;
+
+        while pos < end {
+            let n = pos;
+            pos += 1;
+
+            match layout.items[n] {
                 Entry::Item(m, item, tag) => {
                     if is_first {
                         // the first item on the line gets moved to the left by m.left
-                        self.measure -= m.left;
+                        measure -= m.left;
+                        is_first = false;
                     }
 
                     // take current location
-                    let x = self.measure.at(self.line.factor) + self.line.indent;
+                    let x = measure.at(line.factor);
 
                     // add the width of the item
-                    self.measure += m.content;
+                    measure += m.content;
                             
-                    let size = Size::new(m.content.at(self.line.factor), m.height);
-                    return Some((x, size, item, tag));
+                    let size = Size::new(m.content.at(line.factor), m.height);
+                    runs.push(Run { x, size, item, tag, level: 0 });
+                    levels.push(m.level);
                 },
                 Entry::Space(s, _, _) => {
-                    self.measure += s;
+                    measure += s;
                 },
                 Entry::BranchEntry(len) => {
-                    if self.line.path & (1<<self.branches) == 0 {
+                    if line.path & (1<<branches) == 0 {
                         // not taken
-                        self.pos += len;
+                        pos += len;
                     }
-                    self.branches += 1;
+                    branches += 1;
                 },
-                Entry::BranchExit(skip) => self.pos += skip,
-                Entry::Linebreak(_, _, _) => unreachable!(),
-                Entry::SetWidth(_, _) => {}
+                Entry::BranchExit(skip) => pos += skip,
+                Entry::Linebreak(_, _, _, _) => unreachable!(),
+                Entry::SetWidth(_, _, _) => {}
+                Entry::SetDirection(_) => {}
+                Entry::Float(..) => {}
+                Entry::Wrap(..) => {}
+                Entry::Header(..) => {}
                 Entry::Column => unreachable!()
             }
         }
-        
-        None
+
+        let items = if line.direction == Direction::LeftToRight && levels.iter().all(|l| l.map_or(true, |l| l == 0)) {
+            runs.iter().map(|r| (r.x + line.indent, r.size, r.item, r.tag)).collect()
+        } else {
+            resolve_levels(&mut runs, &levels, line.direction);
+            reorder(&runs, &line)
+        };
+
+        Line {
+            items,
+            pos: 0,
+            line
+        }
+    }
+    /// the items in visual order
+    pub fn items(&self) -> &[(Length, Size, RenderItem, Tag)] {
+        &self.items
+    }
+    /// the same line, moved to the right by `dx`
+    pub fn shift(mut self, dx: Length) -> Line {
+        for item in self.items.iter_mut() {
+            item.0 += dx;
+        }
+        self
+    }
+    /// height above the baseline
+    pub fn height(&self) -> Length {
+        self.line.height
+    }
+    /// depth below the baseline
+    pub fn depth(&self) -> Length {
+        self.line.depth
+    }
+}
+impl Iterator for Line {
+    type Item = (Length, Size, RenderItem, Tag);
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.items.get(self.pos).cloned();
+        self.pos += 1;
+        item
+    }
+}
+
+/// items without text take the level of the items around them if both agree,
+/// and the level of the paragraph otherwise.
+fn resolve_levels(runs: &mut [Run], levels: &[Option<u8>], base: Direction) {
+    let base_level = embedding_level(base, 0);
+    for i in 0 .. runs.len() {
+        runs[i].level = levels[i].unwrap_or_else(|| {
+            let before = levels[.. i].iter().rev().filter_map(|&l| l).next().unwrap_or(base_level);
+            let after = levels[i+1 ..].iter().filter_map(|&l| l).next().unwrap_or(base_level);
+            if before == after { before } else { base_level }
+        });
+    }
+}
+
+/// place the items of a line in visual order (L2)
+fn reorder(runs: &[Run], line: &LineBreak) -> Vec<(Length, Size, RenderItem, Tag)> {
+    let base_level = embedding_level(line.direction, 0);
+
+    // (level, width, index of the item). gaps between items have no index.
+    // a gap takes the lower level of its neighbours if they have the same direction,
+    // and the level of the paragraph otherwise (N1, N2).
+    let mut elements: Vec<(u8, Length, Option<usize>)> = Vec::with_capacity(2 * runs.len());
+    for (i, run) in runs.iter().enumerate() {
+        if i > 0 {
+            let prev = &runs[i-1];
+            let gap = run.x - (prev.x + prev.size.width);
+            let level = match prev.level % 2 == run.level % 2 {
+                true => prev.level.min(run.level),
+                false => base_level
+            };
+            elements.push((level, gap, None));
+        }
+        elements.push((run.level, run.size.width, Some(i)));
+    }
+    let levels: Vec<u8> = elements.iter().map(|e| e.0).collect();
+    let order = visual_order(&levels);
+
+    let lead = runs.first().map(|r| r.x).unwrap_or(Length::zero());
+    let total = elements.iter().fold(Length::zero(), |sum, e| sum + e.1);
+    let mut x = match line.direction {
+        Direction::LeftToRight => line.indent + lead,
+
+        // mirrored: the line starts at the right
+        Direction::RightToLeft => line.indent + line.width - lead - total,
+    };
+
+    let mut items = Vec::with_capacity(runs.len());
+    for &n in order.iter() {
+        let (_, width, idx) = elements[n];
+        if let Some(idx) = idx {
+            let run = &runs[idx];
+            items.push((x, run.size, run.item, run.tag));
+        }
+        x += width;
+    }
+    items
+}
+
+// of a break at a newline, which ends the paragraph if it fills the line.
+// `hyphen` if the line ends in a hyphen, `prev` if the line before it does.
+fn newline_penalty(fill: bool, hyphen: Option<HyphenPenalty>, prev: Option<HyphenPenalty>) -> f32 {
+    let mut penalty = 0.0;
+    // the paragraph ends here. avoid a hyphen on the line before.
+    if let (true, Some(prev)) = (fill, prev) {
+        penalty += prev.last_line;
+    }
+    if let Some(h) = hyphen {
+        penalty += h.hyphen;
+        if prev.is_some() {
+            penalty += h.consecutive;
+        }
+    }
+    penalty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Writer, Glue, ItemMeasure, FlexMeasure};
+    use crate::{SequenceId, SiteId, Stamped};
+
+    fn words(writer: &mut Writer, count: usize, tag: Tag) {
+        let space = Glue::Space {
+            line_break: Some(0.0),
+            column_break: Some(0.0),
+            measure: FlexMeasure { shrink: Length::mm(2.0), length: Length::mm(3.0), stretch: Length::mm(10.0) }
+        };
+        let measure = ItemMeasure {
+            content: FlexMeasure::fixed(Length::mm(10.0)),
+            left: FlexMeasure::zero(),
+            right: FlexMeasure::zero(),
+            height: Length::mm(4.0),
+            depth: Length::mm(1.0),
+            level: None
+        };
+        for _ in 0 .. count {
+            writer.item(space, space, measure, RenderItem::Empty, tag);
+        }
+    }
+
+    #[test]
+    fn float_taller_than_column() {
+        let tag = Tag::Start(SequenceId::new(SiteId(0), 0));
+        let mut writer = Writer::new();
+        words(&mut writer, 4, tag);
+        writer.float(0, Length::mm(150.0), Placement::Top);
+        words(&mut writer, 56, tag);
+        writer.promote(Glue::hfill(Length::zero()));
+        let stream = writer.finish();
+
+        // two words on each of the 30 lines, 20 lines in a column
+        let columns = ColumnLayout::new(stream, Length::mm(25.0), Length::mm(100.0), None).columns();
+        let columns: Vec<(Vec<(Length, usize)>, usize)> = columns.columns()
+            .map(|mut column| {
+                let lines = column.by_ref().count();
+                (column.floats().to_vec(), lines)
+            })
+            .collect();
+
+        let lines: usize = columns.iter().map(|c| c.1).sum();
+        assert_eq!(lines, 30);
+
+        // the float overflows a column of its own
+        let with_float: Vec<_> = columns.iter().filter(|c| c.0.iter().any(|f| f.1 == 0)).collect();
+        assert_eq!(with_float.len(), 1);
+        assert_eq!(with_float[0].1, 0);
+    }
+
+    #[test]
+    fn hyphen_penalties() {
+        let h = HyphenPenalty { hyphen: -1.0, consecutive: -10.0, last_line: -100.0 };
+        assert_eq!(newline_penalty(false, None, None), 0.0);
+        assert_eq!(newline_penalty(false, None, Some(h)), 0.0);
+        assert_eq!(newline_penalty(false, Some(h), None), -1.0);
+        // the second of two hyphenated lines
+        assert_eq!(newline_penalty(false, Some(h), Some(h)), -11.0);
+        // a paragraph that ends after a hyphenated line
+        assert_eq!(newline_penalty(true, None, Some(h)), -100.0);
+        assert_eq!(newline_penalty(true, None, None), 0.0);
     }
 }
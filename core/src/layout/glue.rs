@@ -1,6 +1,6 @@
 use std::ops::{BitOr, BitOrAssign};
 use std::fmt;
-use super::{FlexMeasure, Length};
+use super::{FlexMeasure, Length, HyphenPenalty};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Glue {
@@ -13,7 +13,9 @@ pub enum Glue {
     Newline {
         column_break:   Option<f32>,
         fill:           bool,
-        height:         Length
+        height:         Length,
+        // set when the line is broken at a hyphen
+        hyphen:         Option<HyphenPenalty>
     },
     Column
 }
@@ -22,9 +24,18 @@ impl Glue {
         Glue::Newline {
             fill: true,
             column_break: Some(0.),
-            height
+            height,
+            hyphen: None
         }
-    } 
+    }
+    pub fn hyphen(penalty: HyphenPenalty) -> Glue {
+        Glue::Newline {
+            fill: false,
+            column_break: Some(penalty.last_line),
+            height: Length::zero(),
+            hyphen: Some(penalty)
+        }
+    }
 }
 
 fn merge_break(a: Option<f32>, b: Option<f32>) -> Option<f32> {
@@ -57,11 +68,11 @@ impl BitOr for Glue {
             (Column, _) | (_, Column) => Column,
 
             // Newline wins over Space
-            (Newline { fill, height, column_break }, Space { .. }) | (Space { .. }, Newline { fill, height, column_break })
-                => Newline { fill, height, column_break },
+            (Newline { fill, height, column_break, hyphen }, Space { .. }) | (Space { .. }, Newline { fill, height, column_break, hyphen })
+                => Newline { fill, height, column_break, hyphen },
             
-            (Newline { fill: a, height: h_a, column_break: cb_a }, Newline { fill: b, height: h_b, column_break: cb_b })
-                => Newline { fill: a | b, height: h_a.max(h_b), column_break: merge_break(cb_a, cb_b) }
+            (Newline { fill: a, height: h_a, column_break: cb_a, hyphen: hy_a }, Newline { fill: b, height: h_b, column_break: cb_b, hyphen: hy_b })
+                => Newline { fill: a | b, height: h_a.max(h_b), column_break: merge_break(cb_a, cb_b), hyphen: hy_a.or(hy_b) }
         }
     }
 }
@@ -1,6 +1,7 @@
 use std::fmt::Debug;
-use crate::{Tag, Length};
+use crate::{Tag, Length, Direction, Placement};
 use crate::draw::RenderItem;
+use serde::{Serialize, Deserialize};
 
 // private mods
 mod glue;
@@ -31,7 +32,39 @@ pub struct ItemMeasure {
     // how much to overflow into the right margin
     pub right:   FlexMeasure,
 
+    // above the baseline
     pub height:  Length,
+
+    // below the baseline
+    pub depth:   Length,
+
+    // embedding level from the bidi algorithm. None for items without text,
+    // they take the level of the items around them.
+    pub level: Option<u8>,
+}
+
+/// Penalties for breaking a line at a hyphen.
+/// They are added to the score, so negative values discourage the break.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HyphenPenalty {
+    // for every line that ends in a hyphen
+    pub hyphen:      f32,
+
+    // additionally, if the previous line ended in a hyphen as well
+    pub consecutive: f32,
+
+    // additionally, if the hyphen ends the last line of a paragraph or column
+    pub last_line:   f32,
+}
+impl Default for HyphenPenalty {
+    fn default() -> Self {
+        HyphenPenalty {
+            hyphen:      -0.5,
+            consecutive: -3.0,
+            last_line:   -5.0,
+        }
+    }
 }
 
 enum Break {
@@ -45,13 +78,30 @@ enum Break {
 enum Entry {
     Item(ItemMeasure, RenderItem, Tag),
     
-    /// Continue on the next line (fill), and penalty for a column break
-    Linebreak(bool, Length, Option<f32>),
+    /// Continue on the next line (fill), and penalty for a column break.
+    /// The last field is set if the line is broken at a hyphen.
+    Linebreak(bool, Length, Option<f32>, Option<HyphenPenalty>),
     
     Column,
 
-    /// Indent, Width
-    SetWidth(Length, Length),
+    /// Indent, Width and the number of lines it holds for (0 for all following lines).
+    /// After that the previous indent and width return.
+    SetWidth(Length, Length, u16),
+
+    /// base direction of the following lines
+    SetDirection(Direction),
+
+    /// Anchor of a float: index, height and placement
+    Float(usize, Length, Placement),
+
+    /// Height of a float beside the text, height of a band and the indent and width of
+    /// the lines in each band below the top of the float, then the previous ones return.
+    /// Used to wrap text around the float.
+    Wrap(Length, Length, Vec<(Length, Length)>),
+
+    /// Header rows of a table (first row, number of rows, height).
+    /// They are repeated if a column starts here.
+    Header(usize, usize, Length),
 
     /// (measure, line break, column break)
     Space(FlexMeasure, Option<f32>, Option<f32>),
@@ -98,4 +148,30 @@ impl StreamVec {
     fn len(&self) -> usize {
         self.0.len()
     }
+
+    /// (widest item, longest line) when only the forced breaks are taken.
+    /// Branches take the first alternative.
+    pub fn natural_widths(&self) -> (Length, Length) {
+        let mut widest = Length::zero();
+        let mut longest = Length::zero();
+        let mut line = Length::zero();
+        let mut pos = 0;
+        while pos < self.0.len() {
+            match self.0[pos] {
+                Entry::Item(m, _, _) => {
+                    widest = widest.max(m.content.length);
+                    line += m.content.length;
+                }
+                Entry::Space(s, _, _) => line += s.length,
+                Entry::Linebreak(..) | Entry::Column => {
+                    longest = longest.max(line);
+                    line = Length::zero();
+                }
+                Entry::BranchExit(skip) => pos += skip,
+                _ => {}
+            }
+            pos += 1;
+        }
+        (widest, longest.max(line))
+    }
 }
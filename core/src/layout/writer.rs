@@ -136,8 +136,8 @@ impl Writer {
     fn write_glue(&mut self, left: Glue) {
         match self.state | left {
             Glue::Column => self.stream.push(Entry::Column),
-            Glue::Newline { fill, height, column_break } => {
-                self.stream.push(Entry::Linebreak(fill, height, column_break));
+            Glue::Newline { fill, height, column_break, hyphen } => {
+                self.stream.push(Entry::Linebreak(fill, height, column_break, hyphen));
             },
             Glue::Space { measure, line_break, column_break }
              => self.stream.push(Entry::Space(measure, line_break, column_break)),
@@ -158,7 +158,28 @@ impl Writer {
         self.push(left, right, Entry::Space(measure, line_break, column_break));
     }
     pub fn set_width(&mut self, indent: Length, width: Length) {
-        self.stream.push(Entry::SetWidth(indent, width));
+        self.stream.push(Entry::SetWidth(indent, width, 0));
+    }
+    /// only for the next `lines` lines, starting with the line after the current item
+    pub fn set_width_for(&mut self, indent: Length, width: Length, lines: u16) {
+        self.stream.push(Entry::SetWidth(indent, width, lines));
+    }
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.stream.push(Entry::SetDirection(direction));
+    }
+    /// anchor the float `index` of the given height here
+    pub fn float(&mut self, index: usize, height: Length, placement: Placement) {
+        self.stream.push(Entry::Float(index, height, placement));
+    }
+    /// (indent, width) of the lines in each band of `step` below the float of the given height,
+    /// starting with the line after the current item
+    pub fn wrap(&mut self, height: Length, step: Length, widths: Vec<(Length, Length)>) {
+        self.stream.push(Entry::Wrap(height, step, widths));
+    }
+    /// starts a new line. the header rows `first .. first + count` are repeated
+    /// above it if a column begins here.
+    pub fn header(&mut self, first: usize, count: usize, height: Length) {
+        self.push(Glue::hfill(Length::zero()), Glue::None, Entry::Header(first, count, height));
     }
 
     #[inline(always)]
@@ -22,6 +22,8 @@ mod gen;
 mod text;
 pub mod object;
 mod document;
+pub mod language;
+pub mod bibliography;
 
 pub use content::*;
 pub use layout::FlexMeasure;
@@ -29,7 +31,73 @@ pub use units::*;
 pub use object::*;
 pub use document::*;
 pub use net::*;
+pub use language::LanguageRegistry;
+pub use bibliography::{BibEntry, Citation, CitationStyle};
 
+/// A color as the printer sees it. All components are in 0 ..= 1.
 #[derive(Serialize, Deserialize)]
-#[derive(Debug, Copy, Clone)]
-pub struct Color;
+#[derive(Debug, Clone, PartialEq)]
+pub enum Color {
+    Rgb(f32, f32, f32),
+
+    // process colors
+    Cmyk(f32, f32, f32, f32),
+
+    // an ink of its own. the alternate CMYK values stand in for it on devices without it.
+    Spot { name: String, tint: f32, alternate: [f32; 4] },
+}
+impl Color {
+    pub fn white() -> Color {
+        Color::Rgb(1.0, 1.0, 1.0)
+    }
+    pub fn black() -> Color {
+        Color::Rgb(0.0, 0.0, 0.0)
+    }
+
+    /// the color of crop and registration marks, which shows on every separation
+    pub fn registration() -> Color {
+        Color::Spot { name: "All".into(), tint: 1.0, alternate: [1.0; 4] }
+    }
+
+    pub fn from_rgb8(r: u8, g: u8, b: u8) -> Color {
+        Color::Rgb(r as f32 / 255., g as f32 / 255., b as f32 / 255.)
+    }
+
+    pub fn to_rgb(&self) -> (f32, f32, f32) {
+        match *self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Cmyk(c, m, y, k) => ((1. - c) * (1. - k), (1. - m) * (1. - k), (1. - y) * (1. - k)),
+            Color::Spot { .. } => {
+                let (c, m, y, k) = self.to_cmyk();
+                Color::Cmyk(c, m, y, k).to_rgb()
+            }
+        }
+    }
+
+    /// spot colors become their alternate at their tint. RGB colors get full black generation.
+    pub fn to_cmyk(&self) -> (f32, f32, f32, f32) {
+        match *self {
+            Color::Rgb(r, g, b) => {
+                let k = 1. - r.max(g).max(b);
+                if k >= 1.0 {
+                    return (0., 0., 0., 1.);
+                }
+                ((1. - r - k) / (1. - k), (1. - g - k) / (1. - k), (1. - b - k) / (1. - k), k)
+            }
+            Color::Cmyk(c, m, y, k) => (c, m, y, k),
+            Color::Spot { tint, alternate: [c, m, y, k], .. } => (c * tint, m * tint, y * tint, k * tint)
+        }
+    }
+
+    /// the lightness, 0 is black and 1 is white
+    pub fn to_gray(&self) -> f32 {
+        let (r, g, b) = self.to_rgb();
+        0.299 * r + 0.587 * g + 0.114 * b
+    }
+
+    pub fn to_rgb8(&self) -> (u8, u8, u8) {
+        let (r, g, b) = self.to_rgb();
+        let u = |v: f32| (v.max(0.).min(1.) * 255.).round() as u8;
+        (u(r), u(g), u(b))
+    }
+}
@@ -33,12 +33,22 @@ pub struct ObjectCtx<'a> {
 }
 
 impl Object {
-    pub fn size(&self, ctx: ObjectCtx) -> (FlexMeasure, Length) {
+    /// (width, height above the baseline, depth below the baseline)
+    pub fn size(&self, ctx: ObjectCtx) -> (FlexMeasure, Length, Length) {
         match *self {
             Object::Svg(ref svg) => svg.size(ctx),
             Object::TeX(ref tex) => tex.size(ctx),
         }
     }
+    /// horizontal extent (left, right) of the object within each band (top, bottom),
+    /// relative to its top left corner when drawn at `size`.
+    /// None for objects that fill their box.
+    pub fn contour(&self, size: Vector2F, bands: &[(f32, f32)]) -> Option<Vec<Option<(f32, f32)>>> {
+        match *self {
+            Object::Svg(ref svg) => Some(svg.contour(size, bands)),
+            Object::TeX(_) => None,
+        }
+    }
     pub fn draw(&self, ctx: ObjectCtx, origin: Vector2F, size: Vector2F, scene: &mut Scene) {
         match *self {
             Object::Svg(ref svg) => svg.draw(ctx, origin, size, scene),
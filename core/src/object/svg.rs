@@ -65,7 +65,7 @@ impl Serialize for SvgObject {
     }
 }
 impl SvgObject {
-    pub fn size(&self, ctx: ObjectCtx) -> (FlexMeasure, Length) {
+    pub fn size(&self, ctx: ObjectCtx) -> (FlexMeasure, Length, Length) {
         let type_design = ctx.design.get_type_or_default(ctx.typ);
         let svg_size = self.scene.view_box().size();
         let (w, h) = match self.scale {
@@ -80,7 +80,32 @@ impl SvgObject {
             (None, Some(h)) => (h * (svg_size.x() / svg_size.y()), h),
             _ => unreachable!()
         };
-        (FlexMeasure::fixed(w), h)
+        (FlexMeasure::fixed(w), h, Length::zero())
+    }
+
+    // union of the bounds of all contours that reach into the band
+    pub fn contour(&self, size: Vector2F, bands: &[(f32, f32)]) -> Vec<Option<(f32, f32)>> {
+        let view_box = self.scene.view_box();
+        let scale = size.x() / view_box.size().x();
+        let tr = Transform2F::from_scale(Vector2F::splat(scale))
+            * Transform2F::from_translation(-view_box.origin());
+
+        let mut extents = vec![None; bands.len()];
+        for (_, outline, _) in self.scene.paths() {
+            for contour in outline.contours() {
+                let bounds = tr * contour.bounds();
+                for (extent, &(top, bottom)) in extents.iter_mut().zip(bands) {
+                    if bounds.max_y() < top || bounds.min_y() > bottom {
+                        continue;
+                    }
+                    *extent = Some(match *extent {
+                        Some((left, right)) => (bounds.min_x().min(left), bounds.max_x().max(right)),
+                        None => (bounds.min_x(), bounds.max_x())
+                    });
+                }
+            }
+        }
+        extents
     }
 
     pub fn draw(&self, _: ObjectCtx, origin: Vector2F, size: Vector2F, scene: &mut Scene) {
@@ -94,7 +119,11 @@ impl SvgObject {
         
         for (paint, outline, _) in self.scene.paths() {
             let outline = outline.clone();
-            let new_path = DrawPath::new(outline.transform(tr), scene.push_paint(paint));
+
+            // gradients are in the coordinates of the svg as well
+            let mut paint = paint.clone();
+            paint.apply_transform(&tr);
+            let new_path = DrawPath::new(outline.transform(tr), scene.push_paint(&paint));
             scene.push_path(new_path);
         }
     }
@@ -67,30 +67,21 @@ impl TeX {
         let layout = renderer.layout(&self.tex, layout_settings).unwrap();
         f(layout, renderer, type_design)
     }
-    pub fn size(&self, ctx: ObjectCtx) -> (FlexMeasure, Length) {
-        self.build(ctx, |layout, renderer, type_design| {
-            let (x0, y0, x1, y1) = renderer.size(&layout);
-            // Left and right padding
+    pub fn size(&self, ctx: ObjectCtx) -> (FlexMeasure, Length, Length) {
+        self.build(ctx, |layout, _renderer, _type_design| {
             let width = layout.width;
-            // Top and bot padding
-            let height = match self.style {
-                TeXStyle::Display | TeXStyle::DisplayCramped => cvt(layout.height - layout.depth),
-                _ => type_design.line_height
-            };
 
-            (FlexMeasure::fixed(cvt(width)), height)
+            // the depth is negative for formulas that reach below the baseline
+            let height = cvt(layout.height);
+            let depth = Length::zero() - cvt(layout.depth);
+
+            (FlexMeasure::fixed(cvt(width)), height, depth)
         })
     }
-    pub fn draw(&self, ctx: ObjectCtx, origin: Vector2F, size: Vector2F, surface: &mut Scene) {
-        self.build(ctx, |layout, renderer, type_design| {
-            let layout_width = layout.width / Px;
-            let y_off = match self.style {
-                TeXStyle::Display | TeXStyle::DisplayCramped => layout.depth / Px,
-                _ => 0.0
-            };
-
-            dbg!(origin, size, layout.height, layout.depth);
-            let transform = Transform2F::from_translation(origin + Vector2F::new(0.0, y_off as f32));
+    pub fn draw(&self, ctx: ObjectCtx, origin: Vector2F, _size: Vector2F, surface: &mut Scene) {
+        self.build(ctx, |layout, renderer, _type_design| {
+            // origin is on the baseline
+            let transform = Transform2F::from_translation(origin);
             
             let mut backend = SceneWrapper::with_transform(surface, transform);
             renderer.render(&layout, &mut backend);
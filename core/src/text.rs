@@ -1,47 +1,263 @@
 use font::GlyphId;
-use crate::content::FontFace;
-
-pub fn grapheme_indices(face: &FontFace, text: &str) -> Vec<usize> {
-    if let Some(gsub) = face.get_gsub() {
-        let mut char_indices = text.char_indices();
-        let mut grapheme_indices = Vec::with_capacity(text.len());
-        let gids: Vec<GlyphId> = text.chars().map(|c| face.gid_for_unicode_codepoint(c as u32).unwrap_or(face.get_notdef_gid())).collect();
-        let mut pos = 0;
-    'a: while let Some(&first) = gids.get(pos) {
-            grapheme_indices.push(char_indices.next().unwrap().0);
-            pos += 1;
-            if let Some(subs) = gsub.substitutions(first) {
-                for (sub, _glyph) in subs {
-                    if let Some(len) = sub.matches(&gids[pos ..]) {
-                        pos += len;
-                        char_indices.by_ref().take(len).count();
-                        continue 'a;
-                    }
+use pathfinder_geometry::vector::Vector2F;
+use pathfinder_content::outline::{Outline, Contour};
+use crate::content::{FontFace, Font, Direction};
+use std::collections::HashMap;
+use std::mem::replace;
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo, Level};
+use unicode_segmentation::UnicodeSegmentation;
+use icu_collator::{Collator, CollatorOptions, AlternateHandling};
+use icu_locid::Locale;
+use std::ops::Range;
+
+/// a positioned glyph. all values are in font units.
+#[derive(Debug, Copy, Clone)]
+pub struct ShapedGlyph {
+    pub gid: GlyphId,
+
+    // byte index of the first char of the cluster this glyph belongs to
+    pub cluster: usize,
+
+    pub advance: f32,
+    pub offset: Vector2F,
+}
+
+/// shape `text` with the OpenType tables of the font.
+/// The direction is guessed from the script unless it is given.
+/// Fonts the shaper can't read (Type1, …) get the simple cmap + ligature + kerning treatment.
+pub fn shape(face: &FontFace, text: &str, font: Font, direction: Option<Direction>) -> Vec<ShapedGlyph> {
+    match shape_opentype(face, text, font, direction) {
+        Some(glyphs) => glyphs,
+        None => shape_simple(face, text, font)
+    }
+}
+
+fn tag(s: &str) -> [u8; 4] {
+    let b = s.as_bytes();
+    [b[0], b[1], b[2], b[3]]
+}
+
+/// split `text` into runs of grapheme clusters that use the same face (index into `faces`).
+/// a cluster gets the first face that has all of its chars, or the first face if none does.
+pub fn font_runs(faces: &[&FontFace], text: &str) -> Vec<(usize, Range<usize>)> {
+    let mut runs: Vec<(usize, Range<usize>)> = vec![];
+    for (start, cluster) in text.grapheme_indices(true) {
+        let face = faces.iter().position(|face| covers(face, cluster)).unwrap_or(0);
+        let end = start + cluster.len();
+        match runs.last_mut() {
+            Some((f, range)) if *f == face => range.end = end,
+            _ => runs.push((face, start .. end))
+        }
+    }
+    runs
+}
+
+fn covers(face: &FontFace, cluster: &str) -> bool {
+    let notdef = face.get_notdef_gid();
+    cluster.chars()
+        .filter(|&c| !is_default_ignorable(c))
+        .all(|c| face.gid_for_unicode_codepoint(c as u32).map_or(false, |gid| gid.0 != notdef.0))
+}
+
+// zero width (non-)joiners, marks and variation selectors don't need a glyph
+fn is_default_ignorable(c: char) -> bool {
+    match c {
+        '\u{200B}' ..= '\u{200F}' | '\u{2060}' ..= '\u{2064}' | '\u{FE00}' ..= '\u{FE0F}' => true,
+        _ => false
+    }
+}
+
+fn shape_opentype(face: &FontFace, text: &str, font: Font, direction: Option<Direction>) -> Option<Vec<ShapedGlyph>> {
+    // instances of a variable font are shaped at their axis values
+    let mut rb_face = face.shaper()?;
+    if !font.variations.is_default() {
+        let variations: Vec<rustybuzz::Variation> = font.variations.axes(font.size).into_iter()
+            .map(|(axis, value)| rustybuzz::Variation { tag: rustybuzz::Tag::from_bytes(&tag(axis)), value })
+            .collect();
+        rb_face.set_variations(&variations);
+    }
+
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.guess_segment_properties();
+    match direction {
+        Some(Direction::LeftToRight) => buffer.set_direction(rustybuzz::Direction::LeftToRight),
+        Some(Direction::RightToLeft) => buffer.set_direction(rustybuzz::Direction::RightToLeft),
+        None => {}
+    }
+
+    let features: Vec<rustybuzz::Feature> = font.features.iter()
+        .map(|(feature, on)| rustybuzz::Feature::new(rustybuzz::Tag::from_bytes(&tag(feature)), on as u32, ..))
+        .collect();
+
+    let output = rustybuzz::shape(&rb_face, &features, buffer);
+    let glyphs = output.glyph_infos().iter().zip(output.glyph_positions())
+        .map(|(info, pos)| ShapedGlyph {
+            gid: GlyphId(info.glyph_id),
+            cluster: info.cluster as usize,
+            advance: pos.x_advance as f32,
+            offset: Vector2F::new(pos.x_offset as f32, pos.y_offset as f32)
+        })
+        .collect();
+    Some(glyphs)
+}
+
+// only knows about ligatures and kerning
+fn shape_simple(face: &FontFace, text: &str, font: Font) -> Vec<ShapedGlyph> {
+    let features = font.features;
+    let mut glyphs: Vec<ShapedGlyph> = Vec::with_capacity(text.len());
+    let chars: Vec<(usize, GlyphId)> = text.char_indices()
+        .map(|(idx, c)| (idx, face.gid_for_unicode_codepoint(c as u32).unwrap_or(face.get_notdef_gid())))
+        .collect();
+    let gids: Vec<GlyphId> = chars.iter().map(|&(_, gid)| gid).collect();
+    let gsub = face.get_gsub().filter(|_| !features.is_disabled("liga"));
+    let kerning = !features.is_disabled("kern");
+
+    let mut pos = 0;
+    while let Some(&(cluster, first)) = chars.get(pos) {
+        pos += 1;
+        let mut gid = first;
+        if let Some(subs) = gsub.and_then(|gsub| gsub.substitutions(first)) {
+            for (sub, glyph) in subs {
+                if let Some(len) = sub.matches(&gids[pos ..]) {
+                    pos += len;
+                    gid = glyph;
+                    break;
                 }
             }
         }
-        grapheme_indices
+
+        let glyph = match face.glyph(gid) {
+            Some(glyph) => glyph,
+            None => continue
+        };
+        if let Some(last) = glyphs.last_mut().filter(|_| kerning) {
+            last.advance += face.kerning(last.gid, gid);
+        }
+        glyphs.push(ShapedGlyph {
+            gid,
+            cluster,
+            advance: glyph.metrics.advance.x(),
+            offset: Vector2F::default()
+        });
+    }
+    glyphs
+}
+
+/// direction of the first strongly directional character.
+/// Text with only numbers counts as left-to-right, everything else is neutral.
+pub fn strong_direction(text: &str) -> Option<Direction> {
+    let mut numbers = false;
+    for c in text.chars() {
+        match bidi_class(c) {
+            BidiClass::L => return Some(Direction::LeftToRight),
+            BidiClass::R | BidiClass::AL => return Some(Direction::RightToLeft),
+            BidiClass::EN | BidiClass::AN => numbers = true,
+            _ => {}
+        }
+    }
+    if numbers {
+        Some(Direction::LeftToRight)
     } else {
-        text.char_indices().map(|(idx, _)| idx).collect()
-    }
-}
-
-pub fn build_gids(face: &FontFace, text: &str) -> Vec<GlyphId> {
-    use std::iter::once;
-    let mut gids: Vec<GlyphId> = text.chars().map(|c| face.gid_for_unicode_codepoint(c as u32).unwrap_or(face.get_notdef_gid())).collect();
-    if let Some(gsub) = face.get_gsub() {
-        let mut pos = 0;
-    'a: while let Some(&first) = gids.get(pos) {
-            pos += 1;
-            if let Some(subs) = gsub.substitutions(first) {
-                for (sub, glyph) in subs {
-                    if let Some(len) = sub.matches(&gids[pos ..]) {
-                        gids.splice(pos-1 .. pos+len, once(glyph));
-                        continue 'a;
-                    }
-                }
-            }
+        None
+    }
+}
+
+/// the lowest embedding level of the given direction that is not below the base level
+pub fn embedding_level(direction: Direction, base: u8) -> u8 {
+    match (direction, base % 2) {
+        (Direction::LeftToRight, 0) | (Direction::RightToLeft, 1) => base,
+        _ => base + 1
+    }
+}
+
+/// embedding level of each byte of `text` by the Unicode Bidirectional Algorithm,
+/// for paragraphs in the given base direction.
+pub fn bidi_levels(text: &str, base: Direction) -> Vec<u8> {
+    let level = match base {
+        Direction::LeftToRight => Level::ltr(),
+        Direction::RightToLeft => Level::rtl()
+    };
+    BidiInfo::new(text, Some(level)).levels.iter().map(|level| level.number()).collect()
+}
+
+/// the visual order of items with the given embedding levels (L2).
+/// the n-th entry is the index of the item shown at the n-th place from the left.
+pub fn visual_order(levels: &[u8]) -> Vec<usize> {
+    let levels: Vec<Level> = levels.iter().map(|&level| Level::from(level)).collect();
+    BidiInfo::reorder_visual(&levels)
+}
+
+/// outlines of the glyphs (by id) at the given variation axis values, in font units.
+/// None if the face can't be read as OpenType.
+pub fn variable_outlines(face: &FontFace, axes: &[(&str, f32)], gids: impl Iterator<Item=GlyphId>) -> Option<HashMap<u32, Outline>> {
+    let mut tt_face = ttf_parser::Face::from_slice(face.data(), 0).ok()?;
+    for &(axis, value) in axes {
+        tt_face.set_variation(ttf_parser::Tag::from_bytes(&tag(axis)), value);
+    }
+
+    let mut outlines = HashMap::new();
+    for gid in gids {
+        if outlines.contains_key(&gid.0) {
+            continue;
         }
+        let mut sink = OutlineSink::new();
+        tt_face.outline_glyph(ttf_parser::GlyphId(gid.0 as u16), &mut sink);
+        outlines.insert(gid.0, sink.finish());
     }
-    gids
+    Some(outlines)
+}
+
+struct OutlineSink {
+    outline: Outline,
+    contour: Contour,
+}
+impl OutlineSink {
+    fn new() -> Self {
+        OutlineSink {
+            outline: Outline::new(),
+            contour: Contour::new()
+        }
+    }
+    fn flush(&mut self) {
+        if !self.contour.is_empty() {
+            let contour = replace(&mut self.contour, Contour::new());
+            self.outline.push_contour(contour);
+        }
+    }
+    fn finish(mut self) -> Outline {
+        self.flush();
+        self.outline
+    }
+}
+impl ttf_parser::OutlineBuilder for OutlineSink {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.flush();
+        self.contour.push_endpoint(Vector2F::new(x, y));
+    }
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.contour.push_endpoint(Vector2F::new(x, y));
+    }
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.contour.push_quadratic(Vector2F::new(x1, y1), Vector2F::new(x, y));
+    }
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.contour.push_cubic(Vector2F::new(x1, y1), Vector2F::new(x2, y2), Vector2F::new(x, y));
+    }
+    fn close(&mut self) {
+        self.contour.close();
+        self.flush();
+    }
+}
+
+/// compares the terms of an index by the rules of the language ("sv" sorts å, ä and ö after z).
+/// letters are compared without accents and case first, punctuation is ignored.
+/// languages without rules of their own use the root collation.
+pub fn collator(language: Option<&str>) -> Collator {
+    let mut options = CollatorOptions::new();
+    options.alternate_handling = Some(AlternateHandling::Shifted);
+    let locale: Locale = language.and_then(|tag| tag.parse().ok()).unwrap_or_default();
+    Collator::try_new(&locale.into(), options)
+        .or_else(|_| Collator::try_new(&Default::default(), options))
+        .expect("no root collation")
 }
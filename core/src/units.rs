@@ -64,6 +64,7 @@ impl Bounds {
 }
 
 #[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug)]
 pub struct Size {
     pub width: Length,
     pub height: Length